@@ -12,6 +12,8 @@ use std::time::Duration;
 pub fn bench_raw_node(c: &mut Criterion) {
     bench_raw_node_new(c);
     bench_raw_node_leader_propose(c);
+    bench_raw_node_leader_propose_batch_append(c);
+    bench_raw_node_leader_propose_reserve_entries(c);
     bench_raw_node_new_ready(c);
 }
 
@@ -78,6 +80,76 @@ pub fn bench_raw_node_leader_propose(c: &mut Criterion) {
     }
 }
 
+// Proposes 100 entries of 128 bytes on a leader replicating to 2 followers and builds the
+// ready carrying the appends, with and without coalescing them.
+pub fn bench_raw_node_leader_propose_batch_append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("RawNode::leader_propose_batch_append");
+    for batch_append in &[false, true] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(batch_append),
+            batch_append,
+            |b: &mut Bencher, batch_append| {
+                let logger = raft::default_logger();
+                b.iter_batched(
+                    || {
+                        let conf_state = ConfState::from((vec![1, 2, 3], vec![]));
+                        let storage = MemStorage::new_with_conf_state(conf_state);
+                        let mut config = Config::new(1);
+                        config.batch_append = *batch_append;
+                        let mut node = RawNode::new(&config, storage, &logger).unwrap();
+                        node.raft.become_candidate();
+                        node.raft.become_leader();
+                        for id in 2..=3 {
+                            node.raft.mut_prs().get_mut(id).unwrap().become_replicate();
+                        }
+                        node
+                    },
+                    |mut node| {
+                        for _ in 0..100 {
+                            node.propose(vec![], vec![0; 128]).expect("");
+                        }
+                        node.ready()
+                    },
+                    BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+}
+
+// Proposes 1000 entries of 128 bytes on a single node leader, with and without reserving
+// the unstable log for them beforehand.
+pub fn bench_raw_node_leader_propose_reserve_entries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("RawNode::leader_propose_reserve_entries");
+    for reserve in &[false, true] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(reserve),
+            reserve,
+            |b: &mut Bencher, reserve| {
+                let logger = raft::default_logger();
+                b.iter_batched(
+                    || {
+                        let mut node = quick_raw_node(&logger);
+                        node.raft.become_candidate();
+                        node.raft.become_leader();
+                        node
+                    },
+                    |mut node| {
+                        if *reserve {
+                            node.reserve_entries(1000);
+                        }
+                        for _ in 0..1000 {
+                            node.propose(vec![], vec![0; 128]).expect("");
+                        }
+                        node
+                    },
+                    BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+}
+
 pub fn bench_raw_node_new_ready(c: &mut Criterion) {
     let logger = raft::default_logger();
     let mut group = c.benchmark_group("RawNode::ready");
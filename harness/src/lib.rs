@@ -25,4 +25,7 @@ This module contains various testing harness utilities for Raft.
 mod interface;
 mod network;
 
-pub use self::{interface::Interface, network::Network};
+pub use self::{
+    interface::Interface,
+    network::{ElectionOutcome, Network, StorageOp},
+};
@@ -14,13 +14,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
 use raft::{
-    eraftpb::{ConfState, Message, MessageType},
+    eraftpb::{ConfState, Entry, HardState, Message, MessageType},
     storage::MemStorage,
-    Config, Raft, Result, NO_LIMIT,
+    Config, Raft, Result, StateRole, Storage, NO_LIMIT,
 };
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use slog::Logger;
 
 use super::interface::Interface;
@@ -34,6 +39,48 @@ struct Connection {
     to: u64,
 }
 
+/// A slow disk of a peer.
+///
+/// Used by `Network` for holding back the messages of the peer until they are persisted.
+#[derive(Default)]
+struct SlowDisk {
+    /// The persistence delay in ticks.
+    delay: u64,
+    /// The extra delay in ticks of the appends carrying entries read from the storage.
+    entries_delay: u64,
+    /// The extra delay in ticks of the snapshots read from the storage.
+    snapshot_delay: u64,
+    /// The held back messages and the tick they are due.
+    pending: VecDeque<(u64, Vec<Message>)>,
+}
+
+/// A storage operation of a peer, which can be slowed down by
+/// `Network::set_storage_latency`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageOp {
+    /// Persisting unstable entries and snapshots. Every message of the peer is held back
+    /// until they are persisted.
+    Append,
+    /// Reading entries to replicate. Appends carrying entries are held back.
+    Entries,
+    /// Reading snapshots to replicate. Snapshot messages are held back.
+    Snapshot,
+}
+
+/// The outcome of a single election round driven by `Network::run_election_round`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElectionOutcome {
+    /// The candidate became leader.
+    Won,
+    /// The candidate went back to follower, as a quorum rejected it or it learned of a
+    /// higher term.
+    Lost,
+    /// Neither a quorum granted nor rejected the candidate, e.g. as some voters went for
+    /// another candidate or are unreachable. It stays a candidate until its election times
+    /// out.
+    Split,
+}
+
 /// A simulated network for testing.
 ///
 /// You can use this to create a test network of Raft nodes.
@@ -47,11 +94,35 @@ pub struct Network {
     pub storage: HashMap<u64, MemStorage>,
     /// Drop messages from `from` to `to` at a rate of `f64`.
     dropm: HashMap<Connection, f64>,
+    /// Duplicate messages from `from` to `to` at a rate of `f64`.
+    dupm: HashMap<Connection, f64>,
     /// Drop messages of type `MessageType`.
     ignorem: HashMap<MessageType, bool>,
+    /// The rate and the accumulated fraction of ticks of the skewed clocks.
+    clocks: HashMap<u64, (f64, f64)>,
+    /// The slow disks of the peers.
+    slow_disks: HashMap<u64, SlowDisk>,
+    /// The hard states of the peers when their unstable data was last persisted, which are
+    /// what a crash keeps.
+    persisted_hard_states: HashMap<u64, HardState>,
+    /// The number of ticks of the network.
+    now: u64,
+    /// The elections observed, as the ID of the candidate and the term it campaigned in.
+    elections: HashSet<(u64, u64)>,
+    /// The number of elections held before the network was created.
+    initial_elections: u64,
+    /// The seed of the random generators of the network.
+    seed: u64,
+    /// The generator shuffling the delivery order of the messages if the network only
+    /// reorders them, see `set_reorder_only`.
+    reorder_rng: Option<StdRng>,
 }
 
 impl Network {
+    /// The simulated duration of a network tick in microseconds, which storage latencies
+    /// are converted with.
+    pub const TICK_MICROS: u64 = 1_000;
+
     /// Get a base config. Calling `Network::new` will initialize peers with this config.
     pub fn default_config() -> Config {
         Config {
@@ -107,11 +178,18 @@ impl Network {
                 }
             }
         }
-        Network {
+        let mut network = Network {
             peers: npeers,
             storage: nstorage,
             ..Default::default()
+        };
+        // Elections held before the network is created are not counted.
+        let ids: Vec<u64> = network.peers.keys().cloned().collect();
+        for id in ids {
+            network.record_election(id);
         }
+        network.initial_elections = network.elections.len() as u64;
+        network
     }
 
     /// Ignore a given `MessageType`.
@@ -119,10 +197,14 @@ impl Network {
         self.ignorem.insert(t, true);
     }
 
-    /// Filter out messages that should be dropped according to rules set by `ignore` or `drop`.
+    /// Filter out messages that should be dropped according to rules set by `ignore` or `drop`,
+    /// and duplicate the messages according to rules set by `duplicate`.
     pub fn filter(&self, msgs: impl IntoIterator<Item = Message>) -> Vec<Message> {
         msgs.into_iter()
             .filter(|m| {
+                if self.reorder_rng.is_some() {
+                    return true;
+                }
                 if self
                     .ignorem
                     .get(&m.get_msg_type())
@@ -143,6 +225,21 @@ impl Network {
                     .unwrap_or(0f64);
                 rand::random::<f64>() >= perc
             })
+            .flat_map(|m| {
+                let perc = self
+                    .dupm
+                    .get(&Connection {
+                        from: m.from,
+                        to: m.to,
+                    })
+                    .cloned()
+                    .unwrap_or(0f64);
+                if rand::random::<f64>() < perc {
+                    vec![m.clone(), m]
+                } else {
+                    vec![m]
+                }
+            })
             .collect()
     }
 
@@ -162,21 +259,182 @@ impl Network {
     pub fn send(&mut self, msgs: Vec<Message>) {
         let mut msgs = msgs;
         while !msgs.is_empty() {
+            if let Some(rng) = self.reorder_rng.as_mut() {
+                msgs.shuffle(rng);
+            }
             let mut new_msgs = vec![];
             for m in msgs.drain(..) {
+                let to = m.to;
+                let _ = self.peers.get_mut(&to).unwrap().step(m);
+                self.record_election(to);
+                let resp = self.persist_and_read_messages(to);
+                new_msgs.append(&mut self.filter(resp));
+            }
+            msgs.append(&mut new_msgs);
+        }
+    }
+
+    /// Like `send`, but delivers messages in groups of at most `batch_size`.
+    ///
+    /// Every message of a group is stepped before the receivers persist and emit their
+    /// responses, which models transports that hand messages over in batches. Responses
+    /// are queued behind the messages that are still pending.
+    ///
+    /// NOTE: the given `msgs` won't be filtered by its filters.
+    pub fn send_batched(&mut self, msgs: Vec<Message>, batch_size: usize) {
+        assert!(batch_size > 0, "batch_size must be greater than 0");
+        let mut msgs = msgs;
+        while !msgs.is_empty() {
+            let rest = msgs.split_off(cmp::min(batch_size, msgs.len()));
+            let mut receivers = vec![];
+            for m in msgs.drain(..) {
+                let to = m.to;
+                let p = self.peers.get_mut(&to).unwrap();
+                let _ = p.step(m);
+                if !receivers.contains(&to) {
+                    receivers.push(to);
+                }
+            }
+            msgs = rest;
+            for id in receivers {
+                self.record_election(id);
                 let resp = {
-                    let p = self.peers.get_mut(&m.to).unwrap();
-                    let _ = p.step(m);
+                    let p = self.peers.get_mut(&id).unwrap();
                     // The unstable data should be persisted before sending msg.
-                    p.persist();
+                    persist(p, &mut self.persisted_hard_states);
                     p.read_messages()
                 };
-                new_msgs.append(&mut self.filter(resp));
+                msgs.append(&mut self.filter(resp));
             }
-            msgs.append(&mut new_msgs);
         }
     }
 
+    /// Proposes `n_proposals` entries on `leader` at once, drives the network until all of
+    /// them are committed on `leader` and returns the wall-clock time it took.
+    ///
+    /// The network is ticked while the entries are not committed, e.g. as slow disks hold
+    /// back messages, so the result covers the simulated delays as well. It's meant for
+    /// catching performance regressions on a fixed scenario.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leader` is not the leader, a proposal is dropped, or the entries are not
+    /// committed within a bounded number of ticks.
+    pub fn measure_commit_latency(&mut self, leader: u64, n_proposals: usize) -> Duration {
+        const MAX_TICKS: usize = 100;
+
+        let last_index = {
+            let p = &self.peers[&leader];
+            assert_eq!(p.state, StateRole::Leader, "peer {} is not leader", leader);
+            p.raft_log.last_index()
+        };
+        let msgs = (0..n_proposals)
+            .map(|i| {
+                let mut m = Message::default();
+                m.set_msg_type(MessageType::MsgPropose);
+                m.from = leader;
+                m.to = leader;
+                let mut e = Entry::default();
+                e.set_data(i.to_string().into_bytes().into());
+                m.set_entries(vec![e].into());
+                m
+            })
+            .collect();
+
+        let start = Instant::now();
+        self.send(msgs);
+        let index = last_index + n_proposals as u64;
+        assert!(
+            self.peers[&leader].raft_log.last_index() >= index,
+            "proposals to {} are dropped",
+            leader
+        );
+        for _ in 0..MAX_TICKS {
+            if self.peers[&leader].raft_log.committed >= index {
+                return start.elapsed();
+            }
+            self.tick();
+        }
+        panic!(
+            "entries up to {} are not committed on {} after {} ticks",
+            index, leader, MAX_TICKS
+        );
+    }
+
+    /// Delivers `msgs` in the order given by `order`, a permutation of their indexes, and
+    /// returns the messages generated in response without sending them.
+    ///
+    /// This is useful for exploring whether the order of a batch of messages matters.
+    ///
+    /// NOTE: the given `msgs` won't be filtered by its filters, the responses are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is not a permutation of the indexes of `msgs`.
+    pub fn send_permuted(&mut self, msgs: Vec<Message>, order: &[usize]) -> Vec<Message> {
+        let mut indexes = order.to_vec();
+        indexes.sort_unstable();
+        assert!(
+            indexes.into_iter().eq(0..msgs.len()),
+            "{:?} is not a permutation of {} messages",
+            order,
+            msgs.len()
+        );
+        let mut msgs: Vec<Option<Message>> = msgs.into_iter().map(Some).collect();
+        let mut resps = vec![];
+        for &i in order {
+            let m = msgs[i].take().unwrap();
+            let to = m.to;
+            let _ = self.peers.get_mut(&to).unwrap().step(m);
+            self.record_election(to);
+            let resp = self.persist_and_read_messages(to);
+            resps.append(&mut self.filter(resp));
+        }
+        resps
+    }
+
+    /// Proposes `data` on `leader`, drives the network until the entry is committed on
+    /// `leader` and returns the index of the entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leader` is not the leader, the proposal is dropped, or the entry is not
+    /// committed within a bounded number of heartbeat rounds.
+    pub fn propose_and_commit(&mut self, leader: u64, data: Vec<u8>) -> u64 {
+        const MAX_ROUNDS: usize = 10;
+
+        let last_index = {
+            let p = &self.peers[&leader];
+            assert_eq!(p.state, StateRole::Leader, "peer {} is not leader", leader);
+            p.raft_log.last_index()
+        };
+        let mut m = Message::default();
+        m.set_msg_type(MessageType::MsgPropose);
+        m.from = leader;
+        m.to = leader;
+        let mut e = Entry::default();
+        e.set_data(data.into());
+        m.set_entries(vec![e].into());
+        self.send(vec![m]);
+
+        let index = self.peers[&leader].raft_log.last_index();
+        assert!(index > last_index, "proposal to {} is dropped", leader);
+        for _ in 0..MAX_ROUNDS {
+            if self.peers[&leader].raft_log.committed >= index {
+                return index;
+            }
+            let mut m = Message::default();
+            m.set_msg_type(MessageType::MsgBeat);
+            m.from = leader;
+            m.to = leader;
+            self.send(vec![m]);
+        }
+        panic!(
+            "entry {} is not committed on {} after {} rounds",
+            index, leader, MAX_ROUNDS
+        );
+    }
+
     /// Filter `msgs` and then instruct the cluster to `step` through the given messages.
     pub fn filter_and_send(&mut self, msgs: Vec<Message>) {
         self.send(self.filter(msgs));
@@ -190,10 +448,33 @@ impl Network {
             let to = message.to;
             let peer = self.peers.get_mut(&to).unwrap();
             peer.step(message)?;
+            self.record_election(to);
         }
         Ok(())
     }
 
+    /// Sets the seed of the random generators of the network, which makes the order
+    /// `set_reorder_only` delivers messages in deterministic.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        if self.reorder_rng.is_some() {
+            self.reorder_rng = Some(StdRng::seed_from_u64(seed));
+        }
+    }
+
+    /// Makes the network shuffle the delivery order of every round of messages of `send`,
+    /// seeded by `set_seed`, without ever losing a message.
+    ///
+    /// While enabled, the rules set by `ignore`, `drop`, `cut` and `isolate` are not
+    /// applied, which isolates reordering from loss.
+    pub fn set_reorder_only(&mut self, reorder_only: bool) {
+        self.reorder_rng = if reorder_only {
+            Some(StdRng::seed_from_u64(self.seed))
+        } else {
+            None
+        };
+    }
+
     /// Ignore messages from `from` to `to` at `perc` percent chance.
     ///
     /// `perc` set to `1f64` is a 100% chance, `0f64` is a 0% chance.
@@ -201,6 +482,13 @@ impl Network {
         self.dropm.insert(Connection { from, to }, perc);
     }
 
+    /// Deliver a second copy of messages from `from` to `to` at `perc` percent chance.
+    ///
+    /// `perc` set to `1f64` is a 100% chance, `0f64` is a 0% chance.
+    pub fn duplicate(&mut self, from: u64, to: u64, perc: f64) {
+        self.dupm.insert(Connection { from, to }, perc);
+    }
+
     /// Cut the communication between the two given nodes.
     pub fn cut(&mut self, one: u64, other: u64) {
         self.drop(one, other, 1f64);
@@ -218,9 +506,462 @@ impl Network {
         }
     }
 
-    /// Recover the cluster conditions applied with `drop` and `ignore`.
+    /// Turn the given raft into a black hole, which still receives messages from all other
+    /// raft in the cluster but never responds to them, like a hung but reachable node.
+    pub fn blackhole(&mut self, id: u64) {
+        for i in 0..self.peers.len() as u64 {
+            let nid = i + 1;
+            if nid != id {
+                self.drop(id, nid, 1.0);
+            }
+        }
+    }
+
+    /// Skews the clock of the given peer so that it ticks `rate` times per `tick` of the
+    /// network, e.g. `0.5` for a clock running at half speed.
+    ///
+    /// This is useful for exercising the clock drift hazards of lease based reads.
+    pub fn set_clock_skew(&mut self, id: u64, rate: f64) {
+        assert!(rate >= 0.0, "clock rate {} must not be negative", rate);
+        self.clocks.insert(id, (rate, 0.0));
+    }
+
+    /// Slows down the disk of the given peer so that its unstable data is only persisted,
+    /// and its messages are only sent, `ticks` network ticks after they are generated.
+    ///
+    /// The delay is measured in the simulated time of `tick`, setting it to 0 persists and
+    /// sends out everything held back right away.
+    pub fn set_apply_delay(&mut self, id: u64, ticks: u64) {
+        self.set_disk_delay(id, StorageOp::Append, ticks);
+    }
+
+    /// Makes the given storage operation of the peer take `micros` microseconds of
+    /// simulated time, rounded up to whole ticks of `TICK_MICROS`.
+    ///
+    /// The messages depending on the operation are held back until it's done. Setting
+    /// every latency of the peer to 0 sends out everything held back right away.
+    pub fn set_storage_latency(&mut self, id: u64, op: StorageOp, micros: u64) {
+        let ticks = micros.div_ceil(Self::TICK_MICROS);
+        self.set_disk_delay(id, op, ticks);
+    }
+
+    fn set_disk_delay(&mut self, id: u64, op: StorageOp, ticks: u64) {
+        if ticks == 0 && !self.slow_disks.contains_key(&id) {
+            return;
+        }
+        let disk = self.slow_disks.entry(id).or_default();
+        match op {
+            StorageOp::Append => disk.delay = ticks,
+            StorageOp::Entries => disk.entries_delay = ticks,
+            StorageOp::Snapshot => disk.snapshot_delay = ticks,
+        }
+        if disk.delay + disk.entries_delay + disk.snapshot_delay != 0 {
+            return;
+        }
+        let disk = self.slow_disks.remove(&id).unwrap();
+        persist(
+            self.peers.get_mut(&id).unwrap(),
+            &mut self.persisted_hard_states,
+        );
+        let msgs = disk
+            .pending
+            .into_iter()
+            .flat_map(|(_, msgs)| msgs)
+            .collect();
+        self.filter_and_send(msgs);
+    }
+
+    /// Persists the unstable data of the given peer and reads out its messages.
+    ///
+    /// If the peer has a slow disk, the messages are held back until the delay elapses.
+    fn persist_and_read_messages(&mut self, id: u64) -> Vec<Message> {
+        let p = self.peers.get_mut(&id).unwrap();
+        match self.slow_disks.get_mut(&id) {
+            Some(disk) => {
+                if disk.delay == 0 {
+                    persist(p, &mut self.persisted_hard_states);
+                }
+                let mut ready = vec![];
+                for m in p.read_messages() {
+                    let delay = disk.delay
+                        + match m.get_msg_type() {
+                            MessageType::MsgAppend if !m.entries.is_empty() => disk.entries_delay,
+                            MessageType::MsgSnapshot => disk.snapshot_delay,
+                            _ => 0,
+                        };
+                    if delay == 0 {
+                        ready.push(m);
+                        continue;
+                    }
+                    // Storage operations complete in order, so a faster operation waits
+                    // for the ones held back earlier.
+                    let due = self.now + delay;
+                    match disk.pending.back_mut() {
+                        Some((d, msgs)) if *d == due => msgs.push(m),
+                        _ => disk.pending.push_back((due, vec![m])),
+                    }
+                }
+                ready
+            }
+            None => {
+                // The unstable data should be persisted before sending msg.
+                persist(p, &mut self.persisted_hard_states);
+                p.read_messages()
+            }
+        }
+    }
+
+    /// Ticks every peer according to its clock and sends out the generated messages.
+    pub fn tick(&mut self) {
+        self.now += 1;
+        let mut msgs = vec![];
+        let mut ids: Vec<u64> = self.peers.keys().cloned().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let ticks = match self.clocks.get_mut(&id) {
+                Some((rate, acc)) => {
+                    *acc += *rate;
+                    let ticks = acc.floor();
+                    *acc -= ticks;
+                    ticks as usize
+                }
+                None => 1,
+            };
+            if self.peers[&id].raft.is_none() {
+                continue;
+            }
+            for _ in 0..ticks {
+                self.peers.get_mut(&id).unwrap().tick();
+                self.record_election(id);
+            }
+            msgs.append(&mut self.persist_and_read_messages(id));
+        }
+        let now = self.now;
+        for (id, disk) in &mut self.slow_disks {
+            let mut persisted = false;
+            while disk.pending.front().map_or(false, |(due, _)| *due <= now) {
+                if !persisted {
+                    persist(
+                        self.peers.get_mut(id).unwrap(),
+                        &mut self.persisted_hard_states,
+                    );
+                    persisted = true;
+                }
+                msgs.extend(disk.pending.pop_front().unwrap().1);
+            }
+        }
+        let msgs = self.filter(msgs);
+        self.send(msgs);
+    }
+
+    /// Makes `candidate` campaign, with a pre-vote first if it's enabled, and delivers the
+    /// messages of the campaign until the network settles, without ticking any peer.
+    ///
+    /// Unlike `run_until_leader`, no further round is started if this one doesn't elect the
+    /// candidate.
+    pub fn run_election_round(&mut self, candidate: u64) -> ElectionOutcome {
+        let mut m = Message::default();
+        m.set_msg_type(MessageType::MsgHup);
+        m.from = candidate;
+        m.to = candidate;
+        self.send(vec![m]);
+        match self.peers[&candidate].state {
+            StateRole::Leader => ElectionOutcome::Won,
+            StateRole::Follower => ElectionOutcome::Lost,
+            StateRole::Candidate | StateRole::PreCandidate => ElectionOutcome::Split,
+        }
+    }
+
+    /// Ticks the network until exactly one peer is leader and returns its ID.
+    ///
+    /// Returns an error if there is still no single leader after `max_rounds` ticks.
+    pub fn run_until_leader(&mut self, max_rounds: usize) -> std::result::Result<u64, String> {
+        for _ in 0..max_rounds {
+            if let [id] = self.leaders()[..] {
+                return Ok(id);
+            }
+            self.tick();
+        }
+        match self.leaders()[..] {
+            [id] => Ok(id),
+            ref leaders => Err(format!(
+                "no single leader after {} rounds, leaders: {:?}",
+                max_rounds, leaders
+            )),
+        }
+    }
+
+    /// Makes `leader` transfer its leadership to its most up to date follower and ticks the
+    /// network until a different leader is confirmed, returning its ID.
+    ///
+    /// Returns an error if `leader` is not the leader, has no follower to transfer to, or
+    /// there is no single new leader after `max_rounds` ticks.
+    pub fn step_down_and_confirm(
+        &mut self,
+        leader: u64,
+        max_rounds: usize,
+    ) -> std::result::Result<u64, String> {
+        let successor = {
+            let p = &self.peers[&leader];
+            if p.state != StateRole::Leader {
+                return Err(format!("peer {} is not leader", leader));
+            }
+            let mut voters: Vec<u64> = p.prs().conf().voters().ids().iter().collect();
+            voters.sort_unstable();
+            let mut successor: Option<(u64, u64)> = None;
+            for id in voters.into_iter().filter(|id| *id != leader) {
+                let matched = p.prs().get(id).unwrap().matched;
+                if successor.map_or(true, |(_, m)| matched > m) {
+                    successor = Some((id, matched));
+                }
+            }
+            match successor {
+                Some((id, _)) => id,
+                None => return Err(format!("peer {} has no follower to transfer to", leader)),
+            }
+        };
+        let mut m = Message::default();
+        m.set_msg_type(MessageType::MsgTransferLeader);
+        m.from = successor;
+        m.to = leader;
+        self.send(vec![m]);
+        for _ in 0..max_rounds {
+            if let [id] = self.leaders()[..] {
+                if id != leader {
+                    return Ok(id);
+                }
+            }
+            self.tick();
+        }
+        match self.leaders()[..] {
+            [id] if id != leader => Ok(id),
+            ref leaders => Err(format!(
+                "no new leader after {} rounds, leaders: {:?}",
+                max_rounds, leaders
+            )),
+        }
+    }
+
+    /// Delivers the messages in flight one hop per round until no peer produces any more
+    /// messages.
+    ///
+    /// The network is not ticked, as a ticking leader always produces heartbeats; only the
+    /// messages caused by the pending ones are followed. Messages held back by slow disks
+    /// are not considered.
+    ///
+    /// Returns an error if messages are still in flight after `max_rounds` rounds, which
+    /// indicates a message storm.
+    pub fn assert_quiescent(&mut self, max_rounds: usize) -> std::result::Result<(), String> {
+        let mut ids: Vec<u64> = self.peers.keys().cloned().collect();
+        ids.sort_unstable();
+        let mut msgs = vec![];
+        for id in ids {
+            if self.peers[&id].raft.is_some() {
+                msgs.append(&mut self.persist_and_read_messages(id));
+            }
+        }
+        let mut msgs = self.filter(msgs);
+        for _ in 0..max_rounds {
+            if msgs.is_empty() {
+                return Ok(());
+            }
+            let mut new_msgs = vec![];
+            for m in msgs.drain(..) {
+                let to = m.to;
+                let _ = self.peers.get_mut(&to).unwrap().step(m);
+                self.record_election(to);
+                let resp = self.persist_and_read_messages(to);
+                new_msgs.append(&mut self.filter(resp));
+            }
+            msgs = new_msgs;
+        }
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} messages still in flight after {} rounds",
+            msgs.len(),
+            max_rounds
+        ))
+    }
+
+    /// Returns the number of elections held by the peers since the network was created.
+    ///
+    /// Every term a peer campaigned in counts as an election, whether it won or not.
+    /// Pre-vote campaigns don't increase the term so they are not counted.
+    pub fn election_count(&self) -> u64 {
+        self.elections.len() as u64 - self.initial_elections
+    }
+
+    /// Records the election of the given peer if it has voted for itself in its term,
+    /// which is only done by a campaigning candidate. It should be called after every
+    /// tick and step of the peer as each of them can start a new election.
+    fn record_election(&mut self, id: u64) {
+        if let Some(r) = self.peers[&id].raft.as_ref() {
+            if r.vote == id && r.term > 0 {
+                self.elections.insert((id, r.term));
+            }
+        }
+    }
+
+    /// Returns the IDs of the peers that consider themselves leader.
+    fn leaders(&self) -> Vec<u64> {
+        let mut leaders: Vec<u64> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| {
+                p.raft
+                    .as_ref()
+                    .map_or(false, |r| r.state == StateRole::Leader)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        leaders.sort_unstable();
+        leaders
+    }
+
+    /// Asserts that the logs persisted by the peers are identical up to `up_to`.
+    ///
+    /// Only the entries that are available on every peer are compared, i.e. entries compacted
+    /// on any peer are skipped and a lagging peer is only checked up to its last index.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the mismatching entries of every peer if the logs diverge.
+    pub fn assert_log_prefix_match(&self, up_to: u64) {
+        let mut ids: Vec<u64> = self.storage.keys().cloned().collect();
+        ids.sort_unstable();
+        let low = ids
+            .iter()
+            .map(|id| self.storage[id].first_index().unwrap())
+            .max()
+            .unwrap_or(1);
+        let logs: Vec<(u64, Vec<Entry>)> = ids
+            .iter()
+            .map(|id| {
+                let store = &self.storage[id];
+                let high = cmp::min(up_to, store.last_index().unwrap()) + 1;
+                let ents = if low < high {
+                    store.entries(low, high, None).unwrap()
+                } else {
+                    vec![]
+                };
+                (*id, ents)
+            })
+            .collect();
+
+        let mut diff = vec![];
+        for index in low..=up_to {
+            let ents: Vec<(u64, &Entry)> = logs
+                .iter()
+                .filter_map(|(id, ents)| ents.get((index - low) as usize).map(|e| (*id, e)))
+                .collect();
+            if ents.iter().all(|(_, e)| *e == ents[0].1) {
+                continue;
+            }
+            diff.push(format!("index {}:", index));
+            for (id, e) in ents {
+                diff.push(format!(
+                    "  peer {}: term {}, type {:?}, data {:?}",
+                    id,
+                    e.term,
+                    e.get_entry_type(),
+                    e.get_data()
+                ));
+            }
+        }
+        if !diff.is_empty() {
+            panic!(
+                "logs of peers {:?} mismatch up to {}:\n{}",
+                ids,
+                up_to,
+                diff.join("\n")
+            );
+        }
+    }
+
+    /// Crashes the given peer, losing everything that is not persisted, i.e. its unstable
+    /// entries, the hard state changed since it was last persisted and the messages held
+    /// back by its slow disk. A crashed peer drops all the messages sent to it until it's
+    /// restarted by `restart`.
+    ///
+    /// The hard state persisted along with the unstable data is saved to the storage.
+    pub fn crash(&mut self, id: u64) {
+        self.peers.get_mut(&id).unwrap().raft = None;
+        self.slow_disks.remove(&id);
+        if let Some(hs) = self.persisted_hard_states.remove(&id) {
+            self.storage[&id].wl().set_hardstate(hs);
+        }
+    }
+
+    /// Restarts the given crashed peer from its storage with `config`.
+    pub fn restart(&mut self, id: u64, config: &Config, l: &Logger) {
+        assert!(self.peers[&id].raft.is_none(), "peer {} is not crashed", id);
+        let mut config = config.clone();
+        config.id = id;
+        let store = self.storage[&id].clone();
+        self.peers.get_mut(&id).unwrap().raft = Some(Raft::new(&config, store, l).unwrap());
+    }
+
+    /// Asserts that the entries up to `committed_before`, which were committed before some
+    /// peers crashed, are still persisted on a quorum of the peers.
+    ///
+    /// An entry is persisted on a quorum if a quorum of the peers agree on its term. Entries
+    /// compacted on a peer are considered persisted by it.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the terms of each lost entry on every peer.
+    pub fn assert_durability(&self, committed_before: u64) {
+        let mut ids: Vec<u64> = self.storage.keys().cloned().collect();
+        ids.sort_unstable();
+        let quorum = ids.len() / 2 + 1;
+        let mut lost = vec![];
+        for index in 1..=committed_before {
+            let mut compacted = 0;
+            let mut terms = HashMap::new();
+            for id in &ids {
+                let store = &self.storage[id];
+                if index < store.first_index().unwrap() {
+                    compacted += 1;
+                } else if let Ok(term) = store.term(index) {
+                    terms.insert(*id, term);
+                }
+            }
+            let durable = compacted >= quorum
+                || terms
+                    .values()
+                    .any(|t| terms.values().filter(|t2| *t2 == t).count() + compacted >= quorum);
+            if !durable {
+                let mut terms: Vec<_> = terms.into_iter().collect();
+                terms.sort_unstable();
+                lost.push(format!(
+                    "index {}: compacted on {} peers, (peer, term): {:?}",
+                    index, compacted, terms
+                ));
+            }
+        }
+        if !lost.is_empty() {
+            panic!(
+                "entries committed before {} are lost:\n{}",
+                committed_before,
+                lost.join("\n")
+            );
+        }
+    }
+
+    /// Recover the cluster conditions applied with `drop`, `duplicate` and `ignore`.
     pub fn recover(&mut self) {
         self.dropm = HashMap::new();
+        self.dupm = HashMap::new();
         self.ignorem = HashMap::new();
     }
 }
+
+/// Persists the unstable data of the peer and records its hard state as persisted.
+fn persist(p: &mut Interface, persisted_hard_states: &mut HashMap<u64, HardState>) {
+    p.persist();
+    if let Some(raft) = p.raft.as_ref() {
+        persisted_hard_states.insert(raft.id, raft.hard_state());
+    }
+}
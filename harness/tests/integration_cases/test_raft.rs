@@ -17,6 +17,8 @@
 use std::cmp;
 use std::collections::HashMap;
 use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use harness::*;
 use protobuf::Message as PbMessage;
@@ -1347,6 +1349,48 @@ fn test_handle_msg_append() {
     }
 }
 
+// test_handle_msg_append_stale_commit ensures that an append carrying a commit index lower
+// than the follower's still appends its entries, without regressing the commit index.
+#[test]
+fn test_handle_msg_append_stale_commit() {
+    let l = default_logger();
+    let mut sm = new_test_raft_with_logs(
+        2,
+        vec![1, 2],
+        10,
+        1,
+        MemStorage::new(),
+        &[empty_entry(1, 1), empty_entry(2, 2)],
+        &l,
+    );
+    sm.become_follower(2, 1);
+    let append = |index, commit, entries: Vec<Entry>| {
+        let mut m = new_message(1, 2, MessageType::MsgAppend, 0);
+        m.term = 2;
+        m.log_term = 2;
+        m.index = index;
+        m.commit = commit;
+        m.entries = entries.into();
+        m
+    };
+
+    sm.step(append(2, 3, vec![empty_entry(2, 3)])).unwrap();
+    assert_eq!(sm.raft_log.committed, 3);
+    assert_eq!(sm.stale_commit_appends(), 0);
+    sm.read_messages();
+
+    // An append that was overtaken by the newer one arrives.
+    sm.step(append(3, 2, vec![empty_entry(2, 4)])).unwrap();
+    assert_eq!(sm.raft_log.last_index(), 4);
+    assert_eq!(sm.raft_log.committed, 3);
+    assert_eq!(sm.stale_commit_appends(), 1);
+    assert_eq!(Status::new(&sm).stale_commit_appends, 1);
+    let msgs = sm.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert!(!msgs[0].reject);
+    assert_eq!((msgs[0].index, msgs[0].commit), (4, 3));
+}
+
 // test_handle_heartbeat ensures that the follower commits to the commit in the message.
 #[test]
 fn test_handle_heartbeat() {
@@ -2467,6 +2511,116 @@ fn test_read_only_option_lease() {
     }
 }
 
+// test_read_only_option_lease_with_clock_skew ensures a leader whose clock runs slow
+// keeps serving lease based reads after the rest of the cluster elected a new leader,
+// which returns stale data. This is the clock drift hazard of `LeaseBased`.
+#[test]
+fn test_read_only_option_lease_with_clock_skew() {
+    let l = default_logger();
+    for &(rate, wstale) in &[(0.05, true), (1.0, false)] {
+        let mut cfg = new_test_config(0, 10, 1);
+        cfg.read_only_option = ReadOnlyOption::LeaseBased;
+        cfg.check_quorum = true;
+        let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+        assert_eq!(nt.peers[&1].raft_log.committed, 2);
+
+        nt.set_clock_skew(1, rate);
+        nt.isolate(1);
+        // Tick long enough for a leader with a correct clock to notice it lost the quorum.
+        let mut new_leader = None;
+        for round in 0..60 {
+            nt.tick();
+            new_leader = (2..=3).find(|id| nt.peers[id].state == StateRole::Leader);
+            if round >= 2 * cfg.election_tick && new_leader.is_some() {
+                break;
+            }
+        }
+        let new_leader = new_leader.expect("no new leader is elected");
+        nt.send(vec![new_message(
+            new_leader,
+            new_leader,
+            MessageType::MsgPropose,
+            1,
+        )]);
+        let committed = nt.peers[&new_leader].raft_log.committed;
+        assert_eq!(committed, 4, "rate {}", rate);
+
+        let entry = new_entry(0, 0, Some("ctx"));
+        nt.send(vec![new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![entry],
+        )]);
+        let read_states = &nt.peers[&1].read_states;
+        if wstale {
+            assert_eq!(nt.peers[&1].state, StateRole::Leader);
+            assert_eq!(read_states.len(), 1);
+            assert!(read_states[0].index < committed);
+        } else {
+            assert_ne!(nt.peers[&1].state, StateRole::Leader);
+            assert!(read_states.is_empty());
+        }
+    }
+}
+
+// test_read_only_option_leader_lease_ticks ensures a leader stops serving lease based
+// reads once `leader_lease_ticks` pass without a quorum acknowledging its heartbeats,
+// even though it is still the leader.
+#[test]
+fn test_read_only_option_leader_lease_ticks() {
+    let l = default_logger();
+    let mut cfg = new_test_config(0, 10, 1);
+    cfg.read_only_option = ReadOnlyOption::LeaseBased;
+    cfg.check_quorum = true;
+    cfg.leader_lease_ticks = 4;
+    let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, 2);
+
+    let read = |nt: &mut Network, ctx: &str| {
+        let entry = new_entry(0, 0, Some(ctx));
+        let m = new_message_with_entries(1, 1, MessageType::MsgReadIndex, vec![entry]);
+        nt.peers.get_mut(&1).unwrap().step(m)
+    };
+
+    // No heartbeat has been acknowledged yet.
+    assert_eq!(read(&mut nt, "ctx1"), Err(Error::ReadIndexDropped));
+
+    nt.tick();
+    nt.tick();
+    assert_eq!(read(&mut nt, "ctx2"), Ok(()));
+    let read_states: Vec<_> = nt
+        .peers
+        .get_mut(&1)
+        .unwrap()
+        .read_states
+        .drain(..)
+        .collect();
+    assert_eq!(read_states.len(), 1);
+    assert_eq!(read_states[0].index, 2);
+
+    nt.isolate(1);
+    let mut expired = 0;
+    for i in 1..cfg.election_tick {
+        nt.tick();
+        if read(&mut nt, "ctx3").is_err() {
+            expired = i;
+            break;
+        }
+    }
+    assert!(
+        expired > 0 && expired <= cfg.leader_lease_ticks,
+        "lease expired after {} ticks",
+        expired
+    );
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&1].read_states.len(), expired - 1);
+}
+
 #[test]
 fn test_read_only_option_lease_without_check_quorum() {
     let l = default_logger();
@@ -2497,6 +2651,175 @@ fn test_read_only_option_lease_without_check_quorum() {
     assert_eq!(rs.request_ctx, vec_ctx);
 }
 
+// `test_read_only_max_pending_read_count` ensures that a leader drops safe read requests
+// once `max_pending_read_count` requests are waiting for heartbeat acknowledgments.
+#[test]
+fn test_read_only_max_pending_read_count() {
+    let l = default_logger();
+    let mut config = new_test_config(1, 10, 1);
+    config.max_pending_read_count = 2;
+    let s = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+    let a = new_test_raft_with_config(&config, s, &l);
+    let mut nt = Network::new(vec![Some(a), None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.ignore(MessageType::MsgHeartbeatResponse);
+
+    let read_index = |ctx: &str| {
+        new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some(ctx))],
+        )
+    };
+    for ctx in &["ctx1", "ctx2"] {
+        nt.peers.get_mut(&1).unwrap().step(read_index(ctx)).unwrap();
+    }
+    assert_eq!(
+        nt.peers.get_mut(&1).unwrap().step(read_index("ctx3")),
+        Err(Error::ReadIndexDropped)
+    );
+    // A request that is already pending doesn't take a new slot.
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(read_index("ctx1"))
+        .unwrap();
+    assert_eq!(nt.peers[&1].pending_read_count(), 2);
+    nt.peers.get_mut(&1).unwrap().read_messages();
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(nt.peers[&1].pending_read_count(), 0);
+    assert_eq!(nt.peers[&1].read_states.len(), 2);
+
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(read_index("ctx3"))
+        .unwrap();
+    assert_eq!(nt.peers[&1].pending_read_count(), 1);
+}
+
+// `test_read_only_max_pending_read_count_per_peer` ensures that a leader drops the excess
+// safe read requests of a node flooding it, while still accepting the ones of other nodes.
+#[test]
+fn test_read_only_max_pending_read_count_per_peer() {
+    let l = default_logger();
+    let mut config = new_test_config(1, 10, 1);
+    config.max_pending_read_count_per_peer = 2;
+    let s = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+    let a = new_test_raft_with_config(&config, s, &l);
+    let mut nt = Network::new(vec![Some(a), None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.ignore(MessageType::MsgHeartbeatResponse);
+
+    let read_index = |from: u64, ctx: &str| {
+        new_message_with_entries(
+            from,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some(ctx))],
+        )
+    };
+    for ctx in &["ctx1", "ctx2"] {
+        nt.peers
+            .get_mut(&1)
+            .unwrap()
+            .step(read_index(2, ctx))
+            .unwrap();
+    }
+    for ctx in &["ctx3", "ctx4"] {
+        assert_eq!(
+            nt.peers.get_mut(&1).unwrap().step(read_index(2, ctx)),
+            Err(Error::ReadIndexDropped)
+        );
+    }
+    // Joining a pending request of another node takes a slot too.
+    assert_eq!(
+        nt.peers.get_mut(&1).unwrap().step(read_index(2, "ctx5")),
+        Err(Error::ReadIndexDropped)
+    );
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(read_index(3, "ctx5"))
+        .unwrap();
+    assert_eq!(
+        nt.peers.get_mut(&1).unwrap().step(read_index(2, "ctx5")),
+        Err(Error::ReadIndexDropped)
+    );
+    // Other nodes are not affected.
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(read_index(3, "ctx6"))
+        .unwrap();
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(read_index(1, "ctx7"))
+        .unwrap();
+    assert_eq!(nt.peers[&1].pending_read_count(), 5);
+    nt.peers.get_mut(&1).unwrap().read_messages();
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(nt.peers[&1].pending_read_count(), 0);
+    let mut ctxs: Vec<Vec<u8>> = nt.peers[&2]
+        .read_states
+        .iter()
+        .map(|rs| rs.request_ctx.clone())
+        .collect();
+    ctxs.sort();
+    assert_eq!(ctxs, vec![b"ctx1".to_vec(), b"ctx2".to_vec()]);
+    assert_eq!(nt.peers[&3].read_states.len(), 2);
+
+    // The slots are released once the reads are served.
+    for ctx in &["ctx3", "ctx4"] {
+        nt.peers
+            .get_mut(&1)
+            .unwrap()
+            .step(read_index(2, ctx))
+            .unwrap();
+    }
+    assert_eq!(nt.peers[&1].pending_read_count(), 2);
+}
+
+// `test_read_only_with_same_context` ensures that all the nodes that request a read with
+// the same context are notified.
+#[test]
+fn test_read_only_with_same_context() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.ignore(MessageType::MsgHeartbeatResponse);
+
+    let ctx = "ctx1";
+    for id in 1..=3 {
+        nt.send(vec![new_message_with_entries(
+            id,
+            id,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some(ctx))],
+        )]);
+    }
+    assert_eq!(nt.peers[&1].pending_read_count(), 1);
+    for id in 1..=3 {
+        assert!(nt.peers[&id].read_states.is_empty());
+    }
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(nt.peers[&1].pending_read_count(), 0);
+    for id in 1..=3 {
+        let read_states = &nt.peers[&id].read_states;
+        assert_eq!(read_states.len(), 1, "peer {}", id);
+        assert_eq!(read_states[0].index, nt.peers[&1].raft_log.committed);
+        assert_eq!(read_states[0].request_ctx, ctx.as_bytes().to_vec());
+    }
+}
+
 // `test_read_only_for_new_leader` ensures that a leader only accepts MsgReadIndex message
 // when it commits at least one log entry at it term.
 #[test]
@@ -3127,6 +3450,51 @@ fn test_step_ignore_config() {
     assert_eq!(r.pending_conf_index, pending_conf_index);
 }
 
+// test_min_conf_change_interval tests that a conf change can't be proposed until
+// min_conf_change_interval_ticks have passed since the last one was applied.
+#[test]
+fn test_min_conf_change_interval() {
+    let l = default_logger();
+    let mut config = new_test_config(1, 10, 1);
+    config.min_conf_change_interval_ticks = 5;
+    let s = new_storage();
+    s.initialize_with_conf_state((vec![1], vec![]));
+    let mut r = new_test_raft_with_config(&config, s, &l);
+    r.become_candidate();
+    r.become_leader();
+    r.persist();
+
+    let add_learner = |id| {
+        let mut cc = ConfChange::default();
+        cc.set_change_type(ConfChangeType::AddLearnerNode);
+        cc.node_id = id;
+        let mut e = Entry::default();
+        e.set_entry_type(EntryType::EntryConfChange);
+        e.set_data(cc.write_to_bytes().unwrap().into());
+        let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+        m.set_entries(vec![e].into());
+        (m, cc.into_v2())
+    };
+
+    let (m, cc) = add_learner(2);
+    r.step(m).unwrap();
+    r.persist();
+    let committed = r.raft_log.committed;
+    r.commit_apply(committed);
+    r.apply_conf_change(&cc).unwrap();
+    assert!(!r.has_pending_conf());
+
+    for _ in 0..5 {
+        let last_index = r.raft_log.last_index();
+        let res = r.step(add_learner(3).0);
+        assert!(matches!(res, Err(Error::ConfChangeError(_))), "{:?}", res);
+        assert_eq!(r.raft_log.last_index(), last_index);
+        r.tick();
+    }
+    r.step(add_learner(3).0).unwrap();
+    assert!(r.has_pending_conf());
+}
+
 // test_new_leader_pending_config tests that new leader sets its pending_conf_index
 // based on uncommitted entries.
 #[test]
@@ -3564,6 +3932,110 @@ fn test_leader_transfer_ignore_proposal() {
     assert_eq!(nt.peers[&1].prs().get(1).unwrap().matched, 1);
 }
 
+#[test]
+fn test_leader_transfer_buffer_proposal() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.buffer_proposals_during_transfer = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    // Let 3 lag behind so that the transfer stays pending.
+    nt.isolate(3);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    nt.send(vec![new_message(3, 1, MessageType::MsgTransferLeader, 0)]);
+    assert_eq!(nt.peers[&1].lead_transferee, Some(3));
+
+    // The proposal is neither dropped nor appended while transferring.
+    assert_eq!(
+        nt.peers
+            .get_mut(&1)
+            .unwrap()
+            .step(new_message(1, 1, MessageType::MsgPropose, 1)),
+        Ok(())
+    );
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 2);
+
+    // Once 3 catches up and takes over, the proposal is forwarded to it.
+    nt.recover();
+    nt.send(vec![new_message(
+        3,
+        1,
+        MessageType::MsgHeartbeatResponse,
+        0,
+    )]);
+    check_leader_transfer_state(&nt.peers[&1], StateRole::Follower, 3);
+    assert_eq!(nt.peers[&3].state, StateRole::Leader);
+    let ents = nt.peers[&3].raft_log.all_entries();
+    assert_eq!(ents.len(), 4, "{:?}", ents);
+    assert_eq!(ents[3].data, b"somedata".as_ref());
+    assert_eq!(nt.peers[&1].raft_log.committed, 4);
+}
+
+// test_leader_transfer_buffer_proposal_limit ensures that proposals beyond the count or
+// the uncommitted size limit are dropped instead of buffered during a leader transfer.
+#[test]
+fn test_leader_transfer_buffer_proposal_limit() {
+    let l = default_logger();
+    for &(max_proposals, max_size) in &[(2, NO_LIMIT), (256, 12)] {
+        let mut config = Network::default_config();
+        config.buffer_proposals_during_transfer = true;
+        config.max_transfer_proposals = max_proposals;
+        config.max_uncommitted_size = max_size;
+        config.max_size_per_msg = cmp::min(max_size, config.max_size_per_msg);
+        let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        nt.isolate(3);
+        nt.send(vec![new_message(3, 1, MessageType::MsgTransferLeader, 0)]);
+
+        let mut results = vec![];
+        for _ in 0..3 {
+            let m = new_message(1, 1, MessageType::MsgPropose, 1);
+            results.push(nt.peers.get_mut(&1).unwrap().step(m));
+        }
+        let buffered = if max_size == NO_LIMIT { 2 } else { 1 };
+        for (i, res) in results.into_iter().enumerate() {
+            if i < buffered {
+                assert_eq!(res, Ok(()), "#{} {}", i, max_proposals);
+            } else {
+                assert_eq!(res, Err(Error::ProposalDropped), "#{} {}", i, max_proposals);
+            }
+        }
+
+        // The transfer times out, so 1 proposes the buffered entries itself.
+        let election_timeout = nt.peers[&1].election_timeout();
+        for _ in 0..election_timeout {
+            nt.peers.get_mut(&1).unwrap().tick();
+        }
+        assert_eq!(nt.peers[&1].raft_log.last_index(), 1 + buffered as u64);
+    }
+}
+
+#[test]
+fn test_leader_transfer_abort_replays_buffered_proposal() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.buffer_proposals_during_transfer = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    nt.isolate(3);
+    nt.send(vec![new_message(3, 1, MessageType::MsgTransferLeader, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 1);
+
+    // The transfer times out, so 1 proposes the entry itself.
+    let election_timeout = nt.peers[&1].election_timeout();
+    for _ in 0..election_timeout {
+        nt.peers.get_mut(&1).unwrap().tick();
+    }
+    check_leader_transfer_state(&nt.peers[&1], StateRole::Leader, 1);
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 2);
+    let msgs = nt.read_messages();
+    nt.send(msgs);
+    assert_eq!(nt.peers[&2].raft_log.committed, 2);
+}
+
 #[test]
 fn test_leader_transfer_receive_higher_term_vote() {
     let l = default_logger();
@@ -3703,6 +4175,55 @@ fn test_transfer_non_member() {
     assert_eq!(raft.state, StateRole::Follower);
 }
 
+// `test_strict_msg_term` ensures that remote messages without a term are rejected
+// when `strict_msg_term` is enabled, while forwarded requests are still accepted.
+#[test]
+fn test_strict_msg_term() {
+    let l = default_logger();
+    let mut config = new_test_config(1, 10, 1);
+    config.strict_msg_term = true;
+    let s = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut raft = new_test_raft_with_config(&config, s, &l);
+    raft.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .unwrap();
+    assert_eq!(raft.state, StateRole::Candidate);
+
+    // A crafted vote response without a term must not be counted.
+    for id in 2..=3 {
+        assert_eq!(
+            raft.step(new_message(id, 1, MessageType::MsgRequestVoteResponse, 0)),
+            Err(Error::StepTermMissing)
+        );
+    }
+    assert_eq!(raft.state, StateRole::Candidate);
+    for id in 2..=3 {
+        let mut m = new_message(id, 1, MessageType::MsgRequestVoteResponse, 0);
+        m.term = raft.term;
+        raft.step(m).unwrap();
+    }
+    assert_eq!(raft.state, StateRole::Leader);
+    raft.msgs.clear();
+
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = raft.raft_log.last_index();
+    assert_eq!(raft.step(m), Err(Error::StepTermMissing));
+    assert_eq!(raft.prs().get(2).unwrap().matched, 0);
+
+    // Forwarded proposals have no term.
+    let last_index = raft.raft_log.last_index();
+    raft.step(new_message(2, 1, MessageType::MsgPropose, 1))
+        .unwrap();
+    assert_eq!(raft.raft_log.last_index(), last_index + 1);
+
+    // Without `strict_msg_term`, such messages are treated as local ones.
+    let mut raft = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    raft.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .unwrap();
+    raft.step(new_message(2, 1, MessageType::MsgRequestVoteResponse, 0))
+        .unwrap();
+    assert_eq!(raft.state, StateRole::Leader);
+}
+
 // TestNodeWithSmallerTermCanCompleteElection tests the scenario where a node
 // that has been partitioned away (and fallen behind) rejoins the cluster at
 // about the same time the leader node gets partitioned away.
@@ -4029,15 +4550,68 @@ fn test_restore_learner_promotion() {
     assert!(sm.promotable());
 }
 
-// TestLearnerReceiveSnapshot tests that a learner can receive a snapshot from leader.
+// test_learner_caught_up_notification tests that the leader notifies once when a learner
+// matches its commit index.
 #[test]
-fn test_learner_receive_snapshot() {
+fn test_learner_caught_up_notification() {
     let l = default_logger();
-    let mut s = new_snapshot(11, 11, vec![1]);
-    s.mut_metadata().mut_conf_state().mut_learners().push(2);
-
-    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage(), &l);
-    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage(), &l);
+    let n1 = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage(), &l);
+    let n2 = new_test_learner_raft(2, vec![1, 2], vec![3], 10, 1, new_storage(), &l);
+    let n3 = new_test_learner_raft(3, vec![1, 2], vec![3], 10, 1, new_storage(), &l);
+    let mut nt = Network::new(vec![Some(n1), Some(n2), Some(n3)], &l);
+    let caught_up = Arc::new(Mutex::new(vec![]));
+    let c = caught_up.clone();
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .set_learner_caught_up_observer(move |id, matched| c.lock().unwrap().push((id, matched)));
+
+    nt.isolate(3);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    for _ in 0..3 {
+        nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    }
+    assert!(caught_up.lock().unwrap().is_empty());
+    assert!(!nt.peers[&1].prs().get(3).unwrap().caught_up);
+
+    nt.recover();
+    // The heartbeat resumes the replication to the learner.
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    let committed = nt.peers[&1].raft_log.committed;
+    assert_eq!(nt.peers[&3].raft_log.last_index(), committed);
+    assert_eq!(*caught_up.lock().unwrap(), vec![(3, committed)]);
+    assert!(nt.peers[&1].prs().get(3).unwrap().caught_up);
+
+    // It only fires once.
+    for _ in 0..3 {
+        nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    }
+    assert_eq!(caught_up.lock().unwrap().len(), 1);
+
+    // Even if the learner flaps back to probe and catches up again.
+    nt.send(vec![new_message(3, 1, MessageType::MsgUnreachable, 0)]);
+    assert_eq!(
+        nt.peers[&1].prs().get(3).unwrap().state,
+        ProgressState::Probe
+    );
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let pr = nt.peers[&1].prs().get(3).unwrap();
+    assert_eq!(pr.state, ProgressState::Replicate);
+    assert_eq!(pr.matched, nt.peers[&1].raft_log.committed);
+    assert!(pr.caught_up);
+    assert_eq!(caught_up.lock().unwrap().len(), 1);
+}
+
+// TestLearnerReceiveSnapshot tests that a learner can receive a snapshot from leader.
+#[test]
+fn test_learner_receive_snapshot() {
+    let l = default_logger();
+    let mut s = new_snapshot(11, 11, vec![1]);
+    s.mut_metadata().mut_conf_state().mut_learners().push(2);
+
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage(), &l);
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage(), &l);
 
     n1.restore(s);
     n1.persist();
@@ -4409,6 +4983,70 @@ fn test_new_raft_with_bad_config_errors() {
     assert!(raft.is_err())
 }
 
+// ensure a new Raft refuses to start if the applied index is ahead of the commit index
+#[test]
+fn test_new_raft_with_applied_beyond_commit() {
+    let l = default_logger();
+    for &(applied, committed, wok) in &[(2, 3, true), (3, 3, true), (4, 3, false)] {
+        let mut cfg = new_test_config(1, 10, 1);
+        cfg.applied = applied;
+        let s = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        let ents: Vec<_> = (1..=5).map(|i| empty_entry(1, i)).collect();
+        s.wl().append(&ents).unwrap();
+        s.wl().set_hardstate(hard_state(1, committed, 0));
+        match Raft::new(&cfg, s, &l) {
+            Ok(r) => {
+                assert!(wok, "applied {} should be refused", applied);
+                assert_eq!(r.raft_log.applied, applied);
+            }
+            Err(e) => {
+                assert!(!wok, "applied {}: unexpected error {:?}", applied, e);
+                assert_eq!(
+                    e,
+                    Error::ConfigInvalid(
+                        "applied index 4 is greater than commit index 3".to_owned()
+                    )
+                );
+            }
+        }
+    }
+}
+
+// ensure risky combinations of read only option, check quorum and pre vote are validated
+#[test]
+fn test_config_validate_read_only_option() {
+    let lease_err = || {
+        Err(Error::ConfigInvalid(
+            "read_only_option == LeaseBased requires check_quorum == true".to_owned(),
+        ))
+    };
+    let tests = vec![
+        (ReadOnlyOption::Safe, false, false, Ok(())),
+        (ReadOnlyOption::Safe, false, true, Ok(())),
+        (ReadOnlyOption::Safe, true, false, Ok(())),
+        (ReadOnlyOption::LeaseBased, true, false, Ok(())),
+        (ReadOnlyOption::LeaseBased, true, true, Ok(())),
+        // Without check quorum, a deposed leader keeps serving reads from its lease.
+        (ReadOnlyOption::LeaseBased, false, false, lease_err()),
+        // Pre-vote doesn't stop a partitioned leader from serving stale reads either.
+        (ReadOnlyOption::LeaseBased, false, true, lease_err()),
+    ];
+    for (i, (read_only_option, check_quorum, pre_vote, wres)) in tests.into_iter().enumerate() {
+        let mut cfg = new_test_config(1, 10, 1);
+        cfg.read_only_option = read_only_option;
+        cfg.check_quorum = check_quorum;
+        cfg.pre_vote = pre_vote;
+        assert_eq!(cfg.validate(), wres, "#{}", i);
+        let s = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        assert_eq!(
+            Raft::new(&cfg, s, &default_logger()).is_ok(),
+            wres.is_ok(),
+            "#{}",
+            i
+        );
+    }
+}
+
 // tests whether MsgAppend are batched
 #[test]
 fn test_batch_msg_append() {
@@ -4436,6 +5074,222 @@ fn test_batch_msg_append() {
     assert_eq!(raft.msgs.len(), 3);
 }
 
+// tests that batching MsgAppend respects max_size_per_msg
+#[test]
+fn test_batch_msg_append_size_limit() {
+    let l = default_logger();
+    let storage = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.max_size_per_msg = 100;
+    let mut raft = new_test_raft_with_config(&cfg, storage.clone(), &l);
+    raft.become_candidate();
+    raft.become_leader();
+    raft.set_batch_append(true);
+    commit_noop_entry(&mut raft, &storage);
+    let data = "a".repeat(40);
+    for _ in 0..10 {
+        let prop_msg = new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgPropose,
+            vec![new_entry(0, 0, Some(&data))],
+        );
+        assert!(raft.step(prop_msg).is_ok());
+    }
+    let to_2: Vec<_> = raft.msgs.iter().filter(|m| m.to == 2).collect();
+    assert!(to_2.len() > 1, "{:?}", to_2);
+    let mut total = 0;
+    for msg in &to_2 {
+        assert_eq!(msg.get_msg_type(), MessageType::MsgAppend);
+        assert!(
+            msg.entries.len() == 1 || raft::util::message_size(msg) <= 100,
+            "{:?}",
+            msg
+        );
+        total += msg.entries.len();
+    }
+    assert_eq!(total, 10);
+}
+
+// tests that a single entry larger than max_size_per_msg is either replicated in an
+// over-budget message or rejected, according to the configured policy.
+#[test]
+fn test_oversized_entry_policy() {
+    let l = default_logger();
+    let data = "a".repeat(200);
+    for &policy in &[OversizedEntryPolicy::Force, OversizedEntryPolicy::Reject] {
+        let mut config = Network::default_config();
+        config.max_size_per_msg = 100;
+        config.oversized_entry_policy = policy;
+        let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        let last_index = nt.peers[&1].raft_log.last_index();
+
+        let prop = new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgPropose,
+            vec![new_entry(0, 0, Some(&data))],
+        );
+        let res = nt.peers.get_mut(&1).unwrap().step(prop);
+        let msgs = nt.read_messages();
+        nt.send(msgs);
+        if policy == OversizedEntryPolicy::Force {
+            assert_eq!(res, Ok(()));
+            for id in 1..=3 {
+                assert_eq!(nt.peers[&id].raft_log.committed, last_index + 1, "{}", id);
+            }
+        } else {
+            match res {
+                Err(Error::ProposalTooLarge { size, max }) => {
+                    assert!(size > 200, "{}", size);
+                    assert_eq!(max, 100);
+                }
+                res => panic!("expected ProposalTooLarge, got {:?}", res),
+            }
+            assert_eq!(nt.peers[&1].raft_log.last_index(), last_index);
+            // Entries within the limit are still accepted.
+            nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+            assert_eq!(nt.peers[&3].raft_log.committed, last_index + 1);
+        }
+    }
+}
+
+// tests that the pipeline depth bounds the appends in flight to a follower behind a
+// high latency link, and that a deeper pipeline commits more in the same time.
+#[test]
+fn test_max_pipeline_depth() {
+    let l = default_logger();
+    // The number of rounds a message to or from the follower takes to arrive.
+    let latency = 4;
+    let mut committed = vec![];
+    for &depth in &[1, 4, 0] {
+        let mut config = Network::default_config();
+        config.max_size_per_msg = 0;
+        config.max_pipeline_depth = depth;
+        let mut nt = Network::new_with_config(vec![None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        assert_eq!(
+            nt.peers[&1].prs().get(2).unwrap().state,
+            ProgressState::Replicate
+        );
+        let start = nt.peers[&1].raft_log.committed;
+
+        let mut in_flight: Vec<(usize, Message)> = vec![];
+        for round in 0..40 {
+            nt.peers
+                .get_mut(&1)
+                .unwrap()
+                .step(new_message(1, 1, MessageType::MsgPropose, 1))
+                .unwrap();
+            for id in 1..=2 {
+                let p = nt.peers.get_mut(&id).unwrap();
+                p.persist();
+                for m in p.read_messages() {
+                    in_flight.push((round + latency, m));
+                }
+            }
+            let (due, pending) = in_flight.into_iter().partition(|(at, _)| *at <= round);
+            in_flight = pending;
+            for (_, m) in due {
+                let to = m.to;
+                nt.peers.get_mut(&to).unwrap().step(m).unwrap();
+            }
+
+            let depth_of_2 = nt.peers[&1].prs().get(2).unwrap().pipeline_depth();
+            if depth > 0 {
+                assert!(depth_of_2 <= depth, "{} > {}", depth_of_2, depth);
+            }
+        }
+        committed.push(nt.peers[&1].raft_log.committed - start);
+    }
+    assert!(committed[0] < committed[1], "{:?}", committed);
+    assert!(committed[1] <= committed[2], "{:?}", committed);
+}
+
+// tests that batching MsgAppend sends fewer messages without splitting entries
+#[test]
+fn test_batch_msg_append_message_count() {
+    let l = default_logger();
+    let data = "a".repeat(40);
+    let mut counts = vec![];
+    for (batch_append, max_size_per_msg) in &[(false, NO_LIMIT), (true, NO_LIMIT), (true, 200)] {
+        let storage = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+        let mut cfg = new_test_config(1, 10, 1);
+        cfg.batch_append = *batch_append;
+        cfg.max_size_per_msg = *max_size_per_msg;
+        let mut raft = new_test_raft_with_config(&cfg, storage.clone(), &l);
+        raft.become_candidate();
+        raft.become_leader();
+        commit_noop_entry(&mut raft, &storage);
+        let first = raft.raft_log.last_index() + 1;
+        for _ in 0..20 {
+            let prop_msg = new_message_with_entries(
+                1,
+                1,
+                MessageType::MsgPropose,
+                vec![new_entry(0, 0, Some(&data))],
+            );
+            raft.step(prop_msg).unwrap();
+        }
+        let msgs = raft.read_messages();
+        for to in 2..=3 {
+            // Every entry is sent exactly once and in order.
+            let mut next = first;
+            for m in msgs.iter().filter(|m| m.to == to) {
+                assert_eq!(m.get_msg_type(), MessageType::MsgAppend);
+                assert_eq!(m.index, next - 1);
+                for e in m.get_entries() {
+                    assert_eq!(e.index, next);
+                    assert_eq!(e.data, data.as_bytes());
+                    next += 1;
+                }
+            }
+            assert_eq!(next, first + 20);
+        }
+        counts.push(msgs.len());
+    }
+    assert_eq!(counts[0], 40);
+    assert_eq!(counts[1], 2);
+    assert!(
+        counts[2] > counts[1] && counts[2] < counts[0],
+        "{:?}",
+        counts
+    );
+}
+
+#[test]
+fn test_message_size() {
+    let mut append = new_message_with_entries(
+        1,
+        2,
+        MessageType::MsgAppend,
+        vec![new_entry(2, 11, SOME_DATA), new_entry(2, 12, Some("abc"))],
+    );
+    append.term = 2;
+    append.index = 10;
+    append.log_term = 1;
+    append.commit = 11;
+    let mut heartbeat = new_message(1, 2, MessageType::MsgHeartbeat, 0);
+    heartbeat.term = 3;
+    heartbeat.context = b"ctx".to_vec().into();
+    let mut snapshot = new_message(1, 2, MessageType::MsgSnapshot, 0);
+    snapshot.set_snapshot(new_snapshot(100, 5, vec![1, 2, 3]));
+    let mut response = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    response.reject = true;
+    response.reject_hint = 7;
+    let empty = Message::default();
+
+    for m in &[append, heartbeat, snapshot, response, empty] {
+        assert_eq!(
+            raft::util::message_size(m),
+            m.write_to_bytes().unwrap().len(),
+            "{:?}",
+            m
+        );
+    }
+}
+
 /// Tests if unapplied conf change is checked before campaign.
 #[test]
 fn test_conf_change_check_before_campaign() {
@@ -5837,3 +6691,1570 @@ fn test_fast_log_rejection() {
         assert_eq!(msgs[0].index, next_append_index, "#{}", i);
     }
 }
+
+// test_send_batched ensures delivering messages in batches ends up in the same state as
+// delivering them one at a time.
+#[test]
+fn test_send_batched() {
+    let l = default_logger();
+    let mut one_by_one = Network::new(vec![None, None, None], &l);
+    let mut batched = Network::new(vec![None, None, None], &l);
+    for nt in &mut [&mut one_by_one, &mut batched] {
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    }
+
+    let proposals: Vec<_> = (0..100)
+        .map(|_| new_message(1, 1, MessageType::MsgPropose, 1))
+        .collect();
+    one_by_one.send(proposals.clone());
+    batched.send_batched(proposals, 16);
+
+    for id in 1..=3 {
+        let expected = &one_by_one.peers[&id].raft_log;
+        let got = &batched.peers[&id].raft_log;
+        assert_eq!(got.committed, 101, "#{}", id);
+        assert_eq!(ltoa(got), ltoa(expected), "#{}", id);
+    }
+}
+
+// test_heartbeat_rtt ensures the leader reports the heartbeat round trip time of each
+// follower and that delayed responses increase it.
+#[test]
+fn test_heartbeat_rtt() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    let round = |nt: &mut Network, delay: usize| {
+        nt.peers.get_mut(&1).unwrap().tick();
+        let hb: Vec<_> = nt
+            .peers
+            .get_mut(&1)
+            .unwrap()
+            .read_messages()
+            .into_iter()
+            .filter(|m| m.to == 2)
+            .collect();
+        assert_eq!(hb.len(), 1);
+        assert_eq!(hb[0].get_msg_type(), MessageType::MsgHeartbeat);
+        let follower = nt.peers.get_mut(&2).unwrap();
+        follower.step(hb[0].clone()).unwrap();
+        let resp = follower.read_messages();
+        let leader = nt.peers.get_mut(&1).unwrap();
+        for _ in 0..delay {
+            leader.tick();
+        }
+        leader.read_messages();
+        for m in resp {
+            leader.step(m).unwrap();
+        }
+        let status = Status::new(leader);
+        let pr = status.progress.unwrap().get(2).unwrap();
+        assert_eq!(pr.heartbeat_sent_tick, None);
+        pr.heartbeat_rtt
+    };
+
+    assert_eq!(round(&mut nt, 0), 0);
+    assert_eq!(round(&mut nt, 3), 3);
+    assert_eq!(round(&mut nt, 5), 5);
+    assert_eq!(round(&mut nt, 1), 1);
+    // Node 3 never answers, so its oldest heartbeat is still being timed.
+    let pr = nt.peers[&1].prs().get(3).unwrap();
+    assert_eq!(pr.heartbeat_sent_tick, Some(1));
+    assert_eq!(pr.heartbeat_rtt, 0);
+}
+
+#[test]
+fn test_propose_and_commit() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    let index = nt.propose_and_commit(1, b"foo".to_vec());
+    assert_eq!(index, 2);
+    // A minority partition doesn't block the commit.
+    nt.isolate(3);
+    let index = nt.propose_and_commit(1, b"bar".to_vec());
+    assert_eq!(index, 3);
+    for id in 1..=2 {
+        let raft_log = &nt.peers[&id].raft_log;
+        assert_eq!(raft_log.committed, 3, "#{}", id);
+        let ents = raft_log.entries(2, NO_LIMIT).unwrap();
+        assert_eq!(&ents[0].data[..], b"foo", "#{}", id);
+        assert_eq!(&ents[1].data[..], b"bar", "#{}", id);
+    }
+    assert_eq!(nt.peers[&3].raft_log.committed, 2);
+}
+
+#[test]
+#[should_panic(expected = "is not committed")]
+fn test_propose_and_commit_without_quorum() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.isolate(1);
+    nt.propose_and_commit(1, b"foo".to_vec());
+}
+
+#[test]
+fn test_apply_delay() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.set_apply_delay(3, 3);
+
+    // The entry is committed by the quorum of 1 and 2 while 3 lags.
+    let index = nt.propose_and_commit(1, b"foo".to_vec());
+    assert_eq!(nt.storage[&2].last_index().unwrap(), index);
+    assert!(nt.storage[&3].last_index().unwrap() < index);
+    assert!(nt.peers[&1].prs().get(3).unwrap().matched < index);
+
+    for _ in 0..3 {
+        nt.tick();
+    }
+    assert_eq!(nt.storage[&3].last_index().unwrap(), index);
+    assert_eq!(nt.peers[&1].prs().get(3).unwrap().matched, index);
+
+    // Removing the delay flushes everything held back.
+    let index = nt.propose_and_commit(1, b"bar".to_vec());
+    assert!(nt.storage[&3].last_index().unwrap() < index);
+    nt.set_apply_delay(3, 0);
+    assert_eq!(nt.storage[&3].last_index().unwrap(), index);
+    assert_eq!(nt.peers[&1].prs().get(3).unwrap().matched, index);
+}
+
+// `test_max_probe_rounds` ensures that the leader sends a snapshot to a follower with a
+// long divergent log once it has rejected `max_probe_rounds` appends.
+#[test]
+fn test_max_probe_rounds() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(5, 1, vec![1, 2]))
+        .unwrap();
+    let ents: Vec<_> = (6..=10).map(|i| empty_entry(1, i)).collect();
+    s.wl().append(&ents).unwrap();
+    let mut config = new_test_config(1, 10, 1);
+    config.max_probe_rounds = 2;
+    let mut sm = new_test_raft_with_config(&config, s, &l);
+    sm.become_candidate();
+    sm.become_leader();
+    sm.persist();
+    sm.read_messages();
+
+    // Every probe is rejected as the follower diverges one entry further back.
+    sm.mut_prs().get_mut(2).unwrap().next_idx = 11;
+    for round in 1..=2 {
+        let next = sm.prs().get(2).unwrap().next_idx;
+        let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+        m.term = sm.term;
+        m.index = next - 1;
+        m.reject = true;
+        m.reject_hint = next - 2;
+        sm.step(m).unwrap();
+
+        let msgs = sm.read_messages();
+        assert_eq!(msgs.len(), 1);
+        if round < 2 {
+            assert_eq!(msgs[0].get_msg_type(), MessageType::MsgAppend);
+            assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Probe);
+            assert_eq!(sm.prs().get(2).unwrap().probe_rounds, round);
+        } else {
+            assert_eq!(msgs[0].get_msg_type(), MessageType::MsgSnapshot);
+            assert_eq!(msgs[0].get_snapshot().get_metadata().index, 5);
+            assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Snapshot);
+        }
+    }
+}
+
+// `test_skip_duplicate_empty_entry` ensures that an empty entry is not proposed again while
+// an uncommitted empty entry of the same term is at the end of the log.
+#[test]
+fn test_skip_duplicate_empty_entry() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.skip_duplicate_empty_entry = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    let propose_empty = |nt: &mut Network, id| {
+        let mut m = new_message(id, id, MessageType::MsgPropose, 0);
+        m.set_entries(vec![Entry::default()].into());
+        nt.send(vec![m]);
+    };
+
+    // Leadership flaps between 1 and 2, every new leader proposes empty entries eagerly
+    // while it's isolated.
+    for &id in &[1, 2, 1] {
+        nt.recover();
+        nt.send(vec![new_message(id, id, MessageType::MsgHup, 0)]);
+        assert_eq!(nt.peers[&id].state, StateRole::Leader);
+        let committed = nt.peers[&id].raft_log.committed;
+        assert_eq!(nt.peers[&id].raft_log.last_index(), committed);
+
+        nt.isolate(id);
+        for _ in 0..3 {
+            propose_empty(&mut nt, id);
+        }
+        // The tail is committed, so only the first one is appended.
+        assert_eq!(nt.peers[&id].raft_log.last_index(), committed + 1);
+    }
+
+    // Non-empty proposals are never skipped.
+    let id = 1;
+    let last_index = nt.peers[&id].raft_log.last_index();
+    nt.send(vec![new_message(id, id, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&id].raft_log.last_index(), last_index + 1);
+}
+
+#[test]
+fn test_run_until_leader() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    let leader = nt.run_until_leader(100).unwrap();
+    assert_eq!(nt.peers[&leader].state, StateRole::Leader);
+    // The leader is returned right away if there is one already.
+    assert_eq!(nt.run_until_leader(0), Ok(leader));
+
+    // No peer can reach a quorum.
+    let mut nt = Network::new(vec![None, None, None], &l);
+    for id in 1..=3 {
+        nt.isolate(id);
+    }
+    let err = nt.run_until_leader(100).unwrap_err();
+    assert!(err.contains("no single leader after 100 rounds"), "{}", err);
+}
+
+// `test_run_election_round` drives single election rounds, which are split while no
+// candidate can reach a quorum.
+#[test]
+fn test_run_election_round() {
+    let l = default_logger();
+    for &pre_vote in &[false, true] {
+        let mut config = Network::default_config();
+        config.pre_vote = pre_vote;
+        let mut nt = Network::new_with_config(vec![None, None, None, None, None], &config, &l);
+
+        // 2 only reaches 3.
+        nt.isolate(1);
+        nt.isolate(4);
+        nt.isolate(5);
+        assert_eq!(nt.run_election_round(2), ElectionOutcome::Split);
+        let state = if pre_vote {
+            StateRole::PreCandidate
+        } else {
+            StateRole::Candidate
+        };
+        assert_eq!(nt.peers[&2].state, state);
+
+        if !pre_vote {
+            // 3 has voted for 2, so 1 can't win either.
+            assert_eq!(nt.peers[&3].vote, 2);
+            nt.recover();
+            nt.isolate(4);
+            nt.isolate(5);
+            assert_eq!(nt.run_election_round(1), ElectionOutcome::Split);
+            assert_eq!(nt.peers[&2].state, StateRole::Candidate);
+        }
+
+        nt.recover();
+        assert_eq!(nt.run_election_round(3), ElectionOutcome::Won);
+        assert_eq!(nt.peers[&3].state, StateRole::Leader);
+
+        // 5 falls behind and can't be elected.
+        nt.isolate(5);
+        nt.propose_and_commit(3, b"foo".to_vec());
+        nt.recover();
+        assert_eq!(nt.run_election_round(5), ElectionOutcome::Lost);
+    }
+}
+
+// `test_reset_election_on_append` ensures that followers don't time out under heavy append
+// load when heartbeats are lost, unless the election timer only resets on heartbeats.
+#[test]
+fn test_reset_election_on_append() {
+    let l = default_logger();
+    for &reset_on_append in &[true, false] {
+        let mut config = Network::default_config();
+        config.reset_election_on_append = reset_on_append;
+        let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        let term = nt.peers[&1].term;
+        nt.ignore(MessageType::MsgHeartbeat);
+
+        for _ in 0..3 * config.election_tick {
+            nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+            nt.tick();
+        }
+        if reset_on_append {
+            assert_eq!(nt.peers[&1].state, StateRole::Leader);
+            assert_eq!(nt.peers[&1].term, term);
+        } else {
+            assert!(nt.peers.values().any(|p| p.term > term));
+        }
+    }
+}
+
+#[test]
+fn test_assert_log_prefix_match() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let mut index = 0;
+    for data in &[b"foo", b"bar"] {
+        index = nt.propose_and_commit(1, data.to_vec());
+    }
+    nt.assert_log_prefix_match(index);
+
+    // Corrupt the last entry of peer 3.
+    nt.storage[&3]
+        .wl()
+        .append(&[new_entry(1, index, Some("baz"))])
+        .unwrap();
+    let res = panic::catch_unwind(AssertUnwindSafe(|| nt.assert_log_prefix_match(index)));
+    let msg = *res.unwrap_err().downcast::<String>().unwrap();
+    assert!(msg.contains(&format!("index {}:", index)), "{}", msg);
+    assert!(msg.contains("peer 3: term 1"), "{}", msg);
+    assert!(!msg.contains(&format!("index {}:", index - 1)), "{}", msg);
+    // The entries before are still identical.
+    nt.assert_log_prefix_match(index - 1);
+}
+
+#[test]
+fn test_pause_election() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let term = nt.peers[&1].term;
+    for id in 2..=3 {
+        nt.peers.get_mut(&id).unwrap().pause_election();
+    }
+
+    // Replication goes on while the elections are paused.
+    let index = nt.propose_and_commit(1, b"foo".to_vec());
+    nt.isolate(1);
+    for _ in 0..50 {
+        nt.tick();
+    }
+    for id in 2..=3 {
+        assert_eq!(nt.peers[&id].state, StateRole::Follower);
+        assert_eq!(nt.peers[&id].term, term);
+        assert_eq!(nt.peers[&id].raft_log.last_index(), index);
+    }
+
+    nt.peers.get_mut(&3).unwrap().resume_election();
+    for _ in 0..50 {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&3].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].state, StateRole::Follower);
+    assert_eq!(nt.peers[&2].term, nt.peers[&3].term);
+}
+
+// test_likely_partitioned ensures a leader isolated into the minority reports it's likely
+// partitioned before check quorum makes it step down.
+#[test]
+fn test_likely_partitioned() {
+    let l = default_logger();
+    let mut cfg = new_test_config(0, 10, 1);
+    cfg.check_quorum = true;
+    cfg.partition_detection_ticks = 3;
+    let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    for _ in 0..cfg.election_tick {
+        nt.tick();
+        assert!(!nt.peers[&1].is_likely_partitioned());
+    }
+
+    // Losing a single follower keeps the quorum.
+    nt.isolate(3);
+    for _ in 0..cfg.partition_detection_ticks + 1 {
+        nt.tick();
+    }
+    assert!(!nt.peers[&1].is_likely_partitioned());
+    nt.recover();
+
+    nt.isolate(1);
+    let mut detected = None;
+    for i in 1..=2 * cfg.election_tick {
+        nt.tick();
+        if nt.peers[&1].state != StateRole::Leader {
+            break;
+        }
+        if detected.is_none() && nt.peers[&1].is_likely_partitioned() {
+            detected = Some(i);
+        }
+    }
+    let detected = detected.expect("partition is not detected");
+    assert!(
+        detected <= cfg.partition_detection_ticks + 1,
+        "detected after {} ticks",
+        detected
+    );
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert!(!nt.peers[&1].is_likely_partitioned());
+}
+
+// test_progress_hints ensures a restarted leader probes followers from the hinted matched
+// indexes, which saves the rejected probes of a lagging follower, while a wrong hint is
+// still corrected.
+#[test]
+fn test_progress_hints() {
+    let l = default_logger();
+    // Returns the number of appends rejected by each follower until the logs converge.
+    let run = |hints: Vec<(u64, u64)>| {
+        let ents: Vec<_> = (1..=5).map(|i| empty_entry(1, i)).collect();
+        let mut peers: Vec<_> = [5, 5, 2]
+            .iter()
+            .enumerate()
+            .map(|(i, &last)| {
+                let storage = new_storage();
+                storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+                storage.wl().append(&ents[..last]).unwrap();
+                storage.wl().set_hardstate(hard_state(1, 0, 0));
+                new_test_raft(i as u64 + 1, vec![1, 2, 3], 10, 1, storage, &l)
+            })
+            .collect();
+        peers[0].set_progress_hints(hints);
+        peers[0].become_candidate();
+        peers[0].become_leader();
+        peers[0].bcast_append();
+
+        let mut rejects = vec![0; 2];
+        loop {
+            let msgs = peers[0].read_messages();
+            if msgs.is_empty() {
+                break;
+            }
+            for m in msgs {
+                let follower = &mut peers[m.to as usize - 1];
+                follower.step(m).unwrap();
+                for resp in follower.read_messages() {
+                    if resp.get_msg_type() == MessageType::MsgAppendResponse && resp.reject {
+                        rejects[resp.from as usize - 2] += 1;
+                    }
+                    peers[0].step(resp).unwrap();
+                }
+            }
+        }
+        for p in &peers[1..] {
+            assert_eq!(p.raft_log.last_index(), 6);
+            assert_eq!(p.raft_log.last_term(), 2);
+        }
+        rejects
+    };
+
+    assert_eq!(run(vec![]), vec![0, 1]);
+    // Correct hints save the rejected probe of the lagging follower.
+    assert_eq!(run(vec![(2, 5), (3, 2)]), vec![0, 0]);
+    // Wrong hints are corrected by rejections.
+    assert_eq!(run(vec![(2, 4), (3, 4)]), vec![0, 1]);
+}
+
+// test_election_count ensures a partitioned follower keeps disrupting the cluster with
+// elections unless pre-vote is enabled.
+#[test]
+fn test_election_count() {
+    let l = default_logger();
+    let mut counts = vec![];
+    for &pre_vote in &[false, true] {
+        let mut cfg = Network::default_config();
+        cfg.pre_vote = pre_vote;
+        let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+        assert_eq!(nt.election_count(), 0);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        assert_eq!(nt.election_count(), 1);
+
+        nt.isolate(3);
+        for _ in 0..5 * cfg.election_tick {
+            nt.tick();
+        }
+        nt.recover();
+        let leader = nt.run_until_leader(5 * cfg.election_tick).unwrap();
+        nt.propose_and_commit(leader, b"foo".to_vec());
+        counts.push(nt.election_count());
+    }
+    assert!(counts[0] > 2, "{:?}", counts);
+    assert_eq!(counts[1], 1, "{:?}", counts);
+}
+
+// test_stale_leader_steps_down_on_higher_term_append ensures a stale leader receiving an
+// append from a new leader converts to follower and accepts the entries in place of its
+// uncommitted ones.
+#[test]
+fn test_stale_leader_steps_down_on_higher_term_append() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.propose_and_commit(1, b"foo".to_vec());
+
+    nt.isolate(1);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let stale_index = nt.peers[&1].raft_log.last_index();
+    nt.send(vec![new_message(3, 3, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&3].state, StateRole::Leader);
+    let index = nt.propose_and_commit(3, b"bar".to_vec());
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&1].higher_term_append_step_downs(), 0);
+
+    nt.recover();
+    let mut m = new_message(3, 1, MessageType::MsgAppend, 0);
+    m.term = nt.peers[&3].term;
+    m.index = stale_index - 1;
+    m.log_term = nt.peers[&3].raft_log.term(stale_index - 1).unwrap();
+    m.commit = nt.peers[&3].raft_log.committed;
+    m.entries = nt.peers[&3]
+        .raft_log
+        .entries(stale_index, None)
+        .unwrap()
+        .into();
+    nt.send(vec![m]);
+
+    let stale = &nt.peers[&1];
+    assert_eq!(stale.state, StateRole::Follower);
+    assert_eq!(stale.leader_id, 3);
+    assert_eq!(stale.term, nt.peers[&3].term);
+    assert_eq!(stale.higher_term_append_step_downs(), 1);
+    assert_eq!(Status::new(stale).higher_term_append_step_downs, 1);
+    assert_eq!(stale.raft_log.committed, index);
+    nt.assert_log_prefix_match(index);
+
+    // Followers don't count as stepping down.
+    assert_eq!(nt.peers[&2].higher_term_append_step_downs(), 0);
+}
+
+// test_duplicated_messages ensures duplicated votes are not counted twice and duplicated
+// appends don't append entries twice.
+#[test]
+fn test_duplicated_messages() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None, None, None], &l);
+    for id in 3..=5 {
+        nt.cut(1, id);
+    }
+    nt.duplicate(2, 1, 1.0);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    // Only 1 and 2 voted, no matter how many times the vote of 2 is received.
+    assert_eq!(nt.peers[&1].state, StateRole::Candidate);
+
+    let mut nt = Network::new(vec![None, None, None], &l);
+    for (from, to) in &[(1, 2), (2, 1), (1, 3), (3, 1)] {
+        nt.duplicate(*from, *to, 1.0);
+    }
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    let first = nt.peers[&1].raft_log.last_index() + 1;
+    for i in 0..5 {
+        let data = format!("data{}", i);
+        nt.send(vec![new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgPropose,
+            vec![new_entry(0, 0, Some(&data))],
+        )]);
+    }
+    let last = first + 4;
+    for id in 1..=3 {
+        let p = &nt.peers[&id];
+        assert_eq!(p.raft_log.last_index(), last, "peer {}", id);
+        assert_eq!(p.raft_log.committed, last, "peer {}", id);
+        let ents = p.raft_log.entries(first, None).unwrap();
+        for (i, e) in ents.iter().enumerate() {
+            assert_eq!(e.data, format!("data{}", i).into_bytes(), "peer {}", id);
+        }
+    }
+    nt.assert_log_prefix_match(last);
+}
+
+#[test]
+fn test_status_applied_term() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    let apply_all = |nt: &mut Network| {
+        for id in 1..=3 {
+            let p = nt.peers.get_mut(&id).unwrap();
+            let committed = p.raft_log.committed;
+            p.commit_apply(committed);
+        }
+    };
+    assert_eq!(Status::new(&nt.peers[&1]).applied_term, 0);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.propose_and_commit(1, b"foo".to_vec());
+    apply_all(&mut nt);
+    for id in 1..=3 {
+        let status = Status::new(&nt.peers[&id]);
+        assert_eq!(status.applied, 2);
+        assert_eq!(status.applied_term, 1);
+    }
+
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&2].state, StateRole::Leader);
+    // Applying up to the entries of the old leader keeps its term.
+    for id in 1..=3 {
+        assert_eq!(nt.peers[&id].raft_log.applied_term(), 1);
+    }
+    nt.propose_and_commit(2, b"bar".to_vec());
+    apply_all(&mut nt);
+    for id in 1..=3 {
+        let status = Status::new(&nt.peers[&id]);
+        assert_eq!(status.applied, 4);
+        assert_eq!(status.applied_term, 2);
+    }
+}
+
+// test_lazy_leader_noop ensures a leader with lazy_leader_noop doesn't append an empty
+// entry on election, and gates read index requests until the empty entry appended by the
+// first of them commits its term.
+#[test]
+fn test_lazy_leader_noop() {
+    let l = default_logger();
+    let mut cfg = Network::default_config();
+    cfg.lazy_leader_noop = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    for id in 1..=3 {
+        assert_eq!(nt.peers[&id].raft_log.last_index(), 0);
+    }
+
+    let read = |nt: &mut Network, id: u64, ctx: &str| {
+        let entry = new_entry(0, 0, Some(ctx));
+        nt.send(vec![new_message_with_entries(
+            id,
+            id,
+            MessageType::MsgReadIndex,
+            vec![entry],
+        )]);
+        nt.peers
+            .get_mut(&id)
+            .unwrap()
+            .read_states
+            .drain(..)
+            .collect::<Vec<_>>()
+    };
+
+    // The first read is dropped but commits the term.
+    assert!(read(&mut nt, 2, "ctx1").is_empty());
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 1);
+    assert_eq!(nt.peers[&1].raft_log.committed, 1);
+    let read_states = read(&mut nt, 2, "ctx2");
+    assert_eq!(read_states.len(), 1);
+    assert_eq!(read_states[0].index, 1);
+    assert_eq!(read_states[0].request_ctx, b"ctx2");
+
+    // A proposal commits the term without an empty entry.
+    nt.send(vec![new_message(3, 3, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&3].state, StateRole::Leader);
+    let index = nt.propose_and_commit(3, b"foo".to_vec());
+    assert_eq!(index, 2);
+    let read_states = read(&mut nt, 3, "ctx3");
+    assert_eq!(read_states.len(), 1);
+    assert_eq!(read_states[0].index, index);
+}
+
+// test_lazy_leader_noop_conf_change ensures a conf change proposed as the first proposal
+// of a leader with lazy_leader_noop is preceded by an empty entry of its term.
+#[test]
+fn test_lazy_leader_noop_conf_change() {
+    let l = default_logger();
+    let mut cfg = Network::default_config();
+    cfg.lazy_leader_noop = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 0);
+    assert!(!nt.peers[&1].has_pending_conf());
+
+    let mut cc = ConfChange::default();
+    cc.set_change_type(ConfChangeType::AddLearnerNode);
+    cc.node_id = 4;
+    let mut e = Entry::default();
+    e.set_entry_type(EntryType::EntryConfChange);
+    e.set_data(cc.write_to_bytes().unwrap().into());
+    nt.send(vec![new_message_with_entries(
+        1,
+        1,
+        MessageType::MsgPropose,
+        vec![e],
+    )]);
+
+    let term = nt.peers[&1].term;
+    let ents = nt.peers[&1].raft_log.all_entries();
+    assert_eq!(ents.len(), 2);
+    assert_eq!(ents[0].get_entry_type(), EntryType::EntryNormal);
+    assert!(ents[0].data.is_empty());
+    assert_eq!(ents[0].term, term);
+    assert_eq!(ents[1].get_entry_type(), EntryType::EntryConfChange);
+    assert_eq!(nt.peers[&1].raft_log.committed, 2);
+
+    // The term is committed, so later conf changes don't append another empty entry.
+    let mut e = Entry::default();
+    e.set_entry_type(EntryType::EntryConfChange);
+    nt.peers.get_mut(&1).unwrap().commit_apply(2);
+    nt.send(vec![new_message_with_entries(
+        1,
+        1,
+        MessageType::MsgPropose,
+        vec![e],
+    )]);
+    assert_eq!(nt.peers[&1].raft_log.last_index(), 3);
+}
+
+#[test]
+fn test_durability_across_restarts() {
+    let l = default_logger();
+    let cfg = Network::default_config();
+    let mut nt = Network::new_with_config(vec![None, None, None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    for i in 0..5 {
+        nt.propose_and_commit(1, format!("data{}", i).into_bytes());
+    }
+    // The entry is only persisted by the crashing minority.
+    nt.cut(1, 3);
+    nt.cut(1, 4);
+    nt.cut(1, 5);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let committed_before = nt.peers[&1].raft_log.committed;
+    assert_eq!(nt.peers[&2].raft_log.last_index(), committed_before + 1);
+
+    nt.crash(1);
+    nt.crash(2);
+    nt.recover();
+    nt.assert_durability(committed_before);
+    nt.send(vec![new_message(3, 3, MessageType::MsgHup, 0)]);
+    let leader = 3;
+    assert_eq!(nt.peers[&leader].state, StateRole::Leader);
+    nt.propose_and_commit(leader, b"foo".to_vec());
+
+    nt.restart(1, &cfg, &l);
+    nt.restart(2, &cfg, &l);
+    assert_eq!(nt.peers[&1].raft_log.committed, committed_before);
+    let index = nt.propose_and_commit(leader, b"bar".to_vec());
+    // Let the restarted peers catch up.
+    for _ in 0..cfg.heartbeat_tick * 3 {
+        nt.tick();
+    }
+    for id in 1..=5 {
+        assert_eq!(nt.peers[&id].raft_log.committed, index, "peer {}", id);
+    }
+    nt.assert_durability(index);
+    nt.assert_log_prefix_match(index);
+
+    // Losing a committed entry on a majority is detected.
+    for id in 1..=3 {
+        nt.storage[&id].wl().append(&[empty_entry(99, 2)]).unwrap();
+    }
+    let res = panic::catch_unwind(AssertUnwindSafe(|| nt.assert_durability(index)));
+    assert!(res.is_err());
+}
+
+// test_crash_with_slow_disk ensures a crash only keeps what the peer persisted, even if
+// it learned of a commit index beyond its persisted log.
+#[test]
+fn test_crash_with_slow_disk() {
+    let l = default_logger();
+    let cfg = Network::default_config();
+    let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let persisted = nt.propose_and_commit(1, b"foo".to_vec());
+
+    nt.set_storage_latency(2, StorageOp::Append, 10 * Network::TICK_MICROS);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    // 2 learned that its unpersisted entries are committed.
+    let committed = nt.peers[&2].raft_log.committed;
+    assert!(committed > persisted, "{} <= {}", committed, persisted);
+    assert_eq!(nt.storage[&2].last_index().unwrap(), persisted);
+
+    nt.crash(2);
+    let hs = nt.storage[&2].initial_state().unwrap().hard_state;
+    assert_eq!(hs.term, nt.peers[&1].term);
+    assert!(hs.commit <= persisted, "{:?}", hs);
+    nt.restart(2, &cfg, &l);
+    assert_eq!(nt.peers[&2].raft_log.last_index(), persisted);
+    assert!(nt.peers[&2].raft_log.committed <= persisted);
+
+    for _ in 0..cfg.heartbeat_tick * 3 {
+        nt.tick();
+    }
+    let index = nt.peers[&1].raft_log.committed;
+    assert_eq!(nt.peers[&2].raft_log.committed, index);
+    nt.assert_durability(index);
+    nt.assert_log_prefix_match(index);
+}
+
+// test_split_vote_tie_break ensures that after a split vote, the candidates that are not
+// preferred by the tie break wait for the preferred one to win the next election.
+#[test]
+fn test_split_vote_tie_break() {
+    let l = default_logger();
+    for &(tie_break, wleader) in &[
+        (SplitVoteTieBreak::LowestId, 1),
+        (SplitVoteTieBreak::HighestId, 3),
+    ] {
+        let mut cfg = Network::default_config();
+        cfg.split_vote_tie_break = tie_break;
+        let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+        // All the peers campaign at once and lose.
+        for id in 1..=3 {
+            let m = new_message(id, id, MessageType::MsgHup, 0);
+            nt.peers.get_mut(&id).unwrap().step(m).unwrap();
+        }
+        let msgs = nt.read_messages();
+        nt.send(msgs);
+        for id in 1..=3 {
+            assert_eq!(nt.peers[&id].state, StateRole::Follower);
+            assert_eq!(nt.peers[&id].term, 1);
+        }
+        let elections = nt.election_count();
+        assert_eq!(elections, 3);
+
+        let leader = nt.run_until_leader(4 * cfg.election_tick).unwrap();
+        assert_eq!(leader, wleader, "{:?}", tie_break);
+        assert_eq!(nt.peers[&leader].term, 2, "{:?}", tie_break);
+        assert_eq!(nt.election_count(), elections + 1, "{:?}", tie_break);
+    }
+}
+
+// test_split_vote_tie_break_identical_logs ensures that candidates with identical terms and
+// logs are resolved according to the tie break, with or without pre-vote.
+#[test]
+fn test_split_vote_tie_break_identical_logs() {
+    let l = default_logger();
+    for &pre_vote in &[false, true] {
+        for &(tie_break, wleader) in &[
+            (SplitVoteTieBreak::LowestId, 2),
+            (SplitVoteTieBreak::HighestId, 3),
+        ] {
+            let mut cfg = Network::default_config();
+            cfg.pre_vote = pre_vote;
+            cfg.split_vote_tie_break = tie_break;
+            let mut nt = Network::new_with_config(vec![None, None, None], &cfg, &l);
+            nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+            nt.propose_and_commit(1, b"foo".to_vec());
+
+            // The leader goes away, and the others campaign at once with identical logs.
+            nt.crash(1);
+            for id in 2..=3 {
+                let m = new_message(id, id, MessageType::MsgHup, 0);
+                nt.peers.get_mut(&id).unwrap().step(m).unwrap();
+            }
+            let msgs = nt.read_messages();
+            nt.send(msgs);
+            assert_eq!(nt.peers[&2].term, nt.peers[&3].term);
+            assert_eq!(
+                nt.peers[&2].raft_log.last_index(),
+                nt.peers[&3].raft_log.last_index()
+            );
+            for id in 2..=3 {
+                assert_eq!(nt.peers[&id].state, StateRole::Candidate);
+            }
+
+            let leader = nt.run_until_leader(4 * cfg.election_tick).unwrap();
+            assert_eq!(leader, wleader, "{:?} {}", tie_break, pre_vote);
+        }
+    }
+}
+
+// A black holed follower keeps receiving appends, but the leader marks it as inactive
+// as it never responds.
+#[test]
+fn test_blackhole_follower() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.check_quorum = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    nt.propose_and_commit(1, b"foo".to_vec());
+
+    nt.blackhole(3);
+    let last = nt.propose_and_commit(1, b"bar".to_vec());
+    assert_eq!(nt.peers[&3].raft_log.last_index(), last);
+    for _ in 0..2 * config.election_tick {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert!(!nt.peers[&1].prs().get(3).unwrap().recent_active);
+    assert!(nt.peers[&1].prs().get(3).unwrap().matched < last);
+
+    let last = nt.propose_and_commit(1, b"baz".to_vec());
+    assert_eq!(nt.peers[&3].raft_log.last_index(), last);
+    assert_eq!(nt.peers[&3].raft_log.committed, last);
+}
+
+// Followers replicate entries right away, but can't apply them until the delayed commit
+// index is advertised.
+#[test]
+fn test_commit_advertise_delay() {
+    let l = default_logger();
+    let delay = 3;
+    let mut config = Network::default_config();
+    config.commit_advertise_delay_ticks = delay;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    for _ in 0..delay {
+        nt.tick();
+    }
+    let committed = nt.peers[&1].raft_log.committed;
+    for id in 2..=3 {
+        let p = nt.peers.get_mut(&id).unwrap();
+        assert_eq!(p.raft_log.committed, committed, "peer {}", id);
+        p.commit_apply(committed);
+    }
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let index = nt.peers[&1].raft_log.last_index();
+    assert_eq!(nt.peers[&1].raft_log.committed, index);
+    for id in 2..=3 {
+        let p = &nt.peers[&id];
+        assert_eq!(p.raft_log.last_index(), index, "peer {}", id);
+        assert_eq!(p.raft_log.committed, committed, "peer {}", id);
+        assert!(!p.raft_log.has_next_entries(), "peer {}", id);
+    }
+    for _ in 0..delay - 1 {
+        nt.tick();
+        for id in 2..=3 {
+            assert_eq!(nt.peers[&id].raft_log.committed, committed, "peer {}", id);
+        }
+    }
+    nt.tick();
+    for id in 2..=3 {
+        let p = &nt.peers[&id];
+        assert_eq!(p.raft_log.committed, index, "peer {}", id);
+        assert!(p.raft_log.has_next_entries(), "peer {}", id);
+    }
+}
+
+#[test]
+fn test_last_election_failure() {
+    let l = default_logger();
+    let vote_resp = |from: u64, term: u64, reject: bool| {
+        let mut m = new_message(from, 1, MessageType::MsgRequestVoteResponse, 0);
+        m.term = term;
+        m.reject = reject;
+        m
+    };
+
+    // Rejected by voters that are not ahead of this node, e.g. as they voted already.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    assert_eq!(r.last_election_failure(), None);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    r.step(vote_resp(2, 1, true)).unwrap();
+    r.step(vote_resp(3, 1, true)).unwrap();
+    assert_eq!(r.state, StateRole::Follower);
+    let failure = ElectionFailure {
+        reason: ElectionFailureReason::InsufficientVotes,
+        term: 1,
+        pre_vote: false,
+        granted: 1,
+        rejected: 2,
+    };
+    assert_eq!(r.last_election_failure(), Some(failure));
+
+    // Rejected by a voter that has committed entries this node doesn't have.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    r.step(vote_resp(2, 1, true)).unwrap();
+    let mut m = vote_resp(3, 1, true);
+    m.commit = 3;
+    m.commit_term = 1;
+    r.step(m).unwrap();
+    let failure = ElectionFailure {
+        reason: ElectionFailureReason::LogNotUpToDate,
+        ..failure
+    };
+    assert_eq!(r.last_election_failure(), Some(failure));
+
+    // A pre-vote rejected at a higher term.
+    let mut r = new_test_raft_with_prevote(1, vec![1, 2, 3], 10, 1, new_storage(), true, &l);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    assert_eq!(r.state, StateRole::PreCandidate);
+    let mut m = new_message(2, 1, MessageType::MsgRequestPreVoteResponse, 0);
+    m.term = 5;
+    m.reject = true;
+    r.step(m).unwrap();
+    assert_eq!(r.term, 5);
+    let failure = ElectionFailure {
+        reason: ElectionFailureReason::HigherTerm,
+        term: 1,
+        pre_vote: true,
+        granted: 1,
+        rejected: 0,
+    };
+    assert_eq!(r.last_election_failure(), Some(failure));
+
+    // Another node became leader of the term.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    r.step(vote_resp(2, 1, true)).unwrap();
+    let mut m = new_message(3, 1, MessageType::MsgHeartbeat, 0);
+    m.term = 1;
+    r.step(m).unwrap();
+    assert_eq!(r.leader_id, 3);
+    let failure = ElectionFailure {
+        reason: ElectionFailureReason::LeaderElected,
+        term: 1,
+        pre_vote: false,
+        granted: 1,
+        rejected: 1,
+    };
+    assert_eq!(r.last_election_failure(), Some(failure));
+
+    // No votes received before the election times out.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    while r.term == 1 {
+        r.tick();
+    }
+    assert_eq!(r.state, StateRole::Candidate);
+    let failure = ElectionFailure {
+        reason: ElectionFailureReason::InsufficientVotes,
+        term: 1,
+        pre_vote: false,
+        granted: 1,
+        rejected: 0,
+    };
+    assert_eq!(r.last_election_failure(), Some(failure));
+}
+
+// Appends delivered to a follower in any order end up in the same committed state.
+#[test]
+fn test_send_permuted() {
+    let l = default_logger();
+    let orders = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ];
+    let mut results = vec![];
+    for order in &orders {
+        let mut nt = Network::new(vec![None, None, None], &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        // Replicate the entries to 2 one by one before any of them is acknowledged.
+        for i in 0..3 {
+            let data = format!("data{}", i);
+            let m = new_message_with_entries(
+                1,
+                1,
+                MessageType::MsgPropose,
+                vec![new_entry(0, 0, Some(&data))],
+            );
+            nt.peers.get_mut(&1).unwrap().step(m).unwrap();
+        }
+        let (to_2, rest): (Vec<_>, Vec<_>) = nt
+            .peers
+            .get_mut(&1)
+            .unwrap()
+            .read_messages()
+            .into_iter()
+            .partition(|m| m.to == 2);
+        assert_eq!(to_2.len(), 3, "{:?}", order);
+        let resps = nt.send_permuted(to_2, order);
+        assert!(!resps.is_empty(), "{:?}", order);
+        nt.send(resps);
+        nt.send(rest);
+
+        let committed = nt.peers[&1].raft_log.committed;
+        assert_eq!(committed, nt.peers[&1].raft_log.last_index(), "{:?}", order);
+        nt.assert_log_prefix_match(committed);
+        let ents = nt.peers[&2].raft_log.entries(1, None).unwrap();
+        results.push((committed, ents));
+    }
+    for (order, result) in orders.iter().zip(&results) {
+        assert_eq!(result, &results[0], "{:?}", order);
+    }
+}
+
+// Election timeouts are redrawn on term changes, so the nodes don't keep correlated
+// timeouts after a cluster wide event, unless the redraw is disabled.
+#[test]
+fn test_redraw_election_timeout() {
+    let l = default_logger();
+    let timeouts = |nt: &Network| -> Vec<usize> {
+        (1..=5)
+            .map(|id| Status::new(&nt.peers[&id]).randomized_election_timeout)
+            .collect()
+    };
+    let correlate = |nt: &mut Network| {
+        for id in 1..=5 {
+            nt.peers
+                .get_mut(&id)
+                .unwrap()
+                .set_randomized_election_timeout(15);
+        }
+    };
+
+    let config = Network::default_config();
+    let mut nt = Network::new_with_config(vec![None, None, None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    // A single redraw of 5 nodes from 10 values is identical with a chance of 1e-4.
+    let mut decorrelated = false;
+    for candidate in 2..=5 {
+        correlate(&mut nt);
+        let term = nt.peers[&1].term;
+        nt.send(vec![new_message(
+            candidate,
+            candidate,
+            MessageType::MsgHup,
+            0,
+        )]);
+        assert!(nt.peers[&1].term > term);
+        let ts = timeouts(&nt);
+        for t in &ts {
+            assert!(*t >= config.election_tick && *t < 2 * config.election_tick);
+        }
+        if ts.iter().any(|t| *t != ts[0]) {
+            decorrelated = true;
+            break;
+        }
+    }
+    assert!(decorrelated);
+
+    let mut config = Network::default_config();
+    config.redraw_election_timeout = false;
+    let mut nt = Network::new_with_config(vec![None, None, None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    correlate(&mut nt);
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&2].state, StateRole::Leader);
+    assert_eq!(timeouts(&nt), vec![15; 5]);
+}
+
+#[test]
+fn test_step_down_and_confirm() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    // 3 falls behind, so the leadership is handed over to 2.
+    nt.isolate(3);
+    nt.propose_and_commit(1, b"foo".to_vec());
+    nt.recover();
+    let term = nt.peers[&1].term;
+
+    assert_eq!(nt.step_down_and_confirm(1, 20), Ok(2));
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&1].leader_id, 2);
+    assert!(nt.peers[&2].term > term);
+    let last = nt.propose_and_commit(2, b"bar".to_vec());
+    nt.assert_log_prefix_match(last);
+
+    assert!(nt.step_down_and_confirm(1, 20).is_err());
+}
+
+// A follower that is silent for a single check interval is still reported active within
+// the grace period, while the leader still steps down as soon as its quorum is silent.
+#[test]
+fn test_inactive_grace_ticks() {
+    let l = default_logger();
+    for (grace, silent_ticks, winactive) in &[(0, 14, vec![2]), (15, 14, vec![]), (15, 30, vec![2])]
+    {
+        let mut config = Network::default_config();
+        config.check_quorum = true;
+        config.inactive_grace_ticks = *grace;
+        let mut nt = Network::new_with_config(vec![None, None, None, None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        for _ in 0..8 {
+            nt.tick();
+        }
+        // The responses of 2 are lost across the checks at tick 10 and 20.
+        nt.drop(2, 1, 1.0);
+        for _ in 0..*silent_ticks {
+            nt.tick();
+        }
+        assert_eq!(nt.peers[&1].state, StateRole::Leader);
+        assert_eq!(
+            nt.peers[&1].inactive_peers(),
+            *winactive,
+            "grace {}, silent {}",
+            grace,
+            silent_ticks
+        );
+    }
+
+    // The grace period doesn't keep a leader without an active quorum.
+    let mut config = Network::default_config();
+    config.check_quorum = true;
+    config.inactive_grace_ticks = 15;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.isolate(3);
+    for _ in 0..8 {
+        nt.tick();
+    }
+    nt.drop(2, 1, 1.0);
+    for _ in 0..14 {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+}
+
+#[test]
+fn test_measure_commit_latency() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let last = nt.peers[&1].raft_log.last_index();
+    let latency = nt.measure_commit_latency(1, 100);
+    assert!(latency < Duration::from_secs(10), "{:?}", latency);
+    assert_eq!(nt.peers[&1].raft_log.committed, last + 100);
+    nt.assert_log_prefix_match(last + 100);
+
+    // Slow disks are waited for.
+    nt.set_apply_delay(2, 3);
+    nt.set_apply_delay(3, 3);
+    let last = nt.peers[&1].raft_log.last_index();
+    nt.measure_commit_latency(1, 10);
+    assert_eq!(nt.peers[&1].raft_log.committed, last + 10);
+}
+
+#[test]
+fn test_storage_latency_slow_append() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    // 3 takes 5 ticks to persist entries, so 1 and 2 commit without it.
+    nt.set_storage_latency(3, StorageOp::Append, 5 * Network::TICK_MICROS);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let last_index = nt.peers[&1].raft_log.last_index();
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index);
+    assert_eq!(nt.peers[&2].raft_log.persisted, last_index);
+    assert_eq!(nt.peers[&3].raft_log.last_index(), last_index);
+    assert!(nt.peers[&3].raft_log.persisted < last_index);
+    assert_eq!(nt.peers[&1].prs().get(3).unwrap().matched, last_index - 1);
+
+    for _ in 0..5 {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&3].raft_log.persisted, last_index);
+    assert_eq!(nt.peers[&1].prs().get(3).unwrap().matched, last_index);
+
+    // Slow reads of entries hold back the replication of the leader only.
+    nt.set_storage_latency(3, StorageOp::Append, 0);
+    nt.set_storage_latency(1, StorageOp::Entries, 1);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index);
+    nt.tick();
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index + 1);
+}
+
+#[test]
+fn test_leaderless_ticks() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.pre_vote = true;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&3].leaderless_ticks(), 0);
+
+    // 3 forgets the leader once it campaigns, and can't find one afterwards.
+    nt.isolate(3);
+    let election_timeout = nt.peers[&3].election_timeout() as u64;
+    let mut last = 0;
+    for _ in 0..4 * election_timeout {
+        nt.tick();
+        let ticks = nt.peers[&3].leaderless_ticks();
+        assert!(ticks == 0 || ticks == last + 1, "{} after {}", ticks, last);
+        last = ticks;
+    }
+    assert!(last > 2 * election_timeout as usize, "{}", last);
+    assert_eq!(nt.peers[&1].leaderless_ticks(), 0);
+    assert_eq!(nt.peers[&2].leaderless_ticks(), 0);
+
+    // The counter is reset once 3 hears from the leader again.
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+    assert_eq!(nt.peers[&3].leader_id, 1);
+    assert_eq!(nt.peers[&3].leaderless_ticks(), 0);
+}
+
+#[test]
+fn test_leader_step_down_on_commit_stall() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.max_commit_stall_ticks = 5;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.isolate(1);
+
+    // Without anything to commit, the leader keeps its leadership.
+    for _ in 0..10 {
+        nt.peers.get_mut(&1).unwrap().tick();
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let committed = nt.peers[&1].raft_log.committed;
+    assert!(nt.peers[&1].raft_log.last_index() > committed);
+    for _ in 0..4 {
+        nt.peers.get_mut(&1).unwrap().tick();
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    nt.peers.get_mut(&1).unwrap().tick();
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&1].term, 1);
+    assert_eq!(nt.peers[&1].leader_id, INVALID_ID);
+}
+
+#[test]
+fn test_follower_read_lease() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.check_quorum = true;
+    config.leader_lease_ticks = 2;
+    config.follower_lease_ticks = 3;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    // A new leader doesn't commit until leases of a previous leader have expired.
+    let committed = nt.peers[&1].raft_log.committed;
+    for _ in 0..5 {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&1].raft_log.committed, committed);
+    nt.tick();
+    assert_eq!(nt.peers[&1].raft_log.committed, committed + 1);
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    nt.peers.get_mut(&2).unwrap().request_read_lease().unwrap();
+    let msgs = nt.read_messages();
+    nt.send(msgs);
+    assert!(nt.peers[&2].has_read_lease());
+    assert!(!nt.peers[&3].has_read_lease());
+
+    // Leadership isn't transferred while the lease is held.
+    nt.send(vec![new_message(3, 1, MessageType::MsgTransferLeader, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&1].lead_transferee, None);
+
+    // 2 serves reads locally, without asking the leader.
+    let read_index = |nt: &mut Network, ctx: &str| {
+        let mut m = new_message(2, 2, MessageType::MsgReadIndex, 0);
+        m.set_entries(vec![new_entry(0, 0, Some(ctx))].into());
+        nt.peers.get_mut(&2).unwrap().step(m).unwrap();
+    };
+    read_index(&mut nt, "ctx1");
+    assert!(nt.peers[&2].msgs.is_empty());
+    let last_index = nt.peers[&2].raft_log.last_index();
+    let rs = nt.peers.get_mut(&2).unwrap().read_states.remove(0);
+    assert_eq!((rs.index, rs.request_ctx), (last_index, b"ctx1".to_vec()));
+
+    // While 2 holds the lease, nothing is committed without it.
+    nt.isolate(2);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&3].raft_log.last_index(), last_index + 1);
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index);
+
+    // The lease expires on 2 first, then on the leader.
+    for _ in 0..3 {
+        nt.tick();
+    }
+    assert!(!nt.peers[&2].has_read_lease());
+    read_index(&mut nt, "ctx2");
+    assert!(nt.peers[&2].read_states.is_empty());
+    let msgs = nt.peers.get_mut(&2).unwrap().read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgReadIndex);
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index);
+    for _ in 0..3 {
+        nt.tick();
+    }
+    assert_eq!(nt.peers[&1].raft_log.committed, last_index + 1);
+}
+
+#[test]
+fn test_follower_read_lease_rejected() {
+    let l = default_logger();
+    let mut config = Network::default_config();
+    config.check_quorum = true;
+    config.leader_lease_ticks = 2;
+    config.follower_lease_ticks = 3;
+    let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(
+        nt.peers.get_mut(&1).unwrap().request_read_lease(),
+        Err(Error::ReadIndexDropped)
+    );
+
+    for _ in 0..6 {
+        nt.tick();
+    }
+    assert!(nt.peers[&1].commit_to_current_term());
+
+    // The leader doesn't delegate a lease it doesn't hold itself.
+    for _ in 0..2 {
+        nt.peers.get_mut(&1).unwrap().tick();
+    }
+    let _ = nt.peers.get_mut(&1).unwrap().read_messages();
+    nt.peers.get_mut(&2).unwrap().request_read_lease().unwrap();
+    let msgs = nt.peers.get_mut(&2).unwrap().read_messages();
+    nt.peers.get_mut(&1).unwrap().step(msgs[0].clone()).unwrap();
+    let msgs = nt.peers.get_mut(&1).unwrap().read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgReadLeaseResponse);
+    assert!(msgs[0].reject);
+    nt.peers.get_mut(&2).unwrap().step(msgs[0].clone()).unwrap();
+    assert!(!nt.peers[&2].has_read_lease());
+}
+
+// `test_assert_quiescent` ensures a healthy cluster stops producing messages once the
+// faults are healed, while a message storm is reported.
+#[test]
+fn test_assert_quiescent() {
+    let l = default_logger();
+    let mut nt = Network::new(vec![None, None, None], &l);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.isolate(3);
+    nt.propose_and_commit(1, b"foo".to_vec());
+    nt.recover();
+    for _ in 0..5 {
+        nt.tick();
+    }
+    assert_eq!(nt.assert_quiescent(10), Ok(()));
+
+    // 2 and 3 are misconfigured to take each other as the leader, so a read request
+    // bounces between them forever.
+    nt.peers.get_mut(&2).unwrap().leader_id = 3;
+    nt.peers.get_mut(&3).unwrap().leader_id = 2;
+    let mut m = new_message(2, 2, MessageType::MsgReadIndex, 0);
+    m.set_entries(vec![new_entry(0, 0, Some("ctx"))].into());
+    nt.peers.get_mut(&2).unwrap().step(m).unwrap();
+    let err = nt.assert_quiescent(100).unwrap_err();
+    assert!(err.contains("after 100 rounds"), "{}", err);
+}
+
+// `test_reorder_only` ensures a cluster converges when the network aggressively reorders
+// messages but never loses them.
+#[test]
+fn test_reorder_only() {
+    let l = default_logger();
+    for seed in 0..5 {
+        let mut nt = Network::new(vec![None, None, None, None, None], &l);
+        nt.set_seed(seed);
+        nt.set_reorder_only(true);
+        // Loss rules are not applied while only reordering.
+        nt.drop(1, 2, 1.0);
+        nt.ignore(MessageType::MsgAppendResponse);
+
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        assert_eq!(nt.peers[&1].state, StateRole::Leader, "seed {}", seed);
+        // The appends of all the proposals race with each other.
+        let props = (0..20)
+            .map(|_| new_message(1, 1, MessageType::MsgPropose, 3))
+            .collect();
+        nt.send(props);
+        nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+        let last_index = nt.peers[&1].raft_log.last_index();
+        assert_eq!(last_index, 61, "seed {}", seed);
+        for id in 1..=5 {
+            let p = &nt.peers[&id];
+            assert_eq!(
+                p.raft_log.committed, last_index,
+                "seed {} peer {}",
+                seed, id
+            );
+        }
+        nt.assert_log_prefix_match(last_index);
+    }
+}
+
+// `test_strict_mode_two_leaders` ensures that seeing two leaders in the same term panics
+// under strict mode, and is handled like any other message otherwise.
+#[test]
+fn test_strict_mode_two_leaders() {
+    let l = default_logger();
+    for &strict_mode in &[false, true] {
+        let mut config = Network::default_config();
+        config.strict_mode = strict_mode;
+        let mut nt = Network::new_with_config(vec![None, None, None], &config, &l);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        let term = nt.peers[&1].term;
+
+        // Messages of the known leader are fine.
+        let mut m = new_message(1, 3, MessageType::MsgHeartbeat, 0);
+        m.term = term;
+        nt.peers.get_mut(&3).unwrap().step(m).unwrap();
+
+        // 2 pretends to be the leader of the same term.
+        for &(to, msg_type) in &[(3, MessageType::MsgAppend), (1, MessageType::MsgHeartbeat)] {
+            let mut m = new_message(2, to, msg_type, 0);
+            m.term = term;
+            let peer = nt.peers.get_mut(&to).unwrap();
+            let res = panic::catch_unwind(AssertUnwindSafe(|| peer.step(m)));
+            if strict_mode {
+                let msg = *res.unwrap_err().downcast::<String>().unwrap();
+                let leader = if to == 3 { 1 } else { to };
+                let want = format!("two leaders {} and 2 in term {}", leader, term);
+                assert!(msg.contains(&want), "{}", msg);
+            } else {
+                assert_eq!(res.unwrap(), Ok(()));
+            }
+        }
+        if !strict_mode {
+            assert_eq!(nt.peers[&1].state, StateRole::Leader);
+            assert_eq!(nt.peers[&3].leader_id, 2);
+        }
+    }
+}
+
+// `test_strict_mode_commit_regression` ensures that a leader contradicting committed entries
+// panics under strict mode, and is rejected or ignored otherwise.
+#[test]
+fn test_strict_mode_commit_regression() {
+    let l = default_logger();
+    for &strict_mode in &[false, true] {
+        let mut config = new_test_config(1, 10, 1);
+        config.strict_mode = strict_mode;
+        let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        store
+            .wl()
+            .append(&[empty_entry(1, 1), empty_entry(1, 2), empty_entry(1, 3)])
+            .unwrap();
+        store.wl().set_hardstate(hard_state(2, 3, 0));
+        let mut r = new_test_raft_with_config(&config, store, &l);
+        assert_eq!(r.raft_log.committed, 3);
+
+        // 2 is the leader of the current term, but has other entries at committed indexes.
+        let mut append = new_message_with_entries(
+            2,
+            1,
+            MessageType::MsgAppend,
+            vec![empty_entry(2, 3), empty_entry(2, 4)],
+        );
+        append.term = 2;
+        append.index = 2;
+        append.log_term = 1;
+        append.commit = 4;
+        let mut snapshot = new_message(2, 1, MessageType::MsgSnapshot, 0);
+        snapshot.term = 2;
+        snapshot.set_snapshot(new_snapshot(3, 2, vec![1, 2]));
+
+        for m in [append, snapshot] {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| r.step(m.clone())));
+            if strict_mode {
+                let msg = *res.unwrap_err().downcast::<String>().unwrap();
+                let want = "leader 2 has term 2 at committed index 3 of term 1";
+                assert!(msg.contains(want), "{}", msg);
+            } else {
+                assert!(res.is_ok(), "{:?}", m);
+                assert_eq!(r.raft_log.committed, 3);
+                assert_eq!(r.raft_log.term(3), Ok(1));
+            }
+        }
+
+        // Appends matching the committed entries are fine either way.
+        let mut m = new_message_with_entries(2, 1, MessageType::MsgAppend, vec![empty_entry(2, 4)]);
+        m.term = 2;
+        m.index = 3;
+        m.log_term = 1;
+        m.commit = 4;
+        r.step(m).unwrap();
+        assert_eq!(r.raft_log.committed, 4);
+    }
+}
@@ -175,3 +175,41 @@ fn test_msg_app_flow_control_recv_heartbeat() {
         r.read_messages();
     }
 }
+
+// test_msg_app_flow_control_inflight_limit ensures that a follower with an overridden
+// inflight limit caps its window lower than the others.
+#[test]
+fn test_msg_app_flow_control_inflight_limit() {
+    let l = default_logger();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 5, 1, new_storage(), &l);
+    r.become_candidate();
+    r.become_leader();
+    r.mut_prs().get_mut(2).unwrap().become_replicate();
+    r.mut_prs().get_mut(3).unwrap().become_replicate();
+    r.set_inflight_limit(2, 2);
+
+    for i in 0..5 {
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+            .expect("");
+        let ms = r.read_messages();
+        let to: Vec<u64> = ms.iter().map(|m| m.to).collect();
+        if i < 2 {
+            assert_eq!(to, vec![2, 3], "#{}", i);
+        } else {
+            assert_eq!(to, vec![3], "#{}", i);
+        }
+    }
+    assert!(r.prs().get(2).unwrap().ins.full());
+    assert!(!r.prs().get(3).unwrap().ins.full());
+
+    // Acknowledging an inflight frees a slot of the limited window for one more append.
+    let index = r.prs().get(2).unwrap().next_idx - 1;
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = index - 1;
+    r.step(m).expect("");
+    let ms = r.read_messages();
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0].to, 2);
+    assert_eq!(ms[0].entries.len(), 3);
+    assert!(r.prs().get(2).unwrap().ins.full());
+}
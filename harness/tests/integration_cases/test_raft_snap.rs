@@ -16,8 +16,10 @@
 
 use crate::test_util::*;
 use harness::Network;
+use std::sync::{Arc, Mutex};
+
 use raft::eraftpb::*;
-use raft::{default_logger, Error, ProgressState, INVALID_INDEX};
+use raft::{default_logger, Error, ProgressState, ProgressTransition, StorageError, INVALID_INDEX};
 
 fn testing_snap() -> Snapshot {
     new_snapshot(11, 11, vec![1, 2])
@@ -47,6 +49,56 @@ fn test_sending_snapshot_set_pending_snapshot() {
     assert_eq!(sm.prs().get(2).unwrap().pending_snapshot, 11);
 }
 
+#[test]
+fn test_progress_transitions() {
+    let l = default_logger();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage(), &l);
+    sm.restore(testing_snap());
+    sm.persist();
+
+    sm.become_candidate();
+    sm.become_leader();
+    let transitions = Arc::new(Mutex::new(vec![]));
+    let t = transitions.clone();
+    sm.set_progress_observer(move |pt| t.lock().unwrap().push(*pt));
+
+    // The follower is probed and asks for the compacted entries.
+    sm.mut_prs().get_mut(2).unwrap().next_idx = sm.raft_log.first_index();
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = sm.raft_log.first_index() - 1;
+    m.reject = true;
+    sm.step(m).unwrap();
+    assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Snapshot);
+
+    let mut m = new_message(2, 1, MessageType::MsgSnapStatus, 0);
+    m.reject = false;
+    sm.step(m).unwrap();
+
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = 11;
+    sm.step(m).unwrap();
+
+    sm.step(new_message(2, 1, MessageType::MsgUnreachable, 0))
+        .unwrap();
+    assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Probe);
+
+    let transition = |from, to, index| ProgressTransition {
+        id: 2,
+        from,
+        to,
+        index,
+    };
+    assert_eq!(
+        *transitions.lock().unwrap(),
+        vec![
+            transition(ProgressState::Probe, ProgressState::Snapshot, 11),
+            transition(ProgressState::Snapshot, ProgressState::Probe, 11),
+            transition(ProgressState::Probe, ProgressState::Replicate, 11),
+            transition(ProgressState::Replicate, ProgressState::Probe, 11),
+        ]
+    );
+}
+
 #[test]
 fn test_pending_snapshot_pause_replication() {
     let l = default_logger();
@@ -231,3 +283,46 @@ fn test_request_snapshot() {
     assert_eq!(sm.prs().get(2).unwrap().next_idx, 12);
     assert!(sm.prs().get(2).unwrap().is_paused());
 }
+
+#[test]
+fn test_snapshot_freshness() {
+    let l = default_logger();
+    let mut sm = new_test_raft(2, vec![1, 2], 10, 1, new_storage(), &l);
+    sm.restore(testing_snap());
+    sm.persist();
+    assert_eq!(sm.raft_log.committed, 11);
+
+    let out_of_date = Err(Error::Store(StorageError::SnapshotOutOfDate));
+    for &(index, fresh) in &[(10, false), (11, false), (12, true)] {
+        let snap = new_snapshot(index, 11, vec![1, 2]);
+        let res = sm.check_snapshot_freshness(snap.get_metadata());
+        if fresh {
+            assert_eq!(res, Ok(()), "#{}", index);
+        } else {
+            assert_eq!(res, out_of_date, "#{}", index);
+            assert!(!sm.restore(snap.clone()), "#{}", index);
+            // The receive path rejects it too, and tells the leader the committed index.
+            let mut m = new_message(1, 2, MessageType::MsgSnapshot, 0);
+            m.term = sm.term;
+            m.set_snapshot(snap);
+            assert_eq!(sm.step(m), out_of_date, "#{}", index);
+            let msgs = sm.read_messages();
+            assert_eq!(msgs.len(), 1, "#{}", index);
+            assert_eq!(msgs[0].get_msg_type(), MessageType::MsgAppendResponse);
+            assert_eq!(msgs[0].index, 11, "#{}", index);
+        }
+    }
+
+    // A requested snapshot may be at the committed index.
+    sm.pending_request_snapshot = 11;
+    let snap = new_snapshot(11, 11, vec![1, 2]);
+    assert_eq!(sm.check_snapshot_freshness(snap.get_metadata()), Ok(()));
+    assert!(sm.restore(snap));
+    assert_eq!(sm.pending_request_snapshot, INVALID_INDEX);
+    let snap = new_snapshot(10, 11, vec![1, 2]);
+    sm.pending_request_snapshot = 11;
+    assert_eq!(
+        sm.check_snapshot_freshness(snap.get_metadata()),
+        out_of_date
+    );
+}
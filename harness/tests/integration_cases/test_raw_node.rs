@@ -87,6 +87,21 @@ fn new_raw_node_with_config(
     RawNode::new(&config, storage, logger).unwrap()
 }
 
+/// Creates a raw node on a storage that holds `snap` at index 1 followed by the
+/// committed entries 2..=5 of term 1. Returns the node, its storage and the entries.
+fn new_raw_node_with_log(
+    snap: Snapshot,
+    logger: &Logger,
+) -> (RawNode<MemStorage>, MemStorage, Vec<Entry>) {
+    let s = new_storage();
+    s.wl().apply_snapshot(snap).unwrap();
+    let ents: Vec<_> = (2..=5).map(|i| empty_entry(1, i)).collect();
+    s.wl().append(&ents).unwrap();
+    s.wl().set_hardstate(hard_state(1, 5, 0));
+    let raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), logger);
+    (raw_node, s, ents)
+}
+
 /// Ensures that RawNode::step ignore local message.
 #[test]
 fn test_raw_node_step() {
@@ -1718,3 +1733,1816 @@ impl Storage for IgnoreSizeHintMemStorage {
         self.inner.snapshot(request_index)
     }
 }
+
+/// Ensures that a single-node leader answers read index requests without a heartbeat
+/// round, delivering the read state in the very next `Ready`.
+#[test]
+fn test_raw_node_read_index_single_node() {
+    let l = default_logger();
+    for &option in &[ReadOnlyOption::Safe, ReadOnlyOption::LeaseBased] {
+        let s = new_storage();
+        let mut config = new_test_config(1, 10, 1);
+        config.read_only_option = option;
+        config.check_quorum = true;
+        let mut raw_node = new_raw_node_with_config(vec![1], &config, s.clone(), &l);
+        raw_node.campaign().unwrap();
+
+        // The leader hasn't committed an entry in its term yet, so the read is dropped.
+        raw_node.read_index(b"early".to_vec());
+        let rd = raw_node.ready();
+        assert!(rd.read_states().is_empty());
+        s.wl().append(rd.entries()).unwrap();
+        let _ = raw_node.advance(rd);
+        assert_eq!(raw_node.raft.state, StateRole::Leader);
+
+        let committed = raw_node.raft.raft_log.committed;
+        raw_node.read_index(b"ctx".to_vec());
+        // Nothing is queued waiting for heartbeat acks.
+        assert_eq!(raw_node.raft.pending_read_count(), 0);
+        assert!(raw_node.has_ready());
+        let rd = raw_node.ready();
+        let wrs = vec![ReadState {
+            index: committed,
+            request_ctx: b"ctx".to_vec(),
+        }];
+        assert_eq!(*rd.read_states(), wrs, "{:?}", option);
+        assert!(rd.messages().is_empty());
+        let _ = raw_node.advance(rd);
+        assert!(!raw_node.has_ready());
+    }
+}
+
+/// Tests that only entries of the leader's current term can be committed directly, and
+/// that entries from the previous term commit together with them.
+#[test]
+fn test_raw_node_can_commit_directly() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(1, 1, vec![1, 2, 3]))
+        .unwrap();
+    s.wl()
+        .append(&[empty_entry(1, 2), new_entry(1, 3, SOME_DATA)])
+        .unwrap();
+    s.wl().set_hardstate(hard_state(1, 1, 0));
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert_eq!(raw_node.raft.term, 2);
+    assert_eq!(raw_node.raft.raft_log.last_index(), 4);
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+
+    assert!(!raw_node.can_commit_directly(1));
+    assert!(!raw_node.can_commit_directly(3));
+    assert!(raw_node.can_commit_directly(4));
+    assert!(!raw_node.can_commit_directly(5));
+
+    // A quorum ack for the previous term's entry doesn't commit it.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = 2;
+    m.index = 3;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.raft.raft_log.committed, 1);
+
+    // Committing the current term's entry commits the previous ones as well.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = 2;
+    m.index = 4;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.raft.raft_log.committed, 4);
+
+    // After the next election, entries of term 2 lose direct commit eligibility.
+    raw_node.raft.become_follower(3, INVALID_ID);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert!(!raw_node.can_commit_directly(4));
+    assert!(raw_node.can_commit_directly(5));
+}
+
+/// Tests that a learner applied from a conf change that isn't committed is reported as an
+/// orphan and its removal can be proposed.
+#[test]
+fn test_raw_node_orphan_learner() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+
+    // Node 2 never acks, so the conf change can't commit.
+    let cc = conf_change(ConfChangeType::AddLearnerNode, 3);
+    raw_node.propose_conf_change(vec![], cc.clone()).unwrap();
+    let cc_index = raw_node.raft.pending_conf_index;
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    assert!(raw_node.raft.raft_log.committed < cc_index);
+    // Applying it anyway leaves an orphan learner behind.
+    raw_node.apply_conf_change(&cc).unwrap();
+    assert!(raw_node.is_orphan_learner(3));
+    assert!(!raw_node.is_orphan_learner(1));
+    assert!(!raw_node.is_orphan_learner(2));
+    assert!(!raw_node.is_orphan_learner(4));
+
+    assert!(matches!(
+        raw_node.remove_orphan_learner(2),
+        Err(Error::NotExists { id: 2, .. })
+    ));
+
+    // The removal is proposed after the pending conf change.
+    raw_node.remove_orphan_learner(3).unwrap();
+    assert_eq!(raw_node.raft.pending_conf_index, cc_index + 1);
+    let rd = raw_node.ready();
+    let cc = raw_node.decode_conf_change(&rd.entries()[0]).unwrap();
+    assert_eq!(cc.changes[0].node_id, 3);
+    assert_eq!(cc.changes[0].get_change_type(), ConfChangeType::RemoveNode);
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+
+    // Once the conf change adding it commits, the learner is not an orphan anymore.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = raw_node.raft.term;
+    m.index = cc_index;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.raft.raft_log.committed, cc_index);
+    assert!(!raw_node.is_orphan_learner(3));
+}
+
+/// Tests that persisted entries can be reported in smaller increments than a full `Ready`.
+#[test]
+fn test_raw_node_on_persist_entries_to() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    let ack = |raw_node: &mut RawNode<MemStorage>, index| {
+        let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+        m.term = 2;
+        m.index = index;
+        raw_node.step(m).unwrap();
+    };
+    ack(&mut raw_node, 2);
+    assert_eq!(raw_node.raft.raft_log.committed, 2);
+
+    for _ in 0..3 {
+        raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+    }
+    let rd = raw_node.ready();
+    assert_eq!(rd.entries().len(), 3);
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance_append_async(rd);
+    ack(&mut raw_node, 5);
+    // The leader hasn't persisted the entries itself yet.
+    assert_eq!(raw_node.raft.raft_log.committed, 2);
+
+    raw_node.on_persist_entries_to(3);
+    assert_eq!(raw_node.raft.raft_log.persisted, 3);
+    assert_eq!(raw_node.raft.raft_log.committed, 3);
+    raw_node.on_persist_entries_to(5);
+    assert_eq!(raw_node.raft.raft_log.persisted, 5);
+    assert_eq!(raw_node.raft.raft_log.committed, 5);
+
+    // Entries that haven't been handed back yet can't be marked persisted.
+    raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.on_persist_entries_to(6);
+    assert_eq!(raw_node.raft.raft_log.persisted, 5);
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.raft.raft_log.persisted, 6);
+}
+
+/// Tests that messages for a single peer can be taken out without touching the others.
+#[test]
+fn test_raw_node_take_messages_for() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    raw_node.ping();
+    raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+
+    let msgs = raw_node.take_messages_for(2);
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs.iter().all(|m| m.to == 2));
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgHeartbeat);
+    assert_eq!(msgs[1].get_msg_type(), MessageType::MsgAppend);
+    assert!(raw_node.take_messages_for(2).is_empty());
+    assert!(raw_node.take_messages_for(4).is_empty());
+
+    let rd = raw_node.ready();
+    assert_eq!(rd.messages().len(), 2);
+    assert!(rd.messages().iter().all(|m| m.to == 3));
+}
+
+#[test]
+fn test_raw_node_pending_messages_len() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3, 4], 10, 1, s.clone(), &l);
+    assert_eq!(raw_node.pending_messages_len(), 0);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.pending_messages_len(), 0);
+
+    // Each proposal is appended to the 3 followers.
+    raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+    assert_eq!(raw_node.pending_messages_len(), 3);
+    raw_node.ping();
+    assert_eq!(raw_node.pending_messages_len(), 6);
+
+    assert_eq!(raw_node.take_messages_for(2).len(), 2);
+    assert_eq!(raw_node.pending_messages_len(), 4);
+    let rd = raw_node.ready();
+    assert_eq!(rd.messages().len(), 4);
+    assert_eq!(raw_node.pending_messages_len(), 0);
+}
+
+/// Tests that the log made obsolete by a snapshot is truncated once the snapshot is
+/// persisted, and that appending after it still works.
+#[test]
+fn test_raw_node_post_snapshot_truncate() {
+    let l = default_logger();
+    let (mut raw_node, s, _) = new_raw_node_with_log(new_snapshot(1, 1, vec![1, 2]), &l);
+    assert_eq!(raw_node.raft.raft_log.first_index(), 2);
+    assert_eq!(
+        raw_node.post_snapshot_compact_index(),
+        Err(Error::Store(StorageError::Unavailable))
+    );
+
+    let snapshot = new_snapshot(10, 2, vec![1, 2]);
+    let mut snapshot_msg = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    snapshot_msg.set_term(2);
+    snapshot_msg.set_snapshot(snapshot.clone());
+    raw_node.step(snapshot_msg).unwrap();
+    // The snapshot isn't persisted yet.
+    assert_eq!(
+        raw_node.post_snapshot_truncate(),
+        Err(Error::Store(StorageError::SnapshotTemporarilyUnavailable))
+    );
+
+    let rd = raw_node.ready();
+    assert_eq!(*rd.snapshot(), snapshot);
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    s.wl().apply_snapshot(snapshot).unwrap();
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.post_snapshot_compact_index(), Ok(11));
+    assert_eq!(raw_node.post_snapshot_truncate(), Ok(11));
+    assert_eq!(raw_node.raft.raft_log.first_index(), 11);
+    assert_eq!(s.first_index(), Ok(11));
+
+    let ents: Vec<_> = (11..=13).map(|i| new_entry(2, i, SOME_DATA)).collect();
+    let mut append_msg = new_message_with_entries(2, 1, MessageType::MsgAppend, ents.clone());
+    append_msg.set_term(2);
+    append_msg.set_index(10);
+    append_msg.set_log_term(2);
+    append_msg.set_commit(13);
+    raw_node.step(append_msg).unwrap();
+    let rd = raw_node.ready();
+    assert_eq!(*rd.entries(), ents);
+    s.wl().append(rd.entries()).unwrap();
+    let mut light_rd = raw_node.advance(rd);
+    assert_eq!(light_rd.take_committed_entries(), ents);
+    assert_eq!(raw_node.raft.raft_log.last_index(), 13);
+    raw_node.advance_apply();
+    // Entries after the snapshot are kept.
+    assert_eq!(raw_node.post_snapshot_truncate(), Ok(11));
+    assert_eq!(raw_node.raft.raft_log.last_index(), 13);
+}
+
+/// Tests that leadership is handed to the most caught-up voter.
+#[test]
+fn test_raw_node_transfer_leader_to_best() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut snap = new_snapshot(1, 1, vec![1, 2, 3, 4]);
+    snap.mut_metadata().mut_conf_state().learners = vec![5];
+    s.wl().apply_snapshot(snap).unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3, 4], 10, 1, s.clone(), &l);
+    assert_eq!(raw_node.transfer_leader_to_best(), None);
+
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+    raw_node.propose(vec![], b"somedata".to_vec()).unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.raft.raft_log.last_index(), 4);
+
+    // The learner is the most caught-up, and node 3 and 4 are tied.
+    for &(id, index) in &[(2, 2), (3, 3), (4, 3), (5, 4)] {
+        let mut m = new_message(id, 1, MessageType::MsgAppendResponse, 0);
+        m.term = 2;
+        m.index = index;
+        raw_node.step(m).unwrap();
+    }
+    assert_eq!(raw_node.transfer_leader_to_best(), Some(3));
+    assert_eq!(raw_node.raft.lead_transferee, Some(3));
+
+    // Once node 3 catches up, it's asked to campaign.
+    raw_node.take_messages_for(3);
+    let mut m = new_message(3, 1, MessageType::MsgAppendResponse, 0);
+    m.term = 2;
+    m.index = 4;
+    raw_node.step(m).unwrap();
+    let msgs = raw_node.take_messages_for(3);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgTimeoutNow);
+}
+
+#[test]
+fn test_raw_node_message_stats() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    assert_eq!(raw_node.message_stats(), MessageStats::default());
+
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    let _ = raw_node.advance(rd);
+    let stats = raw_node.message_stats();
+    assert_eq!(stats.sent(MessageType::MsgRequestVote), 2);
+    assert_eq!(stats.received(MessageType::MsgRequestVoteResponse), 0);
+
+    for id in 2..=3 {
+        let mut m = new_message(id, 1, MessageType::MsgRequestVoteResponse, 0);
+        m.term = 2;
+        raw_node.step(m).unwrap();
+    }
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+    let stats = raw_node.message_stats();
+    assert_eq!(stats.received(MessageType::MsgRequestVoteResponse), 2);
+
+    // Local messages are not counted.
+    raw_node.reset_message_stats();
+    raw_node.tick();
+    let msgs = raw_node.take_messages_for(2);
+    assert!(!msgs.is_empty());
+    let stats = raw_node.message_stats();
+    assert_eq!(stats.sent(MessageType::MsgHeartbeat), 1);
+    assert_eq!(stats.sent(MessageType::MsgAppend), 1);
+    assert_eq!(stats.received(MessageType::MsgBeat), 0);
+    assert_eq!(stats.received(MessageType::MsgRequestVoteResponse), 0);
+}
+
+#[test]
+fn test_raw_node_can_commit_now() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut config = new_test_config(1, 10, 1);
+    config.check_quorum = true;
+    let mut raw_node = new_raw_node_with_config(vec![1, 2, 3], &config, s, &l);
+    assert!(!raw_node.can_commit_now());
+
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    // Nothing is heard from the followers yet.
+    assert!(!raw_node.can_commit_now());
+
+    let mut m = new_message(2, 1, MessageType::MsgHeartbeatResponse, 0);
+    m.term = raw_node.raft.term;
+    raw_node.step(m).unwrap();
+    assert!(raw_node.can_commit_now());
+
+    // The quorum check clears the marks, 3 alone is not enough.
+    for _ in 0..10 {
+        raw_node.tick();
+    }
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+    assert!(!raw_node.can_commit_now());
+    let mut m = new_message(3, 1, MessageType::MsgHeartbeatResponse, 0);
+    m.term = raw_node.raft.term;
+    raw_node.step(m).unwrap();
+    assert!(raw_node.can_commit_now());
+
+    // Both followers are isolated, the leader steps down.
+    for _ in 0..20 {
+        raw_node.tick();
+    }
+    assert_ne!(raw_node.raft.state, StateRole::Leader);
+    assert!(!raw_node.can_commit_now());
+}
+
+#[test]
+fn test_raw_node_decode_conf_change() {
+    let l = default_logger();
+    let s = new_storage();
+    let raw_node = new_raw_node(1, vec![1], 10, 1, s, &l);
+
+    let cc = conf_change(ConfChangeType::AddLearnerNode, 2);
+    let mut v1 = new_entry(1, 2, None);
+    v1.set_entry_type(EntryType::EntryConfChange);
+    v1.data = cc.write_to_bytes().unwrap().into();
+    let mut v2 = new_entry(1, 3, None);
+    v2.set_entry_type(EntryType::EntryConfChangeV2);
+    v2.data = cc.as_v2().write_to_bytes().unwrap().into();
+
+    let cc_v1 = raw_node.decode_conf_change(&v1).unwrap();
+    let cc_v2 = raw_node.decode_conf_change(&v2).unwrap();
+    assert_eq!(cc_v1, cc_v2);
+
+    // Both result in the same configuration.
+    let mut states = vec![];
+    for cc in &[cc_v1, cc_v2] {
+        let mut raw_node = new_raw_node(1, vec![1], 10, 1, new_storage(), &l);
+        states.push(raw_node.apply_conf_change(cc).unwrap());
+    }
+    assert_eq!(states[0], states[1]);
+    assert_eq!(states[0].learners, vec![2]);
+
+    let e = new_entry(1, 4, SOME_DATA);
+    assert!(matches!(
+        raw_node.decode_conf_change(&e),
+        Err(Error::ConfChangeError(_))
+    ));
+    let mut garbage = new_entry(1, 5, SOME_DATA);
+    garbage.set_entry_type(EntryType::EntryConfChangeV2);
+    assert!(raw_node.decode_conf_change(&garbage).is_err());
+}
+
+#[test]
+fn test_raw_node_compact_unapplied() {
+    let l = default_logger();
+    let (mut raw_node, s, _) = new_raw_node_with_log(new_snapshot(1, 1, vec![1, 2]), &l);
+
+    let rd = raw_node.ready();
+    assert_eq!(rd.committed_entries().len(), 4);
+    let _ = raw_node.advance_append(rd);
+    // None of the handed out entries are applied yet.
+    assert_eq!(
+        raw_node.check_compact(4),
+        Err(Error::CompactUnapplied {
+            index: 4,
+            applied: 1
+        })
+    );
+    assert_eq!(
+        raw_node.compact(4),
+        Err(Error::CompactUnapplied {
+            index: 4,
+            applied: 1
+        })
+    );
+    assert_eq!(s.first_index(), Ok(2));
+    raw_node.compact(2).unwrap();
+
+    raw_node.advance_apply_to(3);
+    assert_eq!(raw_node.check_compact(4), Ok(()));
+    raw_node.compact(4).unwrap();
+    assert_eq!(s.first_index(), Ok(4));
+    assert_eq!(
+        raw_node.compact(5),
+        Err(Error::CompactUnapplied {
+            index: 5,
+            applied: 3
+        })
+    );
+
+    raw_node.advance_apply();
+    raw_node.compact(5).unwrap();
+    assert_eq!(s.first_index(), Ok(5));
+}
+
+#[test]
+fn test_raw_node_term_of() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(3, 2, vec![1, 2]))
+        .unwrap();
+    let ents = vec![empty_entry(2, 4), empty_entry(3, 5)];
+    s.wl().append(&ents).unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    // The empty entry of the leader is unstable.
+    assert_eq!(raw_node.raft.raft_log.last_index(), 6);
+
+    assert_eq!(
+        raw_node.term_of(2),
+        Err(Error::Store(StorageError::Compacted))
+    );
+    let term = raw_node.raft.term;
+    for &(index, term) in &[(3, 2), (4, 2), (5, 3), (6, term)] {
+        assert_eq!(raw_node.term_of(index), Ok(term), "index {}", index);
+    }
+    assert_eq!(
+        raw_node.term_of(7),
+        Err(Error::Store(StorageError::Unavailable))
+    );
+}
+
+#[test]
+fn test_raw_node_snapshot_applied() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), &l);
+
+    let snapshot = new_snapshot(10, 2, vec![1, 2]);
+    let mut snapshot_msg = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    snapshot_msg.set_term(2);
+    snapshot_msg.set_snapshot(snapshot.clone());
+    raw_node.step(snapshot_msg).unwrap();
+
+    // The snapshot is saved, but applying it to the state machine is deferred.
+    let rd = raw_node.ready();
+    assert_eq!(*rd.snapshot(), snapshot);
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    s.wl().apply_snapshot(snapshot).unwrap();
+    let _ = raw_node.take_messages_for(2);
+    raw_node.advance_append_async(rd);
+    assert_eq!(
+        raw_node.snapshot_applied(9),
+        Err(Error::Store(StorageError::SnapshotOutOfDate))
+    );
+    let unavailable = Err(Error::Store(StorageError::SnapshotTemporarilyUnavailable));
+    assert_eq!(raw_node.post_snapshot_truncate(), unavailable);
+    assert_eq!(raw_node.compact(1), unavailable.map(|_: u64| ()));
+
+    // Entries committed in the meantime are withheld until the snapshot is applied.
+    let ents: Vec<_> = (11..=12).map(|i| new_entry(2, i, SOME_DATA)).collect();
+    let mut append_msg = new_message_with_entries(2, 1, MessageType::MsgAppend, ents.clone());
+    append_msg.set_term(2);
+    append_msg.set_index(10);
+    append_msg.set_log_term(2);
+    append_msg.set_commit(12);
+    raw_node.step(append_msg).unwrap();
+    let rd = raw_node.ready();
+    assert_eq!(*rd.entries(), ents);
+    assert!(rd.committed_entries().is_empty());
+    s.wl().append(rd.entries()).unwrap();
+    let number = rd.number();
+    raw_node.advance_append_async(rd);
+
+    raw_node.snapshot_applied(10).unwrap();
+    assert_eq!(raw_node.raft.raft_log.persisted, 10);
+    raw_node.on_persist_ready(number);
+    let rd = raw_node.ready();
+    assert_eq!(*rd.committed_entries(), ents);
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.raft.raft_log.applied, 12);
+    assert_eq!(raw_node.post_snapshot_truncate(), Ok(11));
+}
+
+#[test]
+fn test_raw_node_plan_membership_change() {
+    let l = default_logger();
+    let single = |id, ty| new_conf_change_single(id, ty);
+    let tests = vec![
+        // add only
+        (
+            vec![1, 2, 3],
+            vec![4],
+            vec![single(4, ConfChangeType::AddLearnerNode)],
+            false,
+        ),
+        (
+            vec![1, 2, 3, 4],
+            vec![],
+            vec![single(4, ConfChangeType::AddNode)],
+            false,
+        ),
+        // remove only
+        (
+            vec![1, 2],
+            vec![],
+            vec![single(3, ConfChangeType::RemoveNode)],
+            false,
+        ),
+        (
+            vec![1],
+            vec![],
+            vec![
+                single(2, ConfChangeType::RemoveNode),
+                single(3, ConfChangeType::RemoveNode),
+            ],
+            true,
+        ),
+        // replace
+        (
+            vec![1, 2, 4],
+            vec![],
+            vec![
+                single(4, ConfChangeType::AddNode),
+                single(3, ConfChangeType::RemoveNode),
+            ],
+            true,
+        ),
+        // demote
+        (
+            vec![1, 2],
+            vec![3],
+            vec![single(3, ConfChangeType::AddLearnerNode)],
+            false,
+        ),
+        // no change
+        (vec![1, 2, 3], vec![], vec![], false),
+    ];
+    for (i, (voters, learners, wchanges, wjoint)) in tests.into_iter().enumerate() {
+        let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+        let cc = raw_node.plan_membership_change(&voters, &learners);
+        assert_eq!(cc.get_changes(), &wchanges[..], "#{}", i);
+        if wchanges.is_empty() {
+            continue;
+        }
+
+        // Applying the change results in the target membership.
+        let mut cs = raw_node.apply_conf_change(&cc).unwrap();
+        assert_eq!(!cs.voters_outgoing.is_empty(), wjoint, "#{}", i);
+        if wjoint {
+            cs = raw_node
+                .apply_conf_change(&ConfChangeV2::default())
+                .unwrap();
+        }
+        cs.voters.sort_unstable();
+        assert_eq!(cs.voters, voters, "#{}", i);
+        assert_eq!(cs.learners, learners, "#{}", i);
+        let cc = raw_node.plan_membership_change(&voters, &learners);
+        assert!(cc.get_changes().is_empty(), "#{}", i);
+    }
+}
+
+#[test]
+fn test_raw_node_propose_if() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+
+    let last = (
+        raw_node.raft.raft_log.last_index(),
+        raw_node.raft.raft_log.last_term(),
+    );
+    raw_node.propose_if(last, vec![], b"a".to_vec()).unwrap();
+    let appended = (last.0 + 1, last.1);
+    assert_eq!(raw_node.raft.raft_log.last_index(), appended.0);
+
+    // The log moved on, so proposing against the old last entry fails.
+    for expected in &[
+        last,
+        (appended.0, appended.1 + 1),
+        (appended.0 + 1, appended.1),
+    ] {
+        assert_eq!(
+            raw_node.propose_if(*expected, vec![], b"b".to_vec()),
+            Err(Error::LastEntryMismatch {
+                expected: *expected,
+                actual: appended,
+            })
+        );
+    }
+    assert_eq!(raw_node.raft.raft_log.last_index(), appended.0);
+    raw_node
+        .propose_if(appended, vec![], b"b".to_vec())
+        .unwrap();
+    let ents = raw_node.raft.raft_log.entries(appended.0, None).unwrap();
+    let data: Vec<&[u8]> = ents.iter().map(|e| e.get_data()).collect();
+    assert_eq!(data, vec![b"a", b"b"]);
+
+    // Only the leader knows the last entry of the log that will be appended to.
+    let mut follower = new_raw_node(2, vec![1, 2], 10, 1, new_storage(), &l);
+    let last = (
+        follower.raft.raft_log.last_index(),
+        follower.raft.raft_log.last_term(),
+    );
+    assert_eq!(
+        follower.propose_if(last, vec![], b"a".to_vec()),
+        Err(Error::ProposalDropped)
+    );
+}
+
+#[test]
+fn test_raw_node_validate_propose() {
+    let l = default_logger();
+    let config = &Config {
+        id: 1,
+        max_uncommitted_size: 12,
+        ..Config::default()
+    };
+    let s = new_storage();
+    let mut raw_node = new_raw_node_with_config(vec![1], config, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    s.wl().append(rd.entries()).unwrap();
+    let _ = raw_node.advance(rd);
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+
+    let data = b"hello world!";
+    let last_index = raw_node.raft.raft_log.last_index();
+    assert_eq!(raw_node.validate_propose(b"", data), Ok(()));
+    assert_eq!(raw_node.validate_propose(b"", data), Ok(()));
+    // Nothing is appended.
+    assert_eq!(raw_node.raft.raft_log.last_index(), last_index);
+
+    raw_node.propose(vec![], data.to_vec()).unwrap();
+    // Exceeds the max uncommitted size like proposing it would.
+    assert_eq!(
+        raw_node.validate_propose(b"", data),
+        Err(Error::ProposalDropped)
+    );
+    assert_eq!(
+        raw_node.propose(vec![], data.to_vec()),
+        Err(Error::ProposalDropped)
+    );
+    assert_eq!(raw_node.validate_propose(b"ctx", b""), Ok(()));
+
+    // A follower can't tell whether the leader accepts the proposal.
+    let mut follower = new_raw_node(2, vec![1, 2], 10, 1, new_storage(), &l);
+    assert_eq!(
+        follower.validate_propose(b"", data),
+        Err(Error::ProposalDropped)
+    );
+    let mut m = new_message(1, 2, MessageType::MsgHeartbeat, 0);
+    m.term = 1;
+    follower.step(m).unwrap();
+    assert_eq!(follower.raft.leader_id, 1);
+    assert_eq!(
+        follower.validate_propose(b"", data),
+        Err(Error::ProposalDropped)
+    );
+}
+
+#[test]
+fn test_raw_node_entries_from() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(10, 1, vec![1, 2]))
+        .unwrap();
+    let ents: Vec<_> = (11..=200).map(|i| new_entry(1, i, SOME_DATA)).collect();
+    s.wl().append(&ents).unwrap();
+    let raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    let size = u64::from(ents[0].compute_size());
+
+    let mut tests = vec![
+        // at least one entry
+        (11, 0, 1),
+        (11, size - 1, 1),
+        (11, size, 1),
+        // limited by the budget
+        (11, size * 3, 3),
+        (11, size * 3 + 1, 3),
+        (11, size * 100, 100),
+        // limited by the log
+        (150, NO_LIMIT, 51),
+        (200, 0, 1),
+        (201, NO_LIMIT, 0),
+        // compacted
+        (10, NO_LIMIT, 0),
+    ];
+    for (i, (lo, max_size, wlen)) in tests.drain(..).enumerate() {
+        let got: Vec<_> = raw_node.entries_from(lo, max_size).collect();
+        assert_eq!(got.len(), wlen, "#{}", i);
+        let want = ents
+            .iter()
+            .skip_while(|e| e.index < lo)
+            .take(wlen)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(got, want, "#{}", i);
+    }
+
+    // The iteration stops at the first entry beyond the budget.
+    let big = new_entry(1, 201, Some(&"a".repeat(100)));
+    let s = raw_node.raft.raft_log.store.clone();
+    s.wl().append(&[big]).unwrap();
+    let raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    let got: Vec<_> = raw_node.entries_from(199, size * 3).collect();
+    assert_eq!(got.len(), 2);
+}
+
+#[test]
+fn test_raw_node_apply_backlog() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl().apply_snapshot(new_snapshot(1, 1, vec![1])).unwrap();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(raw_node.apply_backlog(), 1);
+    raw_node.advance_apply();
+    assert_eq!(raw_node.apply_backlog(), 0);
+
+    // Commit faster than applying.
+    let mut applied = raw_node.raft.raft_log.applied;
+    for i in 1..=3 {
+        for _ in 0..2 {
+            raw_node.propose(vec![], b"foo".to_vec()).unwrap();
+        }
+        let rd = raw_node.ready();
+        s.wl().append(rd.entries()).unwrap();
+        raw_node.advance_append(rd);
+        assert_eq!(raw_node.apply_backlog(), 2 * i);
+    }
+    while raw_node.apply_backlog() > 0 {
+        let backlog = raw_node.apply_backlog();
+        applied += 1;
+        raw_node.advance_apply_to(applied);
+        assert_eq!(raw_node.apply_backlog(), backlog - 1);
+    }
+
+    // Entries in a snapshot are applied at once.
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(1, 1, vec![1, 2]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    let mut snap = new_snapshot(10, 2, vec![1, 2]);
+    snap.mut_metadata().term = 2;
+    let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    m.term = 2;
+    m.set_snapshot(snap);
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.raft.raft_log.committed, 10);
+    assert_eq!(raw_node.apply_backlog(), 0);
+}
+
+/// Tests that `hard_state` reflects votes and commits, and matches what `Ready` asks to persist.
+#[test]
+fn test_raw_node_hard_state() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(1, 1, vec![1, 2, 3]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    assert_eq!(raw_node.hard_state(), hard_state(1, 1, 0));
+
+    // Vote for another candidate.
+    let mut m = new_message(2, 1, MessageType::MsgRequestVote, 0);
+    m.term = 2;
+    m.index = 1;
+    m.log_term = 1;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.hard_state(), hard_state(2, 1, 2));
+    let rd = raw_node.ready();
+    assert_eq!(rd.hs(), Some(&raw_node.hard_state()));
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    raw_node.advance(rd);
+
+    // Receive and commit an entry from the new leader.
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.term = 2;
+    m.index = 1;
+    m.log_term = 1;
+    m.commit = 2;
+    m.entries = vec![empty_entry(2, 2)].into();
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.hard_state(), hard_state(2, 2, 2));
+    let rd = raw_node.ready();
+    assert_eq!(rd.hs(), Some(&raw_node.hard_state()));
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    assert_eq!(s.rl().hard_state(), &raw_node.hard_state());
+}
+
+/// Tests that `in_joint_config` reports whether the configuration is joint.
+#[test]
+fn test_raw_node_in_joint_config() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s, &l);
+    assert!(!raw_node.in_joint_config());
+
+    // A simple change doesn't enter a joint configuration.
+    let cc = conf_change(ConfChangeType::AddLearnerNode, 2);
+    raw_node.apply_conf_change(&cc).unwrap();
+    assert!(!raw_node.in_joint_config());
+
+    let mut cc = conf_change_v2(vec![
+        new_conf_change_single(2, ConfChangeType::AddNode),
+        new_conf_change_single(3, ConfChangeType::AddNode),
+    ]);
+    cc.set_transition(ConfChangeTransition::Explicit);
+    let cs = raw_node.apply_conf_change(&cc).unwrap();
+    assert_eq!(
+        cs,
+        conf_state_v2(vec![1, 2, 3], vec![], vec![1], vec![], false)
+    );
+    assert!(raw_node.in_joint_config());
+
+    let cs = raw_node
+        .apply_conf_change(&ConfChangeV2::default())
+        .unwrap();
+    assert_eq!(cs, conf_state(vec![1, 2, 3], vec![]));
+    assert!(!raw_node.in_joint_config());
+}
+
+/// Tests that `joint_quorum_status` reports which half of a joint configuration lacks a
+/// quorum of active peers.
+#[test]
+fn test_raw_node_joint_quorum_status() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s, &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    let set_active = |raw_node: &mut RawNode<MemStorage>, ids: &[u64], active: bool| {
+        for id in ids {
+            raw_node.raft.mut_prs().get_mut(*id).unwrap().recent_active = active;
+        }
+    };
+    // No follower has responded to the new leader yet.
+    assert_eq!(raw_node.joint_quorum_status(), (false, true));
+    set_active(&mut raw_node, &[2], true);
+    assert_eq!(raw_node.joint_quorum_status(), (true, true));
+
+    let mut cc = conf_change_v2(vec![
+        new_conf_change_single(2, ConfChangeType::RemoveNode),
+        new_conf_change_single(3, ConfChangeType::RemoveNode),
+        new_conf_change_single(4, ConfChangeType::AddNode),
+        new_conf_change_single(5, ConfChangeType::AddNode),
+    ]);
+    cc.set_transition(ConfChangeTransition::Explicit);
+    raw_node.apply_conf_change(&cc).unwrap();
+    assert!(raw_node.in_joint_config());
+    assert_eq!(raw_node.joint_quorum_status(), (true, true));
+
+    // The new peers haven't responded yet.
+    set_active(&mut raw_node, &[4, 5], false);
+    assert_eq!(raw_node.joint_quorum_status(), (false, true));
+    set_active(&mut raw_node, &[4], true);
+    assert_eq!(raw_node.joint_quorum_status(), (true, true));
+    // The old peers are gone.
+    set_active(&mut raw_node, &[2, 3], false);
+    assert_eq!(raw_node.joint_quorum_status(), (true, false));
+
+    // Once leaving the joint configuration, only the incoming half matters.
+    raw_node
+        .apply_conf_change(&ConfChangeV2::default())
+        .unwrap();
+    assert!(!raw_node.in_joint_config());
+    assert_eq!(raw_node.joint_quorum_status(), (true, true));
+}
+
+/// Tests that `role_of` reports the roles of the peers across a joint configuration.
+#[test]
+fn test_raw_node_role_of() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut snap = new_snapshot(1, 1, vec![1, 2, 3]);
+    snap.mut_metadata().mut_conf_state().mut_learners().push(4);
+    s.wl().apply_snapshot(snap).unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s, &l);
+    let roles = |raw_node: &RawNode<MemStorage>| -> Vec<_> {
+        (1..=6).map(|id| raw_node.role_of(id)).collect()
+    };
+    let (v, le) = (Some(PeerRole::Voter), Some(PeerRole::Learner));
+    assert_eq!(roles(&raw_node), vec![v, v, v, le, None, None]);
+
+    // Demote 3, promote 4 and add 5 as a learner.
+    let mut cc = conf_change_v2(vec![
+        new_conf_change_single(3, ConfChangeType::AddLearnerNode),
+        new_conf_change_single(4, ConfChangeType::AddNode),
+        new_conf_change_single(5, ConfChangeType::AddLearnerNode),
+    ]);
+    cc.set_transition(ConfChangeTransition::Explicit);
+    raw_node.apply_conf_change(&cc).unwrap();
+    assert!(raw_node.in_joint_config());
+    // 3 still votes in the outgoing configuration.
+    assert_eq!(roles(&raw_node), vec![v, v, v, v, le, None]);
+
+    raw_node
+        .apply_conf_change(&ConfChangeV2::default())
+        .unwrap();
+    assert_eq!(roles(&raw_node), vec![v, v, le, v, le, None]);
+}
+
+/// Tests that `recompute_commit` advances the commit index from injected progress.
+#[test]
+fn test_raw_node_recompute_commit() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    for _ in 0..3 {
+        raw_node.propose(vec![], b"foo".to_vec()).unwrap();
+    }
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    let committed = raw_node.raft.raft_log.committed;
+    let last_index = raw_node.raft.raft_log.last_index();
+    assert_eq!(raw_node.recompute_commit(), committed);
+
+    let pr = raw_node.raft.mut_prs().get_mut(2).unwrap();
+    pr.matched = last_index - 1;
+    assert_eq!(raw_node.recompute_commit(), last_index - 1);
+    assert_eq!(raw_node.raft.raft_log.committed, last_index - 1);
+    let pr = raw_node.raft.mut_prs().get_mut(3).unwrap();
+    pr.matched = last_index;
+    assert_eq!(raw_node.recompute_commit(), last_index);
+
+    // Followers don't advance the commit index on their own.
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, new_storage(), &l);
+    let committed = raw_node.raft.raft_log.committed;
+    let last_index = raw_node.raft.raft_log.last_index();
+    for id in 2..=3 {
+        raw_node.raft.mut_prs().get_mut(id).unwrap().matched = last_index;
+    }
+    assert_eq!(raw_node.recompute_commit(), committed);
+}
+
+/// Tests that a newer snapshot supersedes the pending one while an older one is rejected.
+#[test]
+fn test_raw_node_snapshot_while_pending() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(1, 1, vec![1, 2]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), &l);
+    let snap_msg = |index: u64| {
+        let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+        m.term = 2;
+        m.set_snapshot(new_snapshot(index, 2, vec![1, 2]));
+        m
+    };
+    let pending_index = |raw_node: &RawNode<MemStorage>| {
+        raw_node
+            .raft
+            .raft_log
+            .unstable
+            .snapshot
+            .as_ref()
+            .map(|s| s.get_metadata().index)
+    };
+
+    raw_node.step(snap_msg(10)).unwrap();
+    assert_eq!(pending_index(&raw_node), Some(10));
+
+    assert_eq!(
+        raw_node.step(snap_msg(5)),
+        Err(Error::Store(StorageError::SnapshotOutOfDate))
+    );
+    assert_eq!(pending_index(&raw_node), Some(10));
+    let msgs = raw_node.raft.msgs.clone();
+    assert_eq!(msgs.last().unwrap().index, 10);
+
+    raw_node.step(snap_msg(20)).unwrap();
+    assert_eq!(pending_index(&raw_node), Some(20));
+    assert_eq!(raw_node.raft.raft_log.committed, 20);
+
+    let rd = raw_node.ready();
+    assert_eq!(rd.snapshot().get_metadata().index, 20);
+    s.wl().apply_snapshot(rd.snapshot().clone()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(pending_index(&raw_node), None);
+    assert_eq!(s.first_index().unwrap(), 21);
+}
+
+/// Tests that `peers_behind` lists the peers whose matched index is below the given index.
+#[test]
+fn test_raw_node_peers_behind() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3, 4], 10, 1, s.clone(), &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    raw_node.raft.mut_prs().get_mut(3).unwrap().matched = 2;
+    raw_node.raft.mut_prs().get_mut(4).unwrap().matched = 1;
+    // The empty entry of the leader at index 2 is not persisted yet.
+    assert_eq!(raw_node.peers_behind(1), vec![2]);
+    assert_eq!(raw_node.peers_behind(2), vec![1, 2, 4]);
+
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(raw_node.peers_behind(0), Vec::<u64>::new());
+    assert_eq!(raw_node.peers_behind(2), vec![2, 4]);
+    assert_eq!(raw_node.peers_behind(3), vec![1, 2, 3, 4]);
+}
+
+/// Tests that `unstable_size_bytes` tracks the unstable entries and snapshot.
+#[test]
+fn test_raw_node_unstable_size_bytes() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(raw_node.unstable_size_bytes(), 0);
+
+    let mut size = 0;
+    for i in 1..=3 {
+        raw_node.propose(vec![], vec![b'x'; 100 * i]).unwrap();
+        let e = raw_node.raft.raft_log.unstable.entries.last().unwrap();
+        assert_eq!(e.data.len(), 100 * i);
+        size += u64::from(e.compute_size());
+        assert_eq!(raw_node.unstable_size_bytes(), size);
+    }
+    assert!(size > 600);
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(raw_node.unstable_size_bytes(), 0);
+
+    // An unstable snapshot counts too.
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, new_storage(), &l);
+    let snap = new_snapshot(10, 2, vec![1, 2]);
+    let snap_size = u64::from(snap.compute_size());
+    let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    m.term = 2;
+    m.set_snapshot(snap);
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.unstable_size_bytes(), snap_size);
+}
+
+/// Tests that `committed_entry` returns the entry at the commit index.
+#[test]
+fn test_raw_node_committed_entry() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    // The entry at the commit index is in the initial snapshot.
+    assert_eq!(
+        raw_node.committed_entry(),
+        Err(Error::Store(StorageError::Compacted))
+    );
+
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    let entry = raw_node.committed_entry().unwrap();
+    assert_eq!(entry.index, 2);
+    assert!(entry.data.is_empty());
+    for data in &[b"foo", b"bar"] {
+        raw_node.propose(vec![], data.to_vec()).unwrap();
+        // Not committed before it's persisted.
+        let entry = raw_node.committed_entry().unwrap();
+        assert_ne!(entry.data, data.to_vec());
+        let rd = raw_node.ready();
+        s.wl().append(rd.entries()).unwrap();
+        raw_node.advance(rd);
+        let entry = raw_node.committed_entry().unwrap();
+        assert_eq!(entry.index, raw_node.raft.raft_log.committed);
+        assert_eq!(entry.term, raw_node.raft.term);
+        assert_eq!(entry.data, data.to_vec());
+    }
+}
+
+/// Tests that `become_follower_at` forces follower state and never goes back in term.
+#[test]
+fn test_raw_node_become_follower_at() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s, &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert_eq!(raw_node.raft.term, 2);
+    assert_eq!(raw_node.raft.vote, 1);
+
+    // Stepping down in the same term keeps the vote.
+    raw_node.become_follower_at(2, INVALID_ID).unwrap();
+    assert_eq!(raw_node.raft.state, StateRole::Follower);
+    assert_eq!(raw_node.raft.leader_id, INVALID_ID);
+    assert_eq!(raw_node.raft.hard_state(), hard_state(2, 1, 1));
+
+    raw_node.become_follower_at(5, 3).unwrap();
+    assert_eq!(raw_node.raft.state, StateRole::Follower);
+    assert_eq!(raw_node.raft.leader_id, 3);
+    assert_eq!(raw_node.raft.hard_state(), hard_state(5, 1, 0));
+
+    assert_eq!(
+        raw_node.become_follower_at(4, 2),
+        Err(Error::StaleTerm {
+            term: 4,
+            current: 5
+        })
+    );
+    assert_eq!(raw_node.raft.term, 5);
+    assert_eq!(raw_node.raft.leader_id, 3);
+}
+
+#[test]
+fn test_raw_node_pending_snapshot_for() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(11, 11, vec![1, 2]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert_eq!(raw_node.pending_snapshot_for(2), None);
+
+    // Force node 2 to need a snapshot.
+    let first_index = raw_node.raft.raft_log.first_index();
+    raw_node.raft.mut_prs().get_mut(2).unwrap().next_idx = first_index;
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = first_index - 1;
+    m.reject = true;
+    raw_node.step(m).unwrap();
+
+    let meta = raw_node.pending_snapshot_for(2).unwrap();
+    assert_eq!((meta.index, meta.term), (11, 11));
+    assert_eq!(meta.get_conf_state().voters, vec![1, 2]);
+    assert_eq!(raw_node.pending_snapshot_for(1), None);
+    assert_eq!(raw_node.pending_snapshot_for(3), None);
+
+    // Once the snapshot is applied, nothing is pending anymore.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = raw_node.raft.term;
+    m.index = 11;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.pending_snapshot_for(2), None);
+}
+
+/// Tests that a follower whose snapshot never completes is reported once it has been in
+/// snapshot state for the threshold.
+#[test]
+fn test_raw_node_stuck_snapshot_peers() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(11, 11, vec![1, 2, 3]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s, &l);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert!(raw_node.stuck_snapshot_peers(0).is_empty());
+
+    // Force node 2 to need a snapshot, which is never delivered.
+    let first_index = raw_node.raft.raft_log.first_index();
+    raw_node.raft.mut_prs().get_mut(2).unwrap().next_idx = first_index;
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.index = first_index - 1;
+    m.reject = true;
+    raw_node.step(m).unwrap();
+    assert_eq!(
+        raw_node.raft.prs().get(2).unwrap().state,
+        ProgressState::Snapshot
+    );
+    let _ = raw_node.raft.msgs.drain(..);
+
+    for _ in 0..4 {
+        raw_node.tick();
+        assert!(raw_node.stuck_snapshot_peers(5).is_empty());
+    }
+    raw_node.tick();
+    assert_eq!(raw_node.stuck_snapshot_peers(5), vec![2]);
+    assert_eq!(raw_node.stuck_snapshot_peers(10), Vec::<u64>::new());
+
+    // Reporting the snapshot as failed lets the leader probe the follower again.
+    raw_node.report_snapshot(2, SnapshotStatus::Failure);
+    assert!(raw_node.stuck_snapshot_peers(0).is_empty());
+
+    // Only the leader tracks snapshots.
+    raw_node.raft.become_follower(raw_node.raft.term + 1, 3);
+    assert!(raw_node.stuck_snapshot_peers(0).is_empty());
+}
+
+/// Tests that `safe_compact_index` keeps the entries lagging voters need, and
+/// `safe_compact_index_including_learners` the ones of lagging learners too.
+#[test]
+fn test_raw_node_safe_compact_index() {
+    let l = default_logger();
+    let mut snap = new_snapshot(1, 1, vec![1, 2]);
+    snap.mut_metadata().mut_conf_state().mut_learners().push(3);
+    let (mut raw_node, s, _) = new_raw_node_with_log(snap, &l);
+    let rd = raw_node.ready();
+    raw_node.advance(rd);
+    raw_node.advance_apply();
+    assert_eq!(raw_node.raft.raft_log.applied, 5);
+    // Only the applied index matters on a follower.
+    assert_eq!(raw_node.safe_compact_index(), 6);
+    assert_eq!(raw_node.safe_compact_index_including_learners(), 6);
+
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    raw_node.raft.mut_prs().get_mut(2).unwrap().matched = 5;
+    raw_node.raft.mut_prs().get_mut(3).unwrap().matched = 3;
+    assert_eq!(raw_node.safe_compact_index(), 6);
+    assert_eq!(raw_node.safe_compact_index_including_learners(), 4);
+
+    raw_node.raft.mut_prs().get_mut(2).unwrap().matched = 2;
+    assert_eq!(raw_node.safe_compact_index(), 3);
+    assert_eq!(raw_node.safe_compact_index_including_learners(), 3);
+
+    // The learner hasn't replicated anything yet.
+    raw_node.raft.mut_prs().get_mut(2).unwrap().matched = 5;
+    raw_node.raft.mut_prs().get_mut(3).unwrap().matched = 0;
+    assert_eq!(raw_node.safe_compact_index(), 6);
+    assert_eq!(raw_node.safe_compact_index_including_learners(), 2);
+
+    raw_node.raft.mut_prs().get_mut(3).unwrap().matched = 4;
+    let index = raw_node.safe_compact_index_including_learners();
+    assert_eq!(index, 5);
+    raw_node.compact(index).unwrap();
+    assert_eq!(s.first_index(), Ok(5));
+}
+
+/// Tests that `dump_log` returns the whole log, which starts after the compacted prefix.
+#[test]
+fn test_raw_node_dump_log() {
+    let l = default_logger();
+    let (mut raw_node, s, ents) = new_raw_node_with_log(new_snapshot(1, 1, vec![1, 2]), &l);
+    assert_eq!(raw_node.dump_log(), Ok(ents.clone()));
+
+    // Unstable entries are included.
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    let mut want = ents.clone();
+    want.push(empty_entry(2, 6));
+    assert_eq!(raw_node.dump_log(), Ok(want.clone()));
+
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    raw_node.advance_apply();
+    raw_node.compact(4).unwrap();
+    assert_eq!(raw_node.dump_log(), Ok(want[2..].to_vec()));
+}
+
+#[test]
+fn test_raw_node_first_and_last_index() {
+    let l = default_logger();
+    let (mut raw_node, s, _) = new_raw_node_with_log(new_snapshot(1, 1, vec![1, 2]), &l);
+    assert_eq!((raw_node.first_index(), raw_node.last_index()), (2, 5));
+
+    // Unstable entries are counted.
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    assert_eq!((raw_node.first_index(), raw_node.last_index()), (2, 6));
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    raw_node.advance_apply();
+
+    raw_node.compact(4).unwrap();
+    assert_eq!((raw_node.first_index(), raw_node.last_index()), (4, 6));
+    assert_eq!(raw_node.first_index(), s.first_index().unwrap());
+    raw_node.compact(6).unwrap();
+    assert_eq!((raw_node.first_index(), raw_node.last_index()), (6, 6));
+
+    // A snapshot not persisted yet sets the boundary on its own.
+    let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    m.term = 3;
+    m.set_snapshot(new_snapshot(10, 3, vec![1, 2]));
+    raw_node.step(m).unwrap();
+    assert_eq!((raw_node.first_index(), raw_node.last_index()), (11, 10));
+    assert_eq!(s.first_index(), Ok(6));
+}
+
+/// Tests that `next_index` follows the leader's view of a follower bouncing between
+/// rejections and acceptances.
+#[test]
+fn test_raw_node_next_index() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &l);
+    assert_eq!(raw_node.next_index(2), None);
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    for _ in 0..3 {
+        raw_node.propose(vec![], b"foo".to_vec()).unwrap();
+    }
+    let last_index = raw_node.raft.raft_log.last_index();
+    assert_eq!(raw_node.next_index(3), None);
+    let term = raw_node.raft.term;
+    let append_response = |index: u64, reject: bool| {
+        let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+        m.term = term;
+        m.index = index;
+        m.reject = reject;
+        m.reject_hint = index.saturating_sub(1);
+        m
+    };
+
+    // The follower keeps rejecting the probes, so the leader walks its next index back.
+    let mut next = raw_node.next_index(2).unwrap();
+    while next > 2 {
+        raw_node.step(append_response(next - 1, true)).unwrap();
+        let new_next = raw_node.next_index(2).unwrap();
+        assert_eq!(new_next, next - 1);
+        next = new_next;
+    }
+
+    // An acceptance moves it forward again.
+    raw_node.step(append_response(last_index, false)).unwrap();
+    assert_eq!(raw_node.next_index(2), Some(last_index + 1));
+
+    // A stale rejection is ignored.
+    raw_node.step(append_response(last_index, true)).unwrap();
+    assert_eq!(raw_node.next_index(2), Some(last_index + 1));
+    // A rejection of the entries in flight moves it back right after the matched index.
+    raw_node.propose(vec![], b"bar".to_vec()).unwrap();
+    assert!(raw_node.next_index(2).unwrap() > last_index + 1);
+    raw_node
+        .step(append_response(last_index + 1, true))
+        .unwrap();
+    assert_eq!(raw_node.next_index(2), Some(last_index + 1));
+}
+
+#[test]
+fn test_raw_node_ready_reason() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), &l);
+    assert!(raw_node.ready_reason().is_empty());
+    assert!(!raw_node.has_ready());
+
+    // Vote for another candidate.
+    let mut m = new_message(2, 1, MessageType::MsgRequestVote, 0);
+    m.term = 2;
+    m.index = 1;
+    m.log_term = 1;
+    raw_node.step(m).unwrap();
+    let want = ReadyReason {
+        messages: true,
+        hard_state: true,
+        ..Default::default()
+    };
+    assert_eq!(raw_node.ready_reason(), want);
+    let rd = raw_node.ready();
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    raw_node.advance(rd);
+    assert!(raw_node.ready_reason().is_empty());
+
+    // Receive an entry from the new leader.
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.term = 2;
+    m.index = 1;
+    m.log_term = 1;
+    m.commit = 2;
+    m.entries = vec![empty_entry(2, 2)].into();
+    raw_node.step(m).unwrap();
+    let want = ReadyReason {
+        messages: true,
+        soft_state: true,
+        hard_state: true,
+        entries: true,
+        ..Default::default()
+    };
+    assert_eq!(raw_node.ready_reason(), want);
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    s.wl().set_hardstate(rd.hs().unwrap().clone());
+    let number = rd.number();
+    raw_node.advance_append_async(rd);
+    assert!(raw_node.ready_reason().is_empty());
+
+    // The entry can be applied once it's persisted.
+    raw_node.on_persist_ready(number);
+    let want = ReadyReason {
+        committed_entries: true,
+        ..Default::default()
+    };
+    assert_eq!(raw_node.ready_reason(), want);
+    let rd = raw_node.ready();
+    raw_node.advance(rd);
+    assert!(raw_node.ready_reason().is_empty());
+
+    // Read states from the leader.
+    let mut m = new_message(2, 1, MessageType::MsgReadIndexResp, 0);
+    m.term = 2;
+    m.index = 2;
+    m.entries = vec![new_entry(0, 0, Some("ctx"))].into();
+    raw_node.step(m).unwrap();
+    let want = ReadyReason {
+        read_states: true,
+        ..Default::default()
+    };
+    assert_eq!(raw_node.ready_reason(), want);
+    let rd = raw_node.ready();
+    raw_node.advance(rd);
+
+    // A snapshot from the leader.
+    let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    m.term = 2;
+    m.set_snapshot(new_snapshot(10, 2, vec![1, 2]));
+    raw_node.step(m).unwrap();
+    let want = ReadyReason {
+        messages: true,
+        hard_state: true,
+        snapshot: true,
+        ..Default::default()
+    };
+    assert_eq!(raw_node.ready_reason(), want);
+    assert!(raw_node.has_ready());
+}
+
+#[test]
+fn test_raw_node_conf_state_digest() {
+    let l = default_logger();
+    let mut nodes: Vec<_> = vec![vec![1, 2, 3], vec![3, 1, 2], vec![2, 3, 1]]
+        .into_iter()
+        .enumerate()
+        .map(|(i, peers)| new_raw_node(i as u64 + 1, peers, 10, 1, new_storage(), &l))
+        .collect();
+    let digest = nodes[0].conf_state_digest();
+    assert!(nodes.iter().all(|n| n.conf_state_digest() == digest));
+
+    // Diverged nodes report different digests until they converge again.
+    let cc = conf_change(ConfChangeType::AddLearnerNode, 4);
+    nodes[0].apply_conf_change(&cc).unwrap();
+    assert_ne!(nodes[0].conf_state_digest(), digest);
+    assert_ne!(nodes[0].conf_state_digest(), nodes[1].conf_state_digest());
+    for n in &mut nodes[1..] {
+        n.apply_conf_change(&cc).unwrap();
+    }
+    let digest = nodes[0].conf_state_digest();
+    assert!(nodes.iter().all(|n| n.conf_state_digest() == digest));
+
+    // Joint configurations are told apart from the simple ones.
+    let mut cc = ConfChangeV2::default();
+    cc.set_changes(vec![new_conf_change_single(4, ConfChangeType::AddNode)].into());
+    cc.set_transition(ConfChangeTransition::Explicit);
+    for n in &mut nodes {
+        n.apply_conf_change(&cc).unwrap();
+    }
+    let joint = nodes[0].conf_state_digest();
+    assert_ne!(joint, digest);
+    assert!(nodes.iter().all(|n| n.conf_state_digest() == joint));
+}
+
+#[test]
+fn test_raw_node_uncommitted_entries() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, s.clone(), &l);
+    assert!(raw_node.uncommitted_entries().is_empty());
+
+    // A leader without any follower commits nothing.
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    raw_node.propose(vec![], b"foo".to_vec()).unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    // Unstable entries are included as well.
+    raw_node.propose(vec![], b"bar".to_vec()).unwrap();
+    let ents = raw_node.uncommitted_entries();
+    let indexes: Vec<_> = ents.iter().map(|e| e.index).collect();
+    assert_eq!(indexes, vec![2, 3, 4]);
+    assert!(ents[0].data.is_empty());
+    assert_eq!(ents[1].data, b"foo".as_ref());
+    assert_eq!(ents[2].data, b"bar".as_ref());
+
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = raw_node.raft.term;
+    m.index = 3;
+    raw_node.step(m).unwrap();
+    let ents = raw_node.uncommitted_entries();
+    assert_eq!(ents.len(), 1);
+    assert_eq!(ents[0].index, 4);
+
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.term = raw_node.raft.term;
+    m.index = 4;
+    raw_node.step(m).unwrap();
+    assert!(raw_node.uncommitted_entries().is_empty());
+}
+
+#[test]
+fn test_raw_node_check_invariants() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    raw_node.propose(vec![], b"foo".to_vec()).unwrap();
+    assert_eq!(raw_node.check_invariants(), Ok(()));
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert_eq!(raw_node.check_invariants(), Ok(()));
+    assert_eq!(raw_node.raft.raft_log.committed, 3);
+
+    let violated = |raw_node: &RawNode<MemStorage>| match raw_node.check_invariants() {
+        Err(Error::InvariantViolated(msg)) => msg,
+        res => panic!("unexpected result {:?}", res),
+    };
+
+    raw_node.raft.raft_log.applied = 4;
+    assert!(violated(&raw_node).contains("applied index 4"));
+    raw_node.raft.raft_log.applied = 3;
+
+    raw_node.raft.raft_log.committed = 4;
+    assert!(violated(&raw_node).contains("committed index 4"));
+    raw_node.raft.raft_log.committed = 3;
+
+    // A hole between the stable and the unstable entries.
+    raw_node.raft.raft_log.unstable.offset = 5;
+    raw_node.raft.raft_log.unstable.entries = vec![empty_entry(2, 5)];
+    assert!(violated(&raw_node).contains("start at 5"));
+
+    // Unstable entries out of order.
+    raw_node.raft.raft_log.unstable.offset = 4;
+    raw_node.raft.raft_log.unstable.entries = vec![empty_entry(2, 4), empty_entry(2, 6)];
+    assert!(violated(&raw_node).contains("at index 6, expected 5"));
+
+    raw_node.raft.raft_log.unstable.entries.truncate(1);
+    assert_eq!(raw_node.check_invariants(), Ok(()));
+}
+
+#[test]
+fn test_raw_node_reject_removing_last_voter() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+
+    let remove = conf_change(ConfChangeType::RemoveNode, 1);
+    let demote = conf_change(ConfChangeType::AddLearnerNode, 1);
+    assert!(raw_node.would_leave_no_voters(&remove));
+    assert!(raw_node.would_leave_no_voters(&demote));
+    assert!(!raw_node.would_leave_no_voters(&conf_change(ConfChangeType::AddNode, 2)));
+    let mut replace = ConfChangeV2::default();
+    replace.set_changes(
+        vec![
+            new_conf_change_single(1, ConfChangeType::RemoveNode),
+            new_conf_change_single(2, ConfChangeType::AddNode),
+        ]
+        .into(),
+    );
+    assert!(!raw_node.would_leave_no_voters(&replace));
+
+    let last_index = raw_node.raft.raft_log.last_index();
+    for cc in &[remove, demote] {
+        match raw_node.propose_conf_change(vec![], cc.clone()) {
+            Err(Error::ConfChangeError(msg)) => assert!(msg.contains("remove all voters")),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(raw_node.raft.raft_log.last_index(), last_index);
+        assert!(!raw_node.raft.has_pending_conf());
+        assert!(matches!(
+            raw_node.apply_conf_change(cc),
+            Err(Error::ConfChangeError(_))
+        ));
+    }
+    assert_eq!(raw_node.raft.prs().conf().voters().ids().len(), 1);
+}
+
+#[test]
+fn test_raw_node_take_read_states() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    assert!(raw_node.take_read_states().is_empty());
+
+    raw_node.read_index(b"a".to_vec());
+    raw_node.read_index(b"b".to_vec());
+    let committed = raw_node.raft.raft_log.committed;
+    let rss = raw_node.take_read_states();
+    let wrss: Vec<_> = [b"a", b"b"]
+        .iter()
+        .map(|ctx| ReadState {
+            index: committed,
+            request_ctx: ctx.to_vec(),
+        })
+        .collect();
+    assert_eq!(rss, wrss);
+
+    // The taken read states are not delivered again.
+    assert!(!raw_node.has_ready());
+    assert!(raw_node.ready().read_states().is_empty());
+    assert!(raw_node.take_read_states().is_empty());
+}
+
+#[test]
+fn test_raw_node_should_snapshot() {
+    let l = default_logger();
+    let s = new_storage();
+    let mut config = new_test_config(1, 10, 1);
+    config.snapshot_entries_threshold = 3;
+    let mut raw_node = new_raw_node_with_config(vec![1], &config, s.clone(), &l);
+    raw_node.campaign().unwrap();
+    assert!(!raw_node.status().should_snapshot);
+
+    for data in &[b"a", b"b"] {
+        raw_node.propose(vec![], data.to_vec()).unwrap();
+        assert!(!raw_node.status().should_snapshot);
+    }
+    // Unstable entries count as well.
+    raw_node.propose(vec![], b"c".to_vec()).unwrap();
+    assert!(raw_node.status().should_snapshot);
+    let rd = raw_node.ready();
+    s.wl().append(rd.entries()).unwrap();
+    raw_node.advance(rd);
+    raw_node.advance_apply();
+    assert!(raw_node.status().should_snapshot);
+
+    // The suggestion goes away once the log is compacted.
+    let applied = raw_node.raft.raft_log.applied;
+    s.wl().compact(applied).unwrap();
+    assert!(!raw_node.status().should_snapshot);
+}
+
+/// Tests that a retransmitted snapshot is applied only once and counted as a duplicate.
+#[test]
+fn test_raw_node_duplicate_snapshot() {
+    let l = default_logger();
+    let s = new_storage();
+    s.wl()
+        .apply_snapshot(new_snapshot(1, 1, vec![1, 2]))
+        .unwrap();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s.clone(), &l);
+    let mut snap = new_snapshot(10, 2, vec![1, 2]);
+    snap.mut_metadata().term = 2;
+    let mut m = new_message(2, 1, MessageType::MsgSnapshot, 0);
+    m.term = 2;
+    m.set_snapshot(snap);
+
+    raw_node.step(m.clone()).unwrap();
+    assert_eq!(raw_node.status().duplicate_snapshots_ignored, 0);
+    // The copy arrives before the first one is persisted.
+    let out_of_date = Err(Error::Store(StorageError::SnapshotOutOfDate));
+    assert_eq!(raw_node.step(m.clone()), out_of_date);
+    assert_eq!(raw_node.status().duplicate_snapshots_ignored, 1);
+
+    let rd = raw_node.ready();
+    assert_eq!(rd.snapshot().get_metadata().index, 10);
+    s.wl().apply_snapshot(rd.snapshot().clone()).unwrap();
+    raw_node.advance(rd);
+    raw_node.advance_apply_to(10);
+
+    // The copy arrives after the snapshot is applied.
+    assert_eq!(raw_node.step(m), out_of_date);
+    assert_eq!(raw_node.status().duplicate_snapshots_ignored, 2);
+    let rd = raw_node.ready();
+    assert!(rd.snapshot().is_empty());
+    assert_eq!(raw_node.raft.raft_log.applied, 10);
+    assert_eq!(raw_node.raft.raft_log.committed, 10);
+}
+
+/// Tests that records logged after `set_logger` go to the new logger only.
+#[test]
+fn test_raw_node_set_logger() {
+    use std::sync::{Arc, Mutex};
+
+    struct CaptureDrain(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for CaptureDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record,
+            _: &slog::OwnedKVList,
+        ) -> std::result::Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    let old_records = Arc::new(Mutex::new(vec![]));
+    let new_records = Arc::new(Mutex::new(vec![]));
+    let old_logger = Logger::root(CaptureDrain(old_records.clone()), slog::o!());
+    let new_logger = Logger::root(CaptureDrain(new_records.clone()), slog::o!());
+
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, s, &old_logger);
+    raw_node.campaign().unwrap();
+    let logged = old_records.lock().unwrap().len();
+    assert!(logged > 0);
+
+    raw_node.set_logger(new_logger);
+    let mut m = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.term = raw_node.raft.term;
+    raw_node.step(m).unwrap();
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+    assert_eq!(old_records.lock().unwrap().len(), logged);
+    let new_records = new_records.lock().unwrap();
+    assert!(
+        new_records.iter().any(|r| r.contains("became leader")),
+        "{:?}",
+        new_records
+    );
+}
@@ -4,6 +4,7 @@ mod changer;
 pub mod datadriven_test;
 mod restore;
 
+pub(crate) use self::changer::REMOVED_ALL_VOTERS;
 pub use self::changer::{Changer, MapChange, MapChangeType};
 pub use self::restore::restore;
 
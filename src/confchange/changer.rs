@@ -4,6 +4,9 @@ use crate::eraftpb::{ConfChangeSingle, ConfChangeType};
 use crate::tracker::{Configuration, ProgressMap, ProgressTracker};
 use crate::{Error, Result};
 
+/// Error message returned when a change would leave the incoming config without voters.
+pub(crate) const REMOVED_ALL_VOTERS: &str = "removed all voters";
+
 /// Change log for progress map.
 pub enum MapChangeType {
     Add,
@@ -179,7 +182,7 @@ impl Changer<'_> {
             }
         }
         if cfg.voters().incoming.is_empty() {
-            return Err(Error::ConfChangeError("removed all voters".to_owned()));
+            return Err(Error::ConfChangeError(REMOVED_ALL_VOTERS.to_owned()));
         }
         Ok(())
     }
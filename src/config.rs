@@ -21,6 +21,47 @@ use super::{
     INVALID_ID,
 };
 
+/// Determines how candidates of the same term break the tie after a split vote.
+///
+/// Votes themselves are always granted first come, first served, even to candidates with
+/// identical terms and logs, as a voter can't take back its vote within a term. The tie is
+/// broken by the candidates instead: once asked for its vote by an equally qualified
+/// candidate that is preferred, a candidate holds back its next campaign.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SplitVoteTieBreak {
+    /// Rely on the randomized election timeouts only.
+    Disabled,
+    /// A candidate that was asked for its vote by an equally qualified candidate with a
+    /// lower id waits an extra election timeout before campaigning again, so that the
+    /// candidate with the lowest id campaigns first in the next term.
+    LowestId,
+    /// Like `LowestId`, but the candidate with the highest id is preferred.
+    HighestId,
+}
+
+impl Default for SplitVoteTieBreak {
+    fn default() -> SplitVoteTieBreak {
+        SplitVoteTieBreak::Disabled
+    }
+}
+
+/// Determines what the leader does with a proposed entry larger than `max_size_per_msg`,
+/// which can't be split into smaller messages.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OversizedEntryPolicy {
+    /// Accept the entry and replicate it alone in a message over the size budget, the
+    /// same way any message carries at least one entry.
+    Force,
+    /// Reject the proposal with `Error::ProposalTooLarge`.
+    Reject,
+}
+
+impl Default for OversizedEntryPolicy {
+    fn default() -> OversizedEntryPolicy {
+        OversizedEntryPolicy::Force
+    }
+}
+
 /// Config contains the parameters to start a raft.
 #[derive(Clone)]
 pub struct Config {
@@ -44,6 +85,8 @@ pub struct Config {
     /// raft. raft will not return entries to the application smaller or equal to Applied.
     /// If Applied is unset when restarting, raft might return previous applied entries.
     /// This is a very application dependent configuration.
+    /// It must not be greater than the persisted commit index, otherwise creating the raft
+    /// fails with `Error::ConfigInvalid`.
     pub applied: u64,
 
     /// Limit the max size of each append message. Smaller value lowers
@@ -87,6 +130,10 @@ pub struct Config {
     pub skip_bcast_commit: bool,
 
     /// Batches every append msg if any append msg already exists
+    ///
+    /// When many proposals arrive between readies, the leader coalesces their appends to the
+    /// same follower into fewer, larger messages. Entries are never split, and an append is
+    /// only extended while it stays within `max_size_per_msg`.
     pub batch_append: bool,
 
     /// The election priority of this node.
@@ -98,6 +145,179 @@ pub struct Config {
 
     /// Max size for committed entries in a `Ready`.
     pub max_committed_size_per_ready: u64,
+
+    /// Specify maximum number of read only requests waiting for heartbeat acknowledgments.
+    /// When this limit is reached, new `ReadOnlyOption::Safe` read requests will be dropped
+    /// with `Error::ReadIndexDropped`, so callers can shed load.
+    pub max_pending_read_count: u64,
+
+    /// Specify maximum number of read only requests of a single node waiting for heartbeat
+    /// acknowledgments, so one node flooding the leader with reads can't take up the whole
+    /// `max_pending_read_count`. Requests beyond it are dropped with
+    /// `Error::ReadIndexDropped`, while the ones of other nodes are still accepted.
+    pub max_pending_read_count_per_peer: u64,
+
+    /// Reject messages without a term from other peers with `Error::StepTermMissing`
+    /// instead of treating them as local messages. Proposals, read index and leader
+    /// transfer requests are still accepted as they are forwarded without a term.
+    pub strict_msg_term: bool,
+
+    /// Specify maximum number of rejected appends a probing follower can go through before
+    /// the leader gives up backtracking its log and sends a snapshot instead. This bounds
+    /// the catch-up latency of a follower with a long divergent log.
+    pub max_probe_rounds: u64,
+
+    /// Drop proposals of empty entries if the last entry of the log is an uncommitted empty
+    /// entry of the current term, which will notify followers just the same. This avoids
+    /// log bloat when empty entries are proposed eagerly, e.g. during leadership churn.
+    pub skip_duplicate_empty_entry: bool,
+
+    /// Whether a follower resets its election timer on appends from the leader, in addition
+    /// to heartbeats and snapshots.
+    ///
+    /// Resetting on appends keeps followers from campaigning as long as they hear from the
+    /// leader at all, which is more stable when heartbeats are delayed behind heavy append
+    /// traffic. Disabling it ties the election timer to the heartbeats only, so a leader
+    /// that can still push appends but fails to send heartbeats in time is replaced.
+    pub reset_election_on_append: bool,
+
+    /// The duration of the leader lease in ticks, used by `ReadOnlyOption::LeaseBased` reads.
+    ///
+    /// If it is 0, the lease is bounded by the election timeout through `check_quorum`.
+    /// Otherwise the leader only serves lease based reads while a quorum has acknowledged
+    /// a heartbeat sent within the last `leader_lease_ticks` ticks, which leaves a safety
+    /// margin against clock drift. It must be less than `election_tick`.
+    pub leader_lease_ticks: usize,
+
+    /// The number of ticks a heartbeat can stay unanswered before the leader suspects the
+    /// peer is unreachable. See `RawNode::is_likely_partitioned`.
+    ///
+    /// If it is 0, 2 * heartbeat_tick will be chosen. It must be less than `election_tick`.
+    pub partition_detection_ticks: usize,
+
+    /// Defer the empty entry a new leader appends to commit its term until the first read
+    /// index request.
+    ///
+    /// A leader can only serve reads once an entry of its term is committed. By default it
+    /// appends an empty entry as soon as it's elected, which costs a write on every election
+    /// but makes the leader ready for reads as early as possible. With this option, a leader
+    /// that is only asked to write never appends the empty entry, as its first proposal
+    /// commits the term just the same. The drawback is that read index requests received
+    /// before the term is committed are dropped while the empty entry is replicated, so the
+    /// first reads after an election take an extra round trip.
+    ///
+    /// A conf change proposed before the term is committed also appends the empty entry
+    /// first. Membership changes are only safe once a leader has committed an entry of its
+    /// term, so a conf change is never the first entry of a term.
+    pub lazy_leader_noop: bool,
+
+    /// Break the tie between candidates after a split vote by their ids instead of relying
+    /// on the randomized election timeouts only, which reduces repeated split votes in small
+    /// clusters at the cost of a longer election for the candidates that are not preferred.
+    pub split_vote_tie_break: SplitVoteTieBreak,
+
+    /// Delay advertising a new commit index to followers by the given number of ticks, while
+    /// entries are still replicated right away. 0 means no delay.
+    ///
+    /// Followers know the entries exist but not that they are committed until the delay
+    /// elapses, which widens the window in which they lag behind the leader. It's meant for
+    /// exercising read consistency in tests and should be left 0 otherwise.
+    pub commit_advertise_delay_ticks: usize,
+
+    /// Draw a new randomized election timeout every time the node resets for a term, i.e.
+    /// on every term change and role change.
+    ///
+    /// This keeps the election timeouts of the nodes from staying correlated after a cluster
+    /// wide event, which could lead to synchronized re-elections. If false, the timeout drawn
+    /// when the node is created is kept.
+    pub redraw_election_timeout: bool,
+
+    /// The number of ticks a follower may stay silent before it's reported inactive by
+    /// `RawNode::inactive_peers`.
+    ///
+    /// If it is 0, a follower is inactive as soon as it's silent for a whole `check_quorum`
+    /// interval, so a single lost heartbeat response right before a check flips its state.
+    /// A larger grace period smooths this health signal. It doesn't delay the step down of a
+    /// leader that lost its quorum, as that would let the leader outlive its lease.
+    pub inactive_grace_ticks: usize,
+
+    /// Buffers proposals received by the leader while a leader transfer is in progress,
+    /// instead of dropping them.
+    ///
+    /// Once the transfer is done, the buffered proposals are forwarded to the new leader, or
+    /// proposed again locally if the transfer is aborted. Like any forwarded proposal, they
+    /// may still be lost and should be retried by the client on timeout.
+    ///
+    /// Buffered proposals count towards `max_uncommitted_size`, and at most
+    /// `max_transfer_proposals` of them are buffered. Proposals beyond either limit are
+    /// dropped with `Error::ProposalDropped`.
+    pub buffer_proposals_during_transfer: bool,
+
+    /// The maximum number of proposals buffered during a leader transfer, see
+    /// `buffer_proposals_during_transfer`.
+    pub max_transfer_proposals: usize,
+
+    /// The minimum number of ticks between applying a conf change and proposing the next
+    /// one, which protects the cluster from reconfiguration storms.
+    ///
+    /// A conf change proposed within the interval is rejected with
+    /// `Error::ConfChangeError`. Leaving a joint configuration is always allowed. If it is
+    /// 0, conf changes are only limited by the one-at-a-time rule.
+    pub min_conf_change_interval_ticks: usize,
+
+    /// The number of ticks a leader waits for its commit index to advance while there are
+    /// uncommitted entries, before it steps down to let a better connected node take over.
+    ///
+    /// Unlike `check_quorum`, it also catches a leader that can reach a quorum but can't
+    /// commit through it. If it is 0, a leader never steps down for a stalled commit index.
+    pub max_commit_stall_ticks: usize,
+
+    /// The number of ticks a follower serves linearizable reads locally under a read lease
+    /// delegated by the leader, see `RawNode::request_read_lease`. If it is 0, the leader
+    /// doesn't delegate read leases.
+    ///
+    /// While a lease is in effect, the leader only commits entries the holder has
+    /// appended, so the holder can serve reads at its last index. The lease starts when
+    /// the follower requests it, and the leader only grants it while its own lease is
+    /// valid, so that no other leader can be elected before it expires. This requires
+    /// `check_quorum` and a non-zero `leader_lease_ticks`, and twice this value plus
+    /// `leader_lease_ticks` must be less than `election_tick`.
+    ///
+    /// The leader keeps honoring a lease for twice as long as the holder does, which
+    /// tolerates the clock of the holder running at half the rate of the leader's. Clocks
+    /// drifting further apart break linearizability.
+    ///
+    /// The commit gate also holds while the holder is isolated: the leader doesn't commit
+    /// anything until the lease runs out on its side, and a newly elected leader doesn't
+    /// commit for twice this value, in case a lease of the previous leader is still in
+    /// effect. Leadership isn't transferred while a lease is held.
+    pub follower_lease_ticks: usize,
+
+    /// The number of entries the log can hold since the last snapshot before a snapshot
+    /// is suggested through `Status::should_snapshot`. The application decides whether
+    /// and when to act on it. By default a snapshot is never suggested.
+    pub snapshot_entries_threshold: u64,
+
+    /// Determines what the leader does with a proposed entry that is larger than
+    /// `max_size_per_msg` on its own. By default it's replicated in an over-budget message.
+    ///
+    /// `OversizedEntryPolicy::Reject` requires a non-zero `max_size_per_msg`.
+    pub oversized_entry_policy: OversizedEntryPolicy,
+
+    /// The maximum number of appends the leader pipelines to a follower in replicate state
+    /// before waiting for an acknowledgement, see `Progress::pipeline_depth`. If it is 0, the
+    /// pipeline is only limited by `max_inflight_msgs`.
+    ///
+    /// Unlike `max_inflight_msgs`, it only throttles the sending, so it can be tuned to the
+    /// latency of the links without resizing the inflight windows. A deeper pipeline keeps
+    /// high latency links busy, at the cost of more data to resend when appends are lost.
+    pub max_pipeline_depth: usize,
+
+    /// Panic on clear protocol violations instead of handling them silently, e.g. when
+    /// two leaders are seen in the same term, or when the leader's log contradicts committed
+    /// entries, so accepting it would move the commit index backwards. It turns latent bugs
+    /// into loud failures and is meant for tests and development.
+    pub strict_mode: bool,
 }
 
 impl Default for Config {
@@ -120,6 +340,28 @@ impl Default for Config {
             priority: 0,
             max_uncommitted_size: NO_LIMIT,
             max_committed_size_per_ready: NO_LIMIT,
+            max_pending_read_count: NO_LIMIT,
+            max_pending_read_count_per_peer: NO_LIMIT,
+            strict_msg_term: false,
+            max_probe_rounds: NO_LIMIT,
+            skip_duplicate_empty_entry: false,
+            reset_election_on_append: true,
+            leader_lease_ticks: 0,
+            partition_detection_ticks: 0,
+            lazy_leader_noop: false,
+            split_vote_tie_break: SplitVoteTieBreak::Disabled,
+            commit_advertise_delay_ticks: 0,
+            redraw_election_timeout: true,
+            inactive_grace_ticks: 0,
+            buffer_proposals_during_transfer: false,
+            max_transfer_proposals: 256,
+            min_conf_change_interval_ticks: 0,
+            max_commit_stall_ticks: 0,
+            follower_lease_ticks: 0,
+            snapshot_entries_threshold: NO_LIMIT,
+            oversized_entry_policy: OversizedEntryPolicy::Force,
+            max_pipeline_depth: 0,
+            strict_mode: false,
         }
     }
 }
@@ -153,7 +395,21 @@ impl Config {
         }
     }
 
+    /// The number of ticks after which an unanswered heartbeat marks a peer as unreachable.
+    #[inline]
+    pub fn partition_detection_ticks(&self) -> usize {
+        if self.partition_detection_ticks == 0 {
+            2 * self.heartbeat_tick
+        } else {
+            self.partition_detection_ticks
+        }
+    }
+
     /// Runs validations against the config.
+    ///
+    /// Besides invalid values, unsafe combinations are rejected too, e.g. lease based reads
+    /// without `check_quorum`, which let a deposed leader serve stale reads regardless of
+    /// `pre_vote`.
     pub fn validate(&self) -> Result<()> {
         if self.id == INVALID_ID {
             return Err(Error::ConfigInvalid("invalid node id".to_owned()));
@@ -199,12 +455,73 @@ impl Config {
             ));
         }
 
+        if self.leader_lease_ticks >= self.election_tick {
+            return Err(Error::ConfigInvalid(format!(
+                "leader lease tick {} should be less than election tick {}",
+                self.leader_lease_ticks, self.election_tick
+            )));
+        }
+
+        if self.follower_lease_ticks > 0 {
+            if !self.check_quorum || self.leader_lease_ticks == 0 {
+                return Err(Error::ConfigInvalid(
+                    "follower lease requires check_quorum == true and leader_lease_ticks > 0"
+                        .to_owned(),
+                ));
+            }
+            if 2 * self.follower_lease_ticks + self.leader_lease_ticks >= self.election_tick {
+                return Err(Error::ConfigInvalid(format!(
+                    "twice follower lease tick {} plus leader lease tick {} should be less \
+                     than election tick {}",
+                    self.follower_lease_ticks, self.leader_lease_ticks, self.election_tick
+                )));
+            }
+        }
+
+        if self.partition_detection_ticks >= self.election_tick {
+            return Err(Error::ConfigInvalid(format!(
+                "partition detection tick {} should be less than election tick {}",
+                self.partition_detection_ticks, self.election_tick
+            )));
+        }
+
+        if self.max_pending_read_count == 0 {
+            return Err(Error::ConfigInvalid(
+                "max pending read count must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.max_pending_read_count_per_peer == 0 {
+            return Err(Error::ConfigInvalid(
+                "max pending read count per peer must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.buffer_proposals_during_transfer && self.max_transfer_proposals == 0 {
+            return Err(Error::ConfigInvalid(
+                "max transfer proposals must be greater than 0 to buffer proposals".to_owned(),
+            ));
+        }
+
+        if self.max_probe_rounds == 0 {
+            return Err(Error::ConfigInvalid(
+                "max probe rounds must be greater than 0".to_owned(),
+            ));
+        }
+
         if self.max_uncommitted_size < self.max_size_per_msg {
             return Err(Error::ConfigInvalid(
                 "max uncommitted size should greater than max_size_per_msg".to_owned(),
             ));
         }
 
+        if self.oversized_entry_policy == OversizedEntryPolicy::Reject && self.max_size_per_msg == 0
+        {
+            return Err(Error::ConfigInvalid(
+                "max_size_per_msg must be greater than 0 to reject oversized entries".to_owned(),
+            ));
+        }
+
         Ok(())
     }
 }
@@ -16,6 +16,9 @@ pub enum Error {
     /// The raft peer is not found and thus cannot step.
     #[error("raft: cannot step as peer not found")]
     StepPeerNotFound,
+    /// Raft cannot step the remote message without a term.
+    #[error("raft: cannot step remote message without term")]
+    StepTermMissing,
     /// The proposal of changes was dropped.
     #[error("raft: proposal dropped")]
     ProposalDropped,
@@ -47,6 +50,44 @@ pub enum Error {
     /// The request snapshot is dropped.
     #[error("raft: request snapshot dropped")]
     RequestSnapshotDropped,
+    /// The read index request is dropped.
+    #[error("raft: read index dropped")]
+    ReadIndexDropped,
+    /// The log can't be compacted beyond the applied index.
+    #[error("raft: cannot compact to {index} as entries after applied index {applied} are not applied yet")]
+    CompactUnapplied {
+        /// The requested compact index.
+        index: u64,
+        /// The applied index.
+        applied: u64,
+    },
+    /// The term can't go backward.
+    #[error("raft: cannot go back to term {term} from term {current}")]
+    StaleTerm {
+        /// The requested term.
+        term: u64,
+        /// The current term.
+        current: u64,
+    },
+    /// An internal invariant of the raft state is violated.
+    #[error("raft: invariant violated: {0}")]
+    InvariantViolated(String),
+    /// A proposed entry is larger than the size limit of a message.
+    #[error("raft: proposed entry of {size} bytes exceeds max_size_per_msg {max}")]
+    ProposalTooLarge {
+        /// The size of the entry.
+        size: u64,
+        /// The size limit of a message.
+        max: u64,
+    },
+    /// The last entry of the log doesn't match the expectation of a conditional proposal.
+    #[error("raft: expected last entry {expected:?}, but got {actual:?}")]
+    LastEntryMismatch {
+        /// The expected index and term of the last entry.
+        expected: (u64, u64),
+        /// The actual index and term of the last entry.
+        actual: (u64, u64),
+    },
 }
 
 impl PartialEq for Error {
@@ -58,9 +99,46 @@ impl PartialEq for Error {
             (Error::Store(ref e1), Error::Store(ref e2)) => e1 == e2,
             (Error::Io(ref e1), Error::Io(ref e2)) => e1.kind() == e2.kind(),
             (Error::StepLocalMsg, Error::StepLocalMsg) => true,
+            (Error::StepTermMissing, Error::StepTermMissing) => true,
             (Error::ConfigInvalid(ref e1), Error::ConfigInvalid(ref e2)) => e1 == e2,
             (Error::RequestSnapshotDropped, Error::RequestSnapshotDropped) => true,
+            (Error::ReadIndexDropped, Error::ReadIndexDropped) => true,
             (Error::ConfChangeError(e1), Error::ConfChangeError(e2)) => e1 == e2,
+            (Error::InvariantViolated(e1), Error::InvariantViolated(e2)) => e1 == e2,
+            (
+                Error::ProposalTooLarge { size: s1, max: m1 },
+                Error::ProposalTooLarge { size: s2, max: m2 },
+            ) => s1 == s2 && m1 == m2,
+            (
+                Error::LastEntryMismatch {
+                    expected: e1,
+                    actual: a1,
+                },
+                Error::LastEntryMismatch {
+                    expected: e2,
+                    actual: a2,
+                },
+            ) => e1 == e2 && a1 == a2,
+            (
+                Error::CompactUnapplied {
+                    index: i1,
+                    applied: a1,
+                },
+                Error::CompactUnapplied {
+                    index: i2,
+                    applied: a2,
+                },
+            ) => i1 == i2 && a1 == a2,
+            (
+                Error::StaleTerm {
+                    term: t1,
+                    current: c1,
+                },
+                Error::StaleTerm {
+                    term: t2,
+                    current: c2,
+                },
+            ) => t1 == t2 && c1 == c2,
             _ => false,
         }
     }
@@ -520,11 +520,11 @@ mod tracker;
 pub mod util;
 
 pub use crate::raft::{
-    vote_resp_msg_type, Raft, SoftState, StateRole, CAMPAIGN_ELECTION, CAMPAIGN_PRE_ELECTION,
-    CAMPAIGN_TRANSFER, INVALID_ID, INVALID_INDEX,
+    vote_resp_msg_type, ElectionFailure, ElectionFailureReason, Raft, SoftState, StateRole,
+    CAMPAIGN_ELECTION, CAMPAIGN_PRE_ELECTION, CAMPAIGN_TRANSFER, INVALID_ID, INVALID_INDEX,
 };
 pub use confchange::{Changer, MapChange};
-pub use config::Config;
+pub use config::{Config, OversizedEntryPolicy, SplitVoteTieBreak};
 pub use errors::{Error, Result, StorageError};
 pub use log_unstable::Unstable;
 pub use quorum::joint::Configuration as JointConfig;
@@ -533,11 +533,13 @@ pub use raft_log::{RaftLog, NO_LIMIT};
 pub use raft_proto::eraftpb;
 #[allow(deprecated)]
 pub use raw_node::is_empty_snap;
-pub use raw_node::{LightReady, Peer, RawNode, Ready, SnapshotStatus};
+pub use raw_node::{
+    LightReady, MessageStats, Peer, PeerRole, RawNode, Ready, ReadyReason, SnapshotStatus,
+};
 pub use read_only::{ReadOnlyOption, ReadState};
 pub use status::Status;
 pub use storage::{RaftState, Storage};
-pub use tracker::{Inflights, Progress, ProgressState, ProgressTracker};
+pub use tracker::{Inflights, Progress, ProgressState, ProgressTracker, ProgressTransition};
 pub use util::majority;
 
 pub mod prelude {
@@ -179,6 +179,12 @@ impl Unstable {
             .sum::<usize>();
     }
 
+    /// Reserves capacity for at least `additional` more unstable entries, so appending
+    /// them doesn't reallocate. The capacity is kept when the entries are stabled.
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
     /// Returns a slice of entries between the high and low.
     ///
     /// # Panics
@@ -15,11 +15,12 @@
 // limitations under the License.
 
 use std::cmp;
+use std::collections::VecDeque;
 use std::ops::{Deref, DerefMut};
 
 use crate::eraftpb::{
     ConfChange, ConfChangeV2, ConfState, Entry, EntryType, HardState, Message, MessageType,
-    Snapshot,
+    Snapshot, SnapshotMetadata,
 };
 use protobuf::Message as _;
 use raft_proto::ConfChangeI;
@@ -31,16 +32,19 @@ use fail::fail_point;
 use getset::Getters;
 use slog::{debug, error, info, o, trace, warn};
 
+use super::config::{Config, OversizedEntryPolicy, SplitVoteTieBreak};
 use super::errors::{Error, Result, StorageError};
 use super::raft_log::RaftLog;
 use super::read_only::{ReadOnly, ReadOnlyOption, ReadState};
 use super::storage::Storage;
-use super::Config;
 use crate::confchange::Changer;
 use crate::quorum::VoteResult;
+use crate::raw_node::is_local_msg;
 use crate::util;
 use crate::util::NO_LIMIT;
-use crate::{confchange, Progress, ProgressState, ProgressTracker};
+use crate::{
+    confchange, HashMap, HashSet, Progress, ProgressState, ProgressTracker, ProgressTransition,
+};
 
 // CAMPAIGN_PRE_ELECTION represents the first phase of a normal election when
 // Config.pre_vote is true.
@@ -80,6 +84,16 @@ pub const INVALID_ID: u64 = 0;
 /// A constant represents invalid index of raft log.
 pub const INVALID_INDEX: u64 = 0;
 
+// A read lease delegated to a follower by its leader.
+#[derive(Clone, Copy, Debug)]
+struct ReadLease {
+    leader: u64,
+    // The tick at which the lease expires.
+    expire_tick: u64,
+    // The committed index of the leader when the lease was granted.
+    index: u64,
+}
+
 /// SoftState provides state that is useful for logging and debugging.
 /// The state is volatile and does not need to be persisted to the WAL.
 #[derive(Default, PartialEq, Debug)]
@@ -90,6 +104,37 @@ pub struct SoftState {
     pub raft_state: StateRole,
 }
 
+/// The reason why a campaign failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElectionFailureReason {
+    /// Not enough votes were granted, either rejected or not received before the election
+    /// timed out.
+    InsufficientVotes,
+    /// Votes were rejected by voters that have committed entries beyond the log of this
+    /// node, so it can't win until it catches up.
+    LogNotUpToDate,
+    /// A message of a higher term was received, e.g. from a newer candidate or leader.
+    HigherTerm,
+    /// Another node became leader of the term.
+    LeaderElected,
+}
+
+/// Describes a failed campaign, which is useful for diagnosing why a node can't become
+/// leader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElectionFailure {
+    /// Why the campaign failed.
+    pub reason: ElectionFailureReason,
+    /// The term campaigned for.
+    pub term: u64,
+    /// Whether it was a pre-vote campaign.
+    pub pre_vote: bool,
+    /// The number of votes granted, including the vote of this node.
+    pub granted: usize,
+    /// The number of votes rejected.
+    pub rejected: usize,
+}
+
 /// UncommittedState is used to keep track of information of uncommitted
 /// log entries on 'leader' node
 struct UncommittedState {
@@ -117,20 +162,36 @@ impl UncommittedState {
             return true;
         }
 
+        if self.can_increase_uncommitted_size(ents) {
+            self.uncommitted_size += ents.iter().map(|ent| ent.get_data().len()).sum::<usize>();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn can_increase_uncommitted_size(&self, ents: &[Entry]) -> bool {
+        // fast path
+        if self.is_no_limit() {
+            return true;
+        }
+
         let size: usize = ents.iter().map(|ent| ent.get_data().len()).sum();
+        self.can_increase_uncommitted_size_by(0, size)
+    }
+
+    /// Like `can_increase_uncommitted_size`, but for `size` bytes on top of `pending` bytes
+    /// that are waiting to be appended, e.g. buffered proposals.
+    pub fn can_increase_uncommitted_size_by(&self, pending: usize, size: usize) -> bool {
+        if self.is_no_limit() {
+            return true;
+        }
+        let uncommitted_size = self.uncommitted_size + pending;
 
         // 1. we should never drop an entry without any data(eg. leader election)
         // 2. we should allow at least one uncommitted entry
         // 3. add these entries will not cause size overlimit
-        if size == 0
-            || self.uncommitted_size == 0
-            || size + self.uncommitted_size <= self.max_uncommitted_size
-        {
-            self.uncommitted_size += size;
-            true
-        } else {
-            false
-        }
+        size == 0 || uncommitted_size == 0 || size + uncommitted_size <= self.max_uncommitted_size
     }
 
     pub fn maybe_reduce_uncommitted_size(&mut self, ents: &[Entry]) -> bool {
@@ -226,6 +287,9 @@ pub struct RaftCore<T: Storage> {
     /// only leader keeps heartbeatElapsed.
     heartbeat_elapsed: usize,
 
+    /// Number of ticks since the raft was created, used to time heartbeat round trips.
+    ticks: u64,
+
     /// Whether to check the quorum
     pub check_quorum: bool,
 
@@ -238,6 +302,59 @@ pub struct RaftCore<T: Storage> {
 
     skip_bcast_commit: bool,
     batch_append: bool,
+    strict_msg_term: bool,
+    max_probe_rounds: u64,
+    skip_duplicate_empty_entry: bool,
+    reset_election_on_append: bool,
+    election_paused: bool,
+    leader_lease_ticks: usize,
+    partition_detection_ticks: usize,
+    lazy_leader_noop: bool,
+    split_vote_tie_break: SplitVoteTieBreak,
+    // Whether to wait an extra election timeout before campaigning again, as a preferred
+    // candidate competed in the current term.
+    yield_election: bool,
+    commit_advertise_delay_ticks: usize,
+    redraw_election_timeout: bool,
+    inactive_grace_ticks: usize,
+    buffer_proposals_during_transfer: bool,
+    max_transfer_proposals: usize,
+    // The total data size of the buffered proposals.
+    transfer_proposals_size: usize,
+    // Proposals received during a leader transfer, replayed once it's done.
+    transfer_proposals: Vec<Message>,
+    min_conf_change_interval_ticks: usize,
+    // The tick at which the last conf change was applied.
+    last_conf_change_tick: Option<u64>,
+    max_commit_stall_ticks: usize,
+    // The commit index of the leader as of the last tick, and the number of consecutive
+    // ticks it has stalled with uncommitted entries.
+    stall_commit: u64,
+    commit_stall_ticks: usize,
+    follower_lease_ticks: usize,
+    // The read lease delegated to this follower by the leader, if any.
+    read_lease: Option<ReadLease>,
+    // The tick at which the earliest unanswered read lease request was sent.
+    read_lease_requested_at: Option<u64>,
+    // The followers holding a read lease delegated by this leader, along with the tick
+    // at which the leader stops honoring it.
+    lease_holders: HashMap<u64, u64>,
+    // The tick before which a newly elected leader doesn't commit, so that the leases
+    // delegated by a previous leader have expired.
+    lease_commit_gate: u64,
+    snapshot_entries_threshold: u64,
+    oversized_entry_policy: OversizedEntryPolicy,
+    max_pipeline_depth: usize,
+    strict_mode: bool,
+    // The number of consecutive ticks without a known leader.
+    leaderless_ticks: usize,
+    // The metadata of the last snapshot sent to each peer.
+    sent_snapshots: HashMap<u64, SnapshotMetadata>,
+    // The commit index advertised to followers, which lags behind the committed index by
+    // commit_advertise_delay_ticks.
+    advertised_commit: u64,
+    // The commit indexes waiting to be advertised, along with the tick they are due at.
+    pending_advertised_commits: VecDeque<(u64, u64)>,
 
     heartbeat_timeout: usize,
     election_timeout: usize,
@@ -260,8 +377,35 @@ pub struct RaftCore<T: Storage> {
 
     /// Max size per committed entries in a `Read`.
     pub(crate) max_committed_size_per_ready: u64,
+
+    progress_observer: Option<ProgressObserver>,
+    learner_caught_up_observer: Option<LearnerCaughtUpObserver>,
+
+    /// The last known matched indexes of followers, used to seed their progress the next
+    /// time this node becomes leader.
+    progress_hints: HashMap<u64, u64>,
+
+    /// The number of times this node stepped down from leader on an append of a higher term.
+    higher_term_append_step_downs: u64,
+
+    /// The number of received snapshots ignored because the log already covered them.
+    duplicate_snapshots_ignored: u64,
+
+    /// The number of appends received with a commit index lower than the local one.
+    stale_commit_appends: u64,
+
+    /// Whether a vote of the current campaign is rejected by a voter whose log is ahead.
+    vote_rejected_for_log: bool,
+    /// The most recent failed campaign.
+    last_election_failure: Option<ElectionFailure>,
 }
 
+/// The callback invoked on each progress state transition of a follower.
+type ProgressObserver = Box<dyn FnMut(&ProgressTransition) + Send>;
+
+/// The callback invoked with the ID and the matched index of a learner that caught up.
+type LearnerCaughtUpObserver = Box<dyn FnMut(u64, u64) + Send>;
+
 /// A struct that represents the raft consensus itself. Stores details concerning the current
 /// and possible state the system can take.
 pub struct Raft<T: Storage> {
@@ -342,7 +486,11 @@ impl<T: Storage> Raft<T> {
                 promotable: false,
                 check_quorum: c.check_quorum,
                 pre_vote: c.pre_vote,
-                read_only: ReadOnly::new(c.read_only_option),
+                read_only: ReadOnly::new(
+                    c.read_only_option,
+                    c.max_pending_read_count as usize,
+                    c.max_pending_read_count_per_peer as usize,
+                ),
                 heartbeat_timeout: c.heartbeat_tick,
                 election_timeout: c.election_tick,
                 leader_id: Default::default(),
@@ -352,11 +500,47 @@ impl<T: Storage> Raft<T> {
                 pending_conf_index: Default::default(),
                 vote: Default::default(),
                 heartbeat_elapsed: Default::default(),
+                ticks: 0,
                 randomized_election_timeout: Default::default(),
                 min_election_timeout: c.min_election_tick(),
                 max_election_timeout: c.max_election_tick(),
                 skip_bcast_commit: c.skip_bcast_commit,
                 batch_append: c.batch_append,
+                strict_msg_term: c.strict_msg_term,
+                max_probe_rounds: c.max_probe_rounds,
+                skip_duplicate_empty_entry: c.skip_duplicate_empty_entry,
+                reset_election_on_append: c.reset_election_on_append,
+                election_paused: false,
+                leader_lease_ticks: c.leader_lease_ticks,
+                partition_detection_ticks: c.partition_detection_ticks(),
+                lazy_leader_noop: c.lazy_leader_noop,
+                split_vote_tie_break: c.split_vote_tie_break,
+                yield_election: false,
+                commit_advertise_delay_ticks: c.commit_advertise_delay_ticks,
+                redraw_election_timeout: c.redraw_election_timeout,
+                inactive_grace_ticks: c.inactive_grace_ticks,
+                buffer_proposals_during_transfer: c.buffer_proposals_during_transfer,
+                max_transfer_proposals: c.max_transfer_proposals,
+                transfer_proposals_size: 0,
+                transfer_proposals: vec![],
+                min_conf_change_interval_ticks: c.min_conf_change_interval_ticks,
+                last_conf_change_tick: None,
+                max_commit_stall_ticks: c.max_commit_stall_ticks,
+                stall_commit: 0,
+                commit_stall_ticks: 0,
+                follower_lease_ticks: c.follower_lease_ticks,
+                read_lease: None,
+                read_lease_requested_at: None,
+                lease_holders: HashMap::default(),
+                lease_commit_gate: 0,
+                snapshot_entries_threshold: c.snapshot_entries_threshold,
+                oversized_entry_policy: c.oversized_entry_policy,
+                max_pipeline_depth: c.max_pipeline_depth,
+                strict_mode: c.strict_mode,
+                leaderless_ticks: 0,
+                sent_snapshots: HashMap::default(),
+                advertised_commit: 0,
+                pending_advertised_commits: VecDeque::new(),
                 logger,
                 priority: c.priority,
                 uncommitted_state: UncommittedState {
@@ -365,6 +549,14 @@ impl<T: Storage> Raft<T> {
                     last_log_tail_index: 0,
                 },
                 max_committed_size_per_ready: c.max_committed_size_per_ready,
+                progress_observer: None,
+                learner_caught_up_observer: None,
+                progress_hints: Default::default(),
+                higher_term_append_step_downs: 0,
+                duplicate_snapshots_ignored: 0,
+                stale_commit_appends: 0,
+                vote_rejected_for_log: false,
+                last_election_failure: None,
             },
         };
         confchange::restore(&mut r.prs, r.r.raft_log.last_index(), conf_state)?;
@@ -381,6 +573,14 @@ impl<T: Storage> Raft<T> {
         if raft_state.hard_state != HardState::default() {
             r.load_state(&raft_state.hard_state);
         }
+        if c.applied > r.raft_log.committed {
+            // Entries can't be applied before they are committed, so either the storage or
+            // the applied index persisted by the application is corrupted.
+            return Err(Error::ConfigInvalid(format!(
+                "applied index {} is greater than commit index {}",
+                c.applied, r.raft_log.committed
+            )));
+        }
         if c.applied > 0 {
             r.commit_apply(c.applied);
         }
@@ -498,6 +698,164 @@ impl<T: Storage> Raft<T> {
         self.skip_bcast_commit = skip;
     }
 
+    /// Sets the callback invoked whenever the progress state of a follower transitions,
+    /// e.g. from `Probe` to `Snapshot`. It's only invoked while this node is leader.
+    pub fn set_progress_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&ProgressTransition) + Send + 'static,
+    {
+        self.progress_observer = Some(Box::new(observer));
+    }
+
+    /// Sets the callback invoked with the ID and the matched index of a learner when its
+    /// matched index first reaches the commit index, e.g. to promote it to a voter.
+    ///
+    /// It's only invoked while this node is leader, once per learner and leadership. Whether
+    /// a learner has caught up is also reflected by `Progress::caught_up`.
+    pub fn set_learner_caught_up_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        self.learner_caught_up_observer = Some(Box::new(observer));
+    }
+
+    /// Sets the last known matched indexes of followers, e.g. persisted before a restart.
+    ///
+    /// The next time this node becomes leader, it probes each hinted follower from its
+    /// hinted index instead of the end of the log, which saves the round trips of
+    /// backtracking a lagging follower. A wrong hint is corrected by the rejection of the
+    /// probe like any other. Hints are never trusted for commitment.
+    pub fn set_progress_hints<I>(&mut self, hints: I)
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+    {
+        self.progress_hints = hints.into_iter().collect();
+    }
+
+    /// Overrides `max_inflight_msgs` for the given peer, e.g. to use a smaller window for a
+    /// slow or distant follower. The override lasts until the peer is removed.
+    ///
+    /// If more messages than `limit` are already in flight, no more are sent until enough
+    /// of them are acknowledged. Unknown peers are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn set_inflight_limit(&mut self, id: u64, limit: usize) {
+        assert!(
+            limit > 0,
+            "inflight limit of peer {} must be greater than 0",
+            id
+        );
+        if let Some(pr) = self.mut_prs().get_mut(id) {
+            pr.ins.set_cap(limit);
+        }
+    }
+
+    /// Stops the election timer from starting campaigns, e.g. during maintenance. The node
+    /// still follows the leader and can be asked to campaign explicitly.
+    pub fn pause_election(&mut self) {
+        self.election_paused = true;
+    }
+
+    /// Resumes the election timer paused by `pause_election`. A whole election timeout
+    /// needs to pass before the node campaigns.
+    pub fn resume_election(&mut self) {
+        if self.election_paused {
+            self.election_paused = false;
+            self.election_elapsed = 0;
+        }
+    }
+
+    /// Replaces the logger of the raft, along with the ones of its log and progress tracker.
+    ///
+    /// Like in `Raft::new`, the id of the raft is attached to every record.
+    pub fn set_logger(&mut self, logger: Logger) {
+        let logger = logger.new(o!("raft_id" => self.id));
+        self.prs.logger = logger.clone();
+        self.r.raft_log.unstable.logger = logger.clone();
+        self.r.logger = logger;
+    }
+
+    /// The number of times this node stepped down from leader because it received an append
+    /// of a higher term, i.e. another leader has been elected without it noticing.
+    #[inline]
+    pub fn higher_term_append_step_downs(&self) -> u64 {
+        self.higher_term_append_step_downs
+    }
+
+    /// The number of received snapshots that were ignored because the log already contained
+    /// them, e.g. a snapshot retransmitted after the first copy has been restored.
+    #[inline]
+    pub fn duplicate_snapshots_ignored(&self) -> u64 {
+        self.duplicate_snapshots_ignored
+    }
+
+    /// Whether protocol violations panic, see `Config::strict_mode`.
+    #[inline]
+    pub fn strict_mode(&self) -> bool {
+        self.strict_mode
+    }
+
+    /// The number of appends received with a commit index lower than the local one, e.g.
+    /// as they were reordered behind a newer append. Their commit index is ignored.
+    #[inline]
+    pub fn stale_commit_appends(&self) -> u64 {
+        self.stale_commit_appends
+    }
+
+    /// Whether the log holds more entries since the last snapshot than
+    /// `Config::snapshot_entries_threshold`, so that taking a snapshot is suggested.
+    pub fn should_snapshot(&self) -> bool {
+        let entries = self.raft_log.last_index() + 1 - self.raft_log.first_index();
+        entries > self.snapshot_entries_threshold
+    }
+
+    /// Returns the reason and the vote tally of the most recent failed campaign, if any.
+    #[inline]
+    pub fn last_election_failure(&self) -> Option<ElectionFailure> {
+        self.last_election_failure
+    }
+
+    /// Returns the number of consecutive ticks this node has gone without knowing the
+    /// leader, or 0 if the leader is known.
+    #[inline]
+    pub fn leaderless_ticks(&self) -> usize {
+        if self.leader_id != INVALID_ID {
+            return 0;
+        }
+        self.leaderless_ticks
+    }
+
+    /// Whether the election timer is paused.
+    #[inline]
+    pub fn is_election_paused(&self) -> bool {
+        self.election_paused
+    }
+
+    /// Whether this node is a leader that likely lost contact with a quorum.
+    ///
+    /// A peer is suspected to be unreachable once a heartbeat sent to it stays unanswered
+    /// for `Config::partition_detection_ticks`. This is reported well before check quorum
+    /// steps the leader down, so clients can stop serving reads early.
+    pub fn is_likely_partitioned(&self) -> bool {
+        if self.state != StateRole::Leader {
+            return false;
+        }
+        let self_id = self.id;
+        let ticks = self.ticks;
+        let window = self.partition_detection_ticks as u64;
+        let reachable: HashSet<u64> = self
+            .prs()
+            .iter()
+            .filter(|&(&id, pr)| {
+                id == self_id || pr.heartbeat_sent_tick.map_or(true, |t| ticks - t < window)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        !self.prs().has_quorum(&reachable)
+    }
+
     /// Set whether batch append msg at runtime.
     #[inline]
     pub fn set_batch_append(&mut self, batch_append: bool) {
@@ -550,6 +908,74 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    /// Returns the metadata of the snapshot being transferred to peer `id`, if it's in
+    /// snapshot state.
+    pub fn pending_snapshot_for(&self, id: u64) -> Option<&SnapshotMetadata> {
+        let pr = self.prs.get(id)?;
+        if pr.state != ProgressState::Snapshot {
+            return None;
+        }
+        self.sent_snapshots
+            .get(&id)
+            .filter(|meta| meta.index == pr.pending_snapshot)
+    }
+
+    /// Returns the IDs of the peers that have been in snapshot state for at least
+    /// `threshold_ticks`, e.g. because the snapshot is lost and never reported.
+    pub fn stuck_snapshot_peers(&self, threshold_ticks: usize) -> Vec<u64> {
+        if self.state != StateRole::Leader {
+            return vec![];
+        }
+        let ticks = self.ticks;
+        let mut ids: Vec<u64> = self
+            .prs()
+            .iter()
+            .filter(|(_, pr)| {
+                pr.state == ProgressState::Snapshot
+                    && pr
+                        .snapshot_sent_tick
+                        .map_or(false, |t| ticks - t >= threshold_ticks as u64)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Returns the IDs of the peers that are not recently active and haven't been heard from
+    /// within `inactive_grace_ticks`, sorted. Only the leader tracks the activity of its
+    /// peers, so this is always empty on other nodes.
+    pub fn inactive_peers(&self) -> Vec<u64> {
+        if self.state != StateRole::Leader {
+            return vec![];
+        }
+        let (ticks, grace) = (self.ticks, self.inactive_grace_ticks as u64);
+        let mut ids: Vec<u64> = self
+            .prs()
+            .iter()
+            .filter(|&(&id, pr)| {
+                id != self.id
+                    && !pr.recent_active
+                    && !pr.last_active_tick.map_or(false, |t| ticks - t < grace)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Recomputes the commit index from the matched indexes of the current progress, e.g.
+    /// after the progress is changed manually, and returns the commit index.
+    ///
+    /// Only a leader advances its commit index, in which case the new commit index is
+    /// broadcast to the followers.
+    pub fn recompute_commit(&mut self) -> u64 {
+        if StateRole::Leader == self.state && self.maybe_commit() {
+            self.bcast_append();
+        }
+        self.raft_log.committed
+    }
+
     /// Checks whether the raft group is using group commit and consistent
     /// over group.
     ///
@@ -598,6 +1024,23 @@ impl<T: Storage> Raft<T> {
 }
 
 impl<T: Storage> RaftCore<T> {
+    fn notify_progress_transition(
+        &mut self,
+        id: u64,
+        from: ProgressState,
+        to: ProgressState,
+        index: u64,
+    ) {
+        if let Some(observer) = self.progress_observer.as_mut() {
+            observer(&ProgressTransition {
+                id,
+                from,
+                to,
+                index,
+            });
+        }
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message, msgs: &mut Vec<Message>) {
         debug!(
@@ -690,6 +1133,8 @@ impl<T: Storage> RaftCore<T> {
             fatal!(self.logger, "need non-empty snapshot");
         }
         let (sindex, sterm) = (snapshot.get_metadata().index, snapshot.get_metadata().term);
+        self.sent_snapshots
+            .insert(to, snapshot.get_metadata().clone());
         m.set_snapshot(snapshot);
         debug!(
             self.logger,
@@ -701,7 +1146,10 @@ impl<T: Storage> RaftCore<T> {
             to = to;
             "progress" => ?pr,
         );
+        let from = pr.state;
         pr.become_snapshot(sindex);
+        pr.snapshot_sent_tick = Some(self.ticks);
+        self.notify_progress_transition(to, from, ProgressState::Snapshot, sindex);
         debug!(
             self.logger,
             "paused sending replication messages to {}",
@@ -711,6 +1159,16 @@ impl<T: Storage> RaftCore<T> {
         true
     }
 
+    // advertised_commit returns the commit index to send to followers, which lags behind
+    // the committed index if commit_advertise_delay_ticks is set.
+    fn advertised_commit(&self) -> u64 {
+        if self.commit_advertise_delay_ticks == 0 {
+            self.raft_log.committed
+        } else {
+            self.advertised_commit
+        }
+    }
+
     fn prepare_send_entries(
         &mut self,
         m: &mut Message,
@@ -722,7 +1180,7 @@ impl<T: Storage> RaftCore<T> {
         m.index = pr.next_idx - 1;
         m.log_term = term;
         m.set_entries(ents.into());
-        m.commit = self.raft_log.committed;
+        m.commit = self.advertised_commit();
         if !m.entries.is_empty() {
             let last = m.entries.last().unwrap().index;
             pr.update_state(last);
@@ -745,13 +1203,23 @@ impl<T: Storage> RaftCore<T> {
                     if !util::is_continuous_ents(msg, ents) {
                         return is_batched;
                     }
+                    if self.max_msg_size != NO_LIMIT && !msg.entries.is_empty() {
+                        let size = util::message_size(msg) as u64
+                            + ents
+                                .iter()
+                                .map(|e| u64::from(e.compute_size()))
+                                .sum::<u64>();
+                        if size > self.max_msg_size {
+                            return is_batched;
+                        }
+                    }
                     let mut batched_entries: Vec<_> = msg.take_entries().into();
                     batched_entries.append(ents);
                     msg.set_entries(batched_entries.into());
                     let last_idx = msg.entries.last().unwrap().index;
                     pr.update_state(last_idx);
                 }
-                msg.commit = self.raft_log.committed;
+                msg.commit = self.advertised_commit();
                 is_batched = true;
                 break;
             }
@@ -765,6 +1233,13 @@ impl<T: Storage> RaftCore<T> {
         self.maybe_send_append(to, pr, true, msgs);
     }
 
+    // is_send_paused checks whether sending appends to the peer is paused, either by its
+    // progress state or by a full replication pipeline.
+    fn is_send_paused(&self, pr: &Progress) -> bool {
+        pr.is_paused()
+            || (self.max_pipeline_depth > 0 && pr.pipeline_depth() >= self.max_pipeline_depth)
+    }
+
     /// Sends an append RPC with new entries to the given peer,
     /// if necessary. Returns true if a message was sent. The allow_empty
     /// argument controls whether messages with no entries will be sent
@@ -777,7 +1252,7 @@ impl<T: Storage> RaftCore<T> {
         allow_empty: bool,
         msgs: &mut Vec<Message>,
     ) -> bool {
-        if pr.is_paused() {
+        if self.is_send_paused(pr) {
             trace!(
                 self.logger,
                 "Skipping sending to {to}, it's paused",
@@ -788,8 +1263,11 @@ impl<T: Storage> RaftCore<T> {
         }
         let mut m = Message::default();
         m.to = to;
-        if pr.pending_request_snapshot != INVALID_INDEX {
+        if pr.pending_request_snapshot != INVALID_INDEX
+            || (pr.state == ProgressState::Probe && pr.probe_rounds >= self.max_probe_rounds)
+        {
             // Check pending request snapshot first to avoid unnecessary loading entries.
+            // Also give up probing a follower that has rejected too many appends.
             if !self.prepare_send_snapshot(&mut m, pr, to) {
                 return false;
             }
@@ -822,7 +1300,7 @@ impl<T: Storage> RaftCore<T> {
     fn send_heartbeat(
         &mut self,
         to: u64,
-        pr: &Progress,
+        pr: &mut Progress,
         ctx: Option<Vec<u8>>,
         msgs: &mut Vec<Message>,
     ) {
@@ -835,11 +1313,12 @@ impl<T: Storage> RaftCore<T> {
         let mut m = Message::default();
         m.to = to;
         m.set_msg_type(MessageType::MsgHeartbeat);
-        let commit = cmp::min(pr.matched, self.raft_log.committed);
+        let commit = cmp::min(pr.matched, self.advertised_commit());
         m.commit = commit;
         if let Some(context) = ctx {
             m.context = context.into();
         }
+        pr.heartbeat_sent(self.ticks);
         self.send(m, msgs);
     }
 }
@@ -891,13 +1370,29 @@ impl<T: Storage> Raft<T> {
     /// Attempts to advance the commit index. Returns true if the commit index
     /// changed (in which case the caller should call `r.bcast_append`).
     pub fn maybe_commit(&mut self) -> bool {
-        let mci = self.mut_prs().maximal_committed_index().0;
+        if self.ticks < self.lease_commit_gate {
+            return false;
+        }
+        let mut mci = self.mut_prs().maximal_committed_index().0;
+        // Entries are only committed once every follower holding a read lease has them.
+        let ticks = self.ticks;
+        for (id, &expire) in &self.lease_holders {
+            if expire > ticks {
+                if let Some(pr) = self.prs.get(*id) {
+                    mci = cmp::min(mci, pr.matched);
+                }
+            }
+        }
         if self.r.raft_log.maybe_commit(mci, self.r.term) {
             let (self_id, committed) = (self.id, self.raft_log.committed);
             self.mut_prs()
                 .get_mut(self_id)
                 .unwrap()
                 .update_committed(committed);
+            if self.commit_advertise_delay_ticks > 0 {
+                let due = self.ticks + self.commit_advertise_delay_ticks as u64;
+                self.pending_advertised_commits.push_back((due, committed));
+            }
             return true;
         }
         false
@@ -943,9 +1438,12 @@ impl<T: Storage> Raft<T> {
         if self.term != term {
             self.term = term;
             self.vote = INVALID_ID;
+            self.yield_election = false;
         }
         self.leader_id = INVALID_ID;
-        self.reset_randomized_election_timeout();
+        if self.redraw_election_timeout || self.randomized_election_timeout == 0 {
+            self.reset_randomized_election_timeout();
+        }
         self.election_elapsed = 0;
         self.heartbeat_elapsed = 0;
 
@@ -954,11 +1452,23 @@ impl<T: Storage> Raft<T> {
         self.prs.reset_votes();
 
         self.pending_conf_index = 0;
-        self.read_only = ReadOnly::new(self.read_only.option);
+        self.read_only = ReadOnly::new(
+            self.read_only.option,
+            self.read_only.max_pending_read_count,
+            self.read_only.max_pending_read_count_per_peer,
+        );
         self.pending_request_snapshot = INVALID_INDEX;
 
         let last_index = self.raft_log.last_index();
         let committed = self.raft_log.committed;
+        self.advertised_commit = committed;
+        self.pending_advertised_commits.clear();
+        self.stall_commit = committed;
+        self.commit_stall_ticks = 0;
+        self.read_lease = None;
+        self.read_lease_requested_at = None;
+        self.lease_holders.clear();
+        self.sent_snapshots.clear();
         let persisted = self.raft_log.persisted;
         let self_id = self.id;
         for (&id, mut pr) in self.mut_prs().iter_mut() {
@@ -1022,12 +1532,47 @@ impl<T: Storage> Raft<T> {
 
     /// Returns true to indicate that there will probably be some readiness need to be handled.
     pub fn tick(&mut self) -> bool {
-        match self.state {
+        self.ticks += 1;
+        if self.leader_id == INVALID_ID {
+            self.leaderless_ticks += 1;
+        } else {
+            self.leaderless_ticks = 0;
+        }
+        let has_ready = match self.state {
             StateRole::Follower | StateRole::PreCandidate | StateRole::Candidate => {
                 self.tick_election()
             }
             StateRole::Leader => self.tick_heartbeat(),
+        };
+        self.release_transfer_proposals() || has_ready
+    }
+
+    // Replays the proposals buffered during a leader transfer once it's done. They are
+    // proposed again if the transfer was aborted, or forwarded to the new leader as soon
+    // as it's known.
+    fn release_transfer_proposals(&mut self) -> bool {
+        if self.transfer_proposals.is_empty() {
+            return false;
         }
+        match self.state {
+            StateRole::Leader if self.lead_transferee.is_none() => {}
+            StateRole::Follower if self.leader_id != INVALID_ID => {}
+            _ => return false,
+        }
+        self.transfer_proposals_size = 0;
+        for m in std::mem::take(&mut self.transfer_proposals) {
+            let entries = m.entries.len();
+            if let Err(e) = self.step(m) {
+                // The client isn't told, so it has to wait for its proposal to time out.
+                warn!(
+                    self.logger,
+                    "dropped proposal buffered during leader transfer";
+                    "error" => ?e,
+                    "entries" => entries,
+                );
+            }
+        }
+        true
     }
 
     // TODO: revoke pub when there is a better way to test.
@@ -1036,11 +1581,17 @@ impl<T: Storage> Raft<T> {
     /// Returns true to indicate that there will probably be some readiness need to be handled.
     pub fn tick_election(&mut self) -> bool {
         self.election_elapsed += 1;
-        if !self.pass_election_timeout() || !self.promotable {
+        if !self.pass_election_timeout() || !self.promotable || self.election_paused {
             return false;
         }
 
         self.election_elapsed = 0;
+        if self.yield_election {
+            // Give the preferred candidate a head start in the next term.
+            self.yield_election = false;
+            return false;
+        }
+        self.record_election_failure(ElectionFailureReason::InsufficientVotes);
         let m = new_message(INVALID_ID, MessageType::MsgHup, Some(self.id));
         let _ = self.step(m);
         true
@@ -1068,6 +1619,30 @@ impl<T: Storage> Raft<T> {
         if self.state != StateRole::Leader {
             return has_ready;
         }
+        if self.max_commit_stall_ticks > 0 && self.check_commit_stall() {
+            return true;
+        }
+        if self.follower_lease_ticks > 0
+            && self.ticks == self.lease_commit_gate
+            && self.maybe_commit()
+        {
+            self.bcast_append();
+        }
+        if !self.lease_holders.is_empty() {
+            let (ticks, holders) = (self.ticks, self.lease_holders.len());
+            self.lease_holders.retain(|_, expire| *expire > ticks);
+            if self.lease_holders.len() < holders && self.maybe_commit() {
+                self.bcast_append();
+            }
+        }
+
+        while let Some(&(due, commit)) = self.pending_advertised_commits.front() {
+            if due > self.ticks {
+                break;
+            }
+            self.advertised_commit = commit;
+            self.pending_advertised_commits.pop_front();
+        }
 
         if self.heartbeat_elapsed >= self.heartbeat_timeout {
             self.heartbeat_elapsed = 0;
@@ -1078,6 +1653,52 @@ impl<T: Storage> Raft<T> {
         has_ready
     }
 
+    // check_commit_stall steps down if the commit index hasn't advanced for
+    // max_commit_stall_ticks while there are entries waiting to be committed. Returns
+    // whether it stepped down.
+    fn check_commit_stall(&mut self) -> bool {
+        let committed = self.raft_log.committed;
+        if committed != self.stall_commit || self.raft_log.last_index() == committed {
+            self.stall_commit = committed;
+            self.commit_stall_ticks = 0;
+            return false;
+        }
+        self.commit_stall_ticks += 1;
+        if self.commit_stall_ticks < self.max_commit_stall_ticks {
+            return false;
+        }
+        warn!(
+            self.logger,
+            "stepped down to follower since the commit index hasn't advanced for {ticks} ticks",
+            ticks = self.commit_stall_ticks;
+            "committed" => committed,
+            "last index" => self.raft_log.last_index(),
+        );
+        let term = self.term;
+        self.become_follower(term, INVALID_ID);
+        true
+    }
+
+    // prefers_candidate checks whether the candidate that sent the vote request m should
+    // win the next election over this competing candidate according to the tie break.
+    fn prefers_candidate(&self, m: &Message) -> bool {
+        let preferred = match self.split_vote_tie_break {
+            SplitVoteTieBreak::Disabled => return false,
+            SplitVoteTieBreak::LowestId => m.from < self.id,
+            SplitVoteTieBreak::HighestId => m.from > self.id,
+        };
+        preferred && self.raft_log.is_up_to_date(m.index, m.log_term)
+    }
+
+    // append_noop appends an empty entry, which commits the term of this leader.
+    fn append_noop(&mut self) {
+        // No need to check result because append_entry never refuse entries
+        // which size is zero
+        if !self.append_entry(&mut [Entry::default()]) {
+            panic!("appending an empty entry should never be dropped")
+        }
+    }
+
     /// Converts this node to a follower.
     pub fn become_follower(&mut self, term: u64, leader_id: u64) {
         let pending_request_snapshot = self.pending_request_snapshot;
@@ -1180,6 +1801,18 @@ impl<T: Storage> Raft<T> {
         let id = self.id;
         self.mut_prs().get_mut(id).unwrap().become_replicate();
 
+        // Probe the hinted followers from their last known match. Hints the log can't
+        // be probed from are ignored.
+        let first_index = self.raft_log.first_index();
+        for (hint_id, hint) in std::mem::take(&mut self.r.progress_hints) {
+            if hint_id == id || hint + 1 < first_index || hint >= last_index {
+                continue;
+            }
+            if let Some(pr) = self.mut_prs().get_mut(hint_id) {
+                pr.next_idx = hint + 1;
+            }
+        }
+
         // Conservatively set the pending_conf_index to the last index in the
         // log. There may or may not be a pending config change, but it's
         // safe to delay any future proposals until we commit all our
@@ -1187,10 +1820,13 @@ impl<T: Storage> Raft<T> {
         // could be expensive.
         self.pending_conf_index = last_index;
 
-        // No need to check result because append_entry never refuse entries
-        // which size is zero
-        if !self.append_entry(&mut [Entry::default()]) {
-            panic!("appending an empty entry should never be dropped")
+        // A follower may still serve reads under a lease delegated by a previous leader,
+        // which honors it for at most twice the lease.
+        self.lease_commit_gate = self.ticks + 2 * self.follower_lease_ticks as u64;
+
+        // The empty entry is appended by the first read index request instead.
+        if !self.lazy_leader_noop {
+            self.append_noop();
         }
 
         info!(
@@ -1223,6 +1859,7 @@ impl<T: Storage> Raft<T> {
             self.become_candidate();
             (MessageType::MsgRequestVote, self.term)
         };
+        self.vote_rejected_for_log = false;
         let self_id = self.id;
         if VoteResult::Won == self.poll(self_id, vote_msg, true) {
             // We won the election after voting for ourselves (which must mean that
@@ -1281,6 +1918,21 @@ impl<T: Storage> Raft<T> {
         // Handle the message term, which may result in our stepping down to a follower.
         if m.term == 0 {
             // local message
+            if self.strict_msg_term
+                && !is_local_msg(m.get_msg_type())
+                && m.get_msg_type() != MessageType::MsgPropose
+                && m.get_msg_type() != MessageType::MsgReadIndex
+                && m.get_msg_type() != MessageType::MsgTransferLeader
+            {
+                warn!(
+                    self.logger,
+                    "ignored a message without term from {from}",
+                    from = m.from;
+                    "term" => self.term,
+                    "msg type" => ?m.get_msg_type(),
+                );
+                return Err(Error::StepTermMissing);
+            }
         } else if m.term > self.term {
             if m.get_msg_type() == MessageType::MsgRequestVote
                 || m.get_msg_type() == MessageType::MsgRequestPreVote
@@ -1337,13 +1989,23 @@ impl<T: Storage> Raft<T> {
                     "message_term" => m.term,
                     "msg type" => ?m.get_msg_type(),
                 );
-                if m.get_msg_type() == MessageType::MsgAppend
-                    || m.get_msg_type() == MessageType::MsgHeartbeat
-                    || m.get_msg_type() == MessageType::MsgSnapshot
-                {
-                    self.become_follower(m.term, m.from);
-                } else {
-                    self.become_follower(m.term, INVALID_ID);
+                self.record_election_failure(ElectionFailureReason::HigherTerm);
+                match m.get_msg_type() {
+                    MessageType::MsgAppend
+                    | MessageType::MsgHeartbeat
+                    | MessageType::MsgSnapshot => {
+                        if self.state == StateRole::Leader
+                            && m.get_msg_type() == MessageType::MsgAppend
+                        {
+                            // A new leader has replicated entries already. The term is
+                            // updated before the entries are handled, so that they are
+                            // appended below as a follower of the new leader, truncating
+                            // the conflicting entries of this stale leader if any.
+                            self.higher_term_append_step_downs += 1;
+                        }
+                        self.become_follower(m.term, m.from);
+                    }
+                    _ => self.become_follower(m.term, INVALID_ID),
                 }
             }
         } else if m.term < self.term {
@@ -1413,6 +2075,10 @@ impl<T: Storage> Raft<T> {
         #[cfg(feature = "failpoints")]
         fail_point!("before_step");
 
+        if self.strict_mode {
+            self.check_single_leader(&m);
+        }
+
         match m.get_msg_type() {
             MessageType::MsgHup => self.hup(false),
             MessageType::MsgRequestVote | MessageType::MsgRequestPreVote => {
@@ -1458,6 +2124,13 @@ impl<T: Storage> Raft<T> {
                     to_send.commit_term = commit_term;
                     self.r.send(to_send, &mut self.msgs);
                     self.maybe_commit_by_vote(&m);
+                    if self.state == StateRole::Candidate
+                        && m.get_msg_type() == MessageType::MsgRequestVote
+                        && m.term == self.term
+                        && self.prefers_candidate(&m)
+                    {
+                        self.yield_election = true;
+                    }
                 }
             }
             _ => match self.state {
@@ -1466,9 +2139,58 @@ impl<T: Storage> Raft<T> {
                 StateRole::Leader => self.step_leader(m)?,
             },
         }
+        self.release_transfer_proposals();
         Ok(())
     }
 
+    // check_single_leader panics if `m` comes from a leader of the current term other than
+    // the one this node knows of, i.e. two leaders are elected in the same term.
+    fn check_single_leader(&self, m: &Message) {
+        if m.term != self.term {
+            return;
+        }
+        match m.get_msg_type() {
+            MessageType::MsgAppend | MessageType::MsgHeartbeat | MessageType::MsgSnapshot => {}
+            _ => return,
+        }
+        let leader = if self.state == StateRole::Leader {
+            self.id
+        } else {
+            self.leader_id
+        };
+        if leader != INVALID_ID && leader != m.from {
+            fatal!(
+                self.logger,
+                "two leaders {} and {} in term {}",
+                leader,
+                m.from,
+                self.term
+            );
+        }
+    }
+
+    // check_committed_match panics if the leader `from` claims the entry at `index` has a
+    // term other than the one of the committed entry in the log. Accepting its log would
+    // move the commit index backwards.
+    fn check_committed_match(&self, from: u64, index: u64, term: u64) {
+        if index > self.raft_log.committed {
+            return;
+        }
+        // The term is 0 if the entry is unknown, e.g. as it's compacted.
+        if let Ok(t) = self.raft_log.term(index) {
+            if t != 0 && t != term {
+                fatal!(
+                    self.logger,
+                    "commit index regressed: leader {} has term {} at committed index {} of term {}",
+                    from,
+                    term,
+                    index,
+                    t
+                );
+            }
+        }
+    }
+
     fn hup(&mut self, transfer_leader: bool) {
         if self.state == StateRole::Leader {
             debug!(
@@ -1556,6 +2278,31 @@ impl<T: Storage> Raft<T> {
         );
     }
 
+    fn maybe_notify_learner_caught_up(&mut self, id: u64) {
+        if !self.prs.conf().learners.contains(&id) {
+            return;
+        }
+        let committed = self.r.raft_log.committed;
+        let pr = match self.prs.get_mut(id) {
+            Some(pr) => pr,
+            None => return,
+        };
+        if pr.caught_up || pr.matched < committed {
+            return;
+        }
+        pr.caught_up = true;
+        info!(
+            self.r.logger,
+            "learner {id} caught up",
+            id = id;
+            "matched" => pr.matched,
+            "committed" => committed,
+        );
+        if let Some(observer) = self.r.learner_caught_up_observer.as_mut() {
+            observer(id, pr.matched);
+        }
+    }
+
     fn handle_append_response(&mut self, m: &Message) {
         let mut next_probe_index: u64 = m.reject_hint;
         // pull out find_conflict_by_term for immutable borrow
@@ -1672,6 +2419,7 @@ impl<T: Storage> Raft<T> {
             }
         };
         pr.recent_active = true;
+        pr.last_active_tick = Some(self.r.ticks);
 
         // update followers committed index via append response
         pr.update_committed(m.commit);
@@ -1715,19 +2463,33 @@ impl<T: Storage> Raft<T> {
                 );
                 if pr.state == ProgressState::Replicate {
                     pr.become_probe();
+                    self.r.notify_progress_transition(
+                        m.from,
+                        ProgressState::Replicate,
+                        ProgressState::Probe,
+                        m.index,
+                    );
                 }
                 self.send_append(m.from);
             }
             return;
         }
 
-        let old_paused = pr.is_paused();
+        let old_paused = self.r.is_send_paused(pr);
         if !pr.maybe_update(m.index) {
             return;
         }
 
         match pr.state {
-            ProgressState::Probe => pr.become_replicate(),
+            ProgressState::Probe => {
+                pr.become_replicate();
+                self.r.notify_progress_transition(
+                    m.from,
+                    ProgressState::Probe,
+                    ProgressState::Replicate,
+                    m.index,
+                );
+            }
             ProgressState::Snapshot => {
                 if pr.maybe_snapshot_abort() {
                     debug!(
@@ -1736,7 +2498,14 @@ impl<T: Storage> Raft<T> {
                         from = m.from;
                         "progress" => ?pr,
                     );
+                    let pending_snapshot = pr.pending_snapshot;
                     pr.become_probe();
+                    self.r.notify_progress_transition(
+                        m.from,
+                        ProgressState::Snapshot,
+                        ProgressState::Probe,
+                        pending_snapshot,
+                    );
                 }
             }
             ProgressState::Replicate => pr.ins.free_to(m.get_index()),
@@ -1749,6 +2518,7 @@ impl<T: Storage> Raft<T> {
         } else if old_paused {
             self.send_append(m.from)
         }
+        self.maybe_notify_learner_caught_up(m.from);
         // Hack to get around borrow check. It may be possible to move L1448 above L1433 to
         // get around the problem. But here choose to keep consistent with Etcd.
         let pr = self.prs.get_mut(m.from).unwrap();
@@ -1789,11 +2559,13 @@ impl<T: Storage> Raft<T> {
         };
         // update followers committed index via heartbeat response
         pr.update_committed(m.commit);
+        pr.heartbeat_responded(self.r.ticks);
         pr.recent_active = true;
+        pr.last_active_tick = Some(self.r.ticks);
         pr.resume();
 
-        // free one slot for the full inflights window to allow progress.
-        if pr.state == ProgressState::Replicate && pr.ins.full() {
+        // free one slot for the full inflights window or pipeline to allow progress.
+        if pr.state == ProgressState::Replicate && self.r.is_send_paused(pr) {
             pr.ins.free_first_one();
         }
         // Does it request snapshot?
@@ -1812,8 +2584,11 @@ impl<T: Storage> Raft<T> {
         }
 
         for rs in self.r.read_only.advance(&m.context, &self.r.logger) {
-            if let Some(m) = self.handle_ready_read_index(rs.req, rs.index) {
-                self.r.send(m, &mut self.msgs);
+            let index = rs.index;
+            for req in rs.into_requests() {
+                if let Some(m) = self.handle_ready_read_index(req, index) {
+                    self.r.send(m, &mut self.msgs);
+                }
             }
         }
     }
@@ -1837,6 +2612,19 @@ impl<T: Storage> Raft<T> {
             );
             return;
         }
+        // The transferee may commit entries a lease holder doesn't have, so the holder
+        // would serve stale reads.
+        let ticks = self.ticks;
+        if self.lease_holders.values().any(|&expire| expire > ticks) {
+            info!(
+                self.logger,
+                "[term {term}] ignored transferring leadership while read leases are held",
+                term = self.term;
+                "to" => from,
+                "holders" => ?self.lease_holders.keys().collect::<Vec<_>>(),
+            );
+            return;
+        }
         let lead_transferee = from;
         if let Some(last_lead_transferee) = self.lead_transferee {
             if last_lead_transferee == lead_transferee {
@@ -1903,6 +2691,12 @@ impl<T: Storage> Raft<T> {
         if pr.state != ProgressState::Snapshot {
             return;
         }
+        self.r.notify_progress_transition(
+            m.from,
+            ProgressState::Snapshot,
+            ProgressState::Probe,
+            pr.pending_snapshot,
+        );
         if m.reject {
             pr.snapshot_failure();
             pr.become_probe();
@@ -1944,6 +2738,12 @@ impl<T: Storage> Raft<T> {
         // there is huge probability that a MsgAppend is lost.
         if pr.state == ProgressState::Replicate {
             pr.become_probe();
+            self.r.notify_progress_transition(
+                m.from,
+                ProgressState::Replicate,
+                ProgressState::Probe,
+                pr.matched,
+            );
         }
         debug!(
             self.r.logger,
@@ -1975,21 +2775,49 @@ impl<T: Storage> Raft<T> {
                 if m.entries.is_empty() {
                     fatal!(self.logger, "stepped empty MsgProp");
                 }
-                if !self.prs.progress().contains_key(&self.id) {
-                    // If we are not currently a member of the range (i.e. this node
-                    // was removed from the configuration while serving as leader),
-                    // drop any new proposals.
-                    return Err(Error::ProposalDropped);
-                }
-                if self.lead_transferee.is_some() {
+                self.check_propose_admission()?;
+                self.check_entry_sizes(&m.entries)?;
+                if let Some(lead_transferee) = self.lead_transferee {
+                    let size = m.entries.iter().map(|e| e.get_data().len()).sum();
+                    if self.transfer_proposals.len() >= self.max_transfer_proposals
+                        || !self
+                            .uncommitted_state
+                            .can_increase_uncommitted_size_by(self.transfer_proposals_size, size)
+                    {
+                        debug!(
+                            self.logger,
+                            "[term {term}] transfer leadership to {lead_transferee} is in \
+                             progress and too many proposals are buffered; dropping proposal",
+                            term = self.term,
+                            lead_transferee = lead_transferee;
+                            "buffered" => self.transfer_proposals.len(),
+                            "buffered_size" => self.transfer_proposals_size,
+                        );
+                        return Err(Error::ProposalDropped);
+                    }
                     debug!(
                         self.logger,
-                        "[term {term}] transfer leadership to {lead_transferee} is in progress; dropping \
-                         proposal",
+                        "[term {term}] transfer leadership to {lead_transferee} is in progress; \
+                         buffering proposal",
                         term = self.term,
-                        lead_transferee = self.lead_transferee.unwrap();
+                        lead_transferee = lead_transferee;
                     );
-                    return Err(Error::ProposalDropped);
+                    self.transfer_proposals_size += size;
+                    self.transfer_proposals.push(m);
+                    return Ok(());
+                }
+
+                // A conf change must not be the first entry of a term, or it may take effect
+                // before the leader has committed anything in its term, so the deferred empty
+                // entry is appended first.
+                if self.lazy_leader_noop
+                    && self.raft_log.last_term() != self.term
+                    && m.entries.iter().any(|e| {
+                        e.get_entry_type() == EntryType::EntryConfChange
+                            || e.get_entry_type() == EntryType::EntryConfChangeV2
+                    })
+                {
+                    self.append_noop();
                 }
 
                 for (i, e) in m.mut_entries().iter_mut().enumerate() {
@@ -2010,6 +2838,15 @@ impl<T: Storage> Raft<T> {
                     } else {
                         continue;
                     }
+                    if !cc.leave_joint() {
+                        if self.would_leave_no_voters(&cc) {
+                            return Err(Error::ConfChangeError(format!(
+                                "conf change {:?} would remove all voters",
+                                cc
+                            )));
+                        }
+                        self.check_conf_change_interval()?;
+                    }
 
                     let reason = if self.has_pending_conf() {
                         "possible unapplied conf change"
@@ -2041,6 +2878,14 @@ impl<T: Storage> Raft<T> {
                         e.set_entry_type(EntryType::EntryNormal);
                     }
                 }
+                if self.skip_duplicate_empty_entry && self.is_duplicate_empty_proposal(&m.entries) {
+                    debug!(
+                        self.logger,
+                        "skipped proposing empty entries as the last entry is an uncommitted empty entry";
+                        "last_index" => self.raft_log.last_index(),
+                    );
+                    return Ok(());
+                }
                 if !self.append_entry(&mut m.mut_entries()) {
                     // return ProposalDropped when uncommitted size limit is reached
                     debug!(
@@ -2057,6 +2902,10 @@ impl<T: Storage> Raft<T> {
                 if !self.commit_to_current_term() {
                     // Reject read only request when this leader has not committed any log entry
                     // in its term.
+                    if self.lazy_leader_noop && self.raft_log.last_term() != self.term {
+                        self.append_noop();
+                        self.bcast_append();
+                    }
                     return Ok(());
                 }
 
@@ -2075,12 +2924,32 @@ impl<T: Storage> Raft<T> {
                 match self.read_only.option {
                     ReadOnlyOption::Safe => {
                         let ctx = m.entries[0].data.to_vec();
-                        self.r
+                        let from = m.from;
+                        if !self
+                            .r
                             .read_only
-                            .add_request(self.r.raft_log.committed, m, self.r.id);
+                            .add_request(self.r.raft_log.committed, m, self.r.id)
+                        {
+                            debug!(
+                                self.logger,
+                                "read index is dropped due to overlimit of max pending read count";
+                                "pending_read_count" => self.pending_read_count(),
+                                "from" => from,
+                                "pending_read_count_of_peer" => self.read_only.pending_read_count_of(from),
+                            );
+                            return Err(Error::ReadIndexDropped);
+                        }
                         self.bcast_heartbeat_with_ctx(Some(ctx));
                     }
                     ReadOnlyOption::LeaseBased => {
+                        if !self.leader_lease_valid() {
+                            debug!(
+                                self.logger,
+                                "read index is dropped since leader lease has expired";
+                                "leader_lease_ticks" => self.leader_lease_ticks,
+                            );
+                            return Err(Error::ReadIndexDropped);
+                        }
                         let read_index = self.raft_log.committed;
                         if let Some(m) = self.handle_ready_read_index(m, read_index) {
                             self.r.send(m, &mut self.msgs);
@@ -2108,6 +2977,9 @@ impl<T: Storage> Raft<T> {
             MessageType::MsgTransferLeader => {
                 self.handle_transfer_leader(&m);
             }
+            MessageType::MsgReadLeaseRequest => {
+                self.handle_read_lease_request(&m);
+            }
             _ => {
                 if self.prs().get(m.from).is_none() {
                     debug!(
@@ -2190,6 +3062,12 @@ impl<T: Storage> Raft<T> {
                 }
             }
             VoteResult::Lost => {
+                let reason = if self.vote_rejected_for_log {
+                    ElectionFailureReason::LogNotUpToDate
+                } else {
+                    ElectionFailureReason::InsufficientVotes
+                };
+                self.record_election_failure(reason);
                 // pb.MsgPreVoteResp contains future term of pre-candidate
                 // m.term > self.term; reuse self.term
                 let term = self.term;
@@ -2200,6 +3078,34 @@ impl<T: Storage> Raft<T> {
         res
     }
 
+    // record_election_failure records the failure of the ongoing campaign along with its
+    // vote tally. It must be called before the node steps down, which resets the votes.
+    fn record_election_failure(&mut self, reason: ElectionFailureReason) {
+        let pre_vote = match self.state {
+            StateRole::PreCandidate => true,
+            StateRole::Candidate => false,
+            StateRole::Follower | StateRole::Leader => return,
+        };
+        let (granted, rejected, _) = self.prs().tally_votes();
+        let term = if pre_vote { self.term + 1 } else { self.term };
+        info!(
+            self.logger,
+            "campaign failed";
+            "reason" => ?reason,
+            "term" => term,
+            "pre_vote" => pre_vote,
+            "granted" => granted,
+            "rejected" => rejected,
+        );
+        self.last_election_failure = Some(ElectionFailure {
+            reason,
+            term,
+            pre_vote,
+            granted,
+            rejected,
+        });
+    }
+
     // step_candidate is shared by state Candidate and PreCandidate; the difference is
     // whether they respond to MsgRequestVote or MsgRequestPreVote.
     fn step_candidate(&mut self, m: Message) -> Result<()> {
@@ -2214,18 +3120,21 @@ impl<T: Storage> Raft<T> {
             }
             MessageType::MsgAppend => {
                 debug_assert_eq!(self.term, m.term);
+                self.record_election_failure(ElectionFailureReason::LeaderElected);
                 self.become_follower(m.term, m.from);
                 self.handle_append_entries(&m);
             }
             MessageType::MsgHeartbeat => {
                 debug_assert_eq!(self.term, m.term);
+                self.record_election_failure(ElectionFailureReason::LeaderElected);
                 self.become_follower(m.term, m.from);
                 self.handle_heartbeat(m);
             }
             MessageType::MsgSnapshot => {
                 debug_assert_eq!(self.term, m.term);
+                self.record_election_failure(ElectionFailureReason::LeaderElected);
                 self.become_follower(m.term, m.from);
-                self.handle_snapshot(m);
+                self.handle_snapshot(m)?;
             }
             MessageType::MsgRequestPreVoteResponse | MessageType::MsgRequestVoteResponse => {
                 // Only handle vote responses corresponding to our candidacy (while in
@@ -2239,6 +3148,14 @@ impl<T: Storage> Raft<T> {
                     return Ok(());
                 }
 
+                if m.reject
+                    && (m.commit_term > self.raft_log.last_term()
+                        || m.commit_term == self.raft_log.last_term()
+                            && m.commit > self.raft_log.last_index())
+                {
+                    // The voter has committed entries this node doesn't have.
+                    self.vote_rejected_for_log = true;
+                }
                 self.poll(m.from, m.get_msg_type(), !m.reject);
                 self.maybe_commit_by_vote(&m);
             }
@@ -2269,7 +3186,9 @@ impl<T: Storage> Raft<T> {
                 self.r.send(m, &mut self.msgs);
             }
             MessageType::MsgAppend => {
-                self.election_elapsed = 0;
+                if self.reset_election_on_append {
+                    self.election_elapsed = 0;
+                }
                 self.leader_id = m.from;
                 self.handle_append_entries(&m);
             }
@@ -2281,7 +3200,7 @@ impl<T: Storage> Raft<T> {
             MessageType::MsgSnapshot => {
                 self.election_elapsed = 0;
                 self.leader_id = m.from;
-                self.handle_snapshot(m);
+                self.handle_snapshot(m)?;
             }
             MessageType::MsgTransferLeader => {
                 if self.leader_id == INVALID_ID {
@@ -2317,6 +3236,14 @@ impl<T: Storage> Raft<T> {
                 }
             }
             MessageType::MsgReadIndex => {
+                if let Some(index) = self.read_lease_index().filter(|_| m.entries.len() == 1) {
+                    let rs = ReadState {
+                        index,
+                        request_ctx: m.take_entries()[0].take_data().to_vec(),
+                    };
+                    self.read_states.push(rs);
+                    return Ok(());
+                }
                 if self.leader_id == INVALID_ID {
                     info!(
                         self.logger,
@@ -2348,11 +3275,91 @@ impl<T: Storage> Raft<T> {
                 // because the leader only handle MsgReadIndex after it has committed log entry in its term.
                 self.raft_log.maybe_commit(m.index, m.term);
             }
+            MessageType::MsgReadLeaseResponse => {
+                let requested_at = match self.read_lease_requested_at.take() {
+                    Some(tick) => tick,
+                    None => return Ok(()),
+                };
+                if m.reject || m.from != self.leader_id {
+                    debug!(
+                        self.logger,
+                        "read lease is not granted by {from}",
+                        from = m.from;
+                        "leader" => self.leader_id,
+                    );
+                    return Ok(());
+                }
+                self.read_lease = Some(ReadLease {
+                    leader: m.from,
+                    expire_tick: requested_at + self.follower_lease_ticks as u64,
+                    index: m.index,
+                });
+            }
             _ => {}
         }
         Ok(())
     }
 
+    /// Requests a read lease from the leader, which lets this follower serve read index
+    /// requests locally until it expires. See `Config::follower_lease_ticks`.
+    ///
+    /// Returns `Error::ReadIndexDropped` if read leases are disabled, or this node is not
+    /// a follower of a known leader.
+    pub fn request_read_lease(&mut self) -> Result<()> {
+        if self.follower_lease_ticks == 0
+            || self.state != StateRole::Follower
+            || self.leader_id == INVALID_ID
+        {
+            info!(
+                self.logger,
+                "drop read lease request";
+                "state" => ?self.state,
+                "leader" => self.leader_id,
+            );
+            return Err(Error::ReadIndexDropped);
+        }
+        // The lease starts when it's requested, which is before the leader grants it.
+        if self.read_lease_requested_at.is_none() {
+            self.read_lease_requested_at = Some(self.ticks);
+        }
+        let m = new_message(self.leader_id, MessageType::MsgReadLeaseRequest, None);
+        self.r.send(m, &mut self.msgs);
+        Ok(())
+    }
+
+    /// Whether this follower holds a valid read lease delegated by its leader.
+    #[inline]
+    pub fn has_read_lease(&self) -> bool {
+        self.read_lease_index().is_some()
+    }
+
+    // read_lease_index returns the index local reads are served at, if this follower
+    // holds a valid read lease.
+    fn read_lease_index(&self) -> Option<u64> {
+        let lease = self.read_lease.as_ref()?;
+        if lease.leader != self.leader_id || self.ticks >= lease.expire_tick {
+            return None;
+        }
+        Some(cmp::max(lease.index, self.raft_log.last_index()))
+    }
+
+    fn handle_read_lease_request(&mut self, m: &Message) {
+        let mut to_send = new_message(m.from, MessageType::MsgReadLeaseResponse, None);
+        if self.follower_lease_ticks == 0
+            || self.prs().get(m.from).is_none()
+            || !self.leader_lease_valid()
+            || !self.commit_to_current_term()
+            || self.lead_transferee.is_some()
+        {
+            to_send.reject = true;
+        } else {
+            let expire = self.ticks + 2 * self.follower_lease_ticks as u64;
+            self.lease_holders.insert(m.from, expire);
+            to_send.index = self.raft_log.committed;
+        }
+        self.r.send(to_send, &mut self.msgs);
+    }
+
     /// Request a snapshot from a leader.
     pub fn request_snapshot(&mut self, request_index: u64) -> Result<()> {
         if self.state == StateRole::Leader {
@@ -2387,10 +3394,26 @@ impl<T: Storage> Raft<T> {
     // TODO: revoke pub when there is a better way to test.
     /// For a given message, append the entries to the log.
     pub fn handle_append_entries(&mut self, m: &Message) {
+        if self.strict_mode {
+            self.check_committed_match(m.from, m.index, m.log_term);
+            for e in m.get_entries() {
+                self.check_committed_match(m.from, e.index, e.term);
+            }
+        }
         if self.pending_request_snapshot != INVALID_INDEX {
             self.send_request_snapshot();
             return;
         }
+        if m.commit < self.raft_log.committed {
+            // The entries are still appended, but the commit index never goes backward.
+            debug!(
+                self.logger,
+                "got message with lower commit than committed";
+                "commit" => m.commit,
+                "committed" => self.raft_log.committed,
+            );
+            self.stale_commit_appends += 1;
+        }
         if m.index < self.raft_log.committed {
             debug!(
                 self.logger,
@@ -2463,9 +3486,40 @@ impl<T: Storage> Raft<T> {
         self.r.send(to_send, &mut self.msgs);
     }
 
-    fn handle_snapshot(&mut self, mut m: Message) {
+    fn handle_snapshot(&mut self, mut m: Message) -> Result<()> {
         let metadata = m.get_snapshot().get_metadata();
         let (sindex, sterm) = (metadata.index, metadata.term);
+        if self.strict_mode {
+            self.check_committed_match(m.from, sindex, sterm);
+        }
+        // Another snapshot may arrive before the pending one is persisted. A newer one
+        // supersedes it, while an older one is out of date, as restoring the pending one
+        // already committed its index.
+        let pending_index = self
+            .raft_log
+            .pending_snapshot()
+            .map(|s| s.get_metadata().index);
+        if let Err(e) = self.check_snapshot_freshness(metadata) {
+            // A snapshot whose last entry is already committed carries nothing new, so a
+            // retransmitted copy must leave the state untouched.
+            if self.raft_log.match_term(sindex, sterm) {
+                self.duplicate_snapshots_ignored += 1;
+            }
+            info!(
+                self.logger,
+                "[commit: {commit}] rejected out of date snapshot [index: {snapshot_index}, term: {snapshot_term}]",
+                commit = self.raft_log.committed,
+                snapshot_index = sindex,
+                snapshot_term = sterm;
+                "pending_snapshot_index" => pending_index,
+            );
+            let mut to_send = Message::default();
+            to_send.set_msg_type(MessageType::MsgAppendResponse);
+            to_send.to = m.from;
+            to_send.index = self.raft_log.committed;
+            self.r.send(to_send, &mut self.msgs);
+            return Err(e);
+        }
         if self.restore(m.take_snapshot()) {
             info!(
                 self.logger,
@@ -2474,6 +3528,7 @@ impl<T: Storage> Raft<T> {
                 commit = self.raft_log.committed,
                 snapshot_index = sindex,
                 snapshot_term = sterm;
+                "superseded_snapshot_index" => pending_index,
             );
             let mut to_send = Message::default();
             to_send.set_msg_type(MessageType::MsgAppendResponse);
@@ -2494,12 +3549,35 @@ impl<T: Storage> Raft<T> {
             to_send.index = self.raft_log.committed;
             self.r.send(to_send, &mut self.msgs);
         }
+        Ok(())
+    }
+
+    /// Checks whether a snapshot is fresh enough to be restored. A received snapshot that
+    /// fails the check is rejected with the returned error.
+    ///
+    /// A snapshot is out of date if `snapshot.index <= committed`: it carries nothing that
+    /// isn't committed already, and restoring an older one would move the commit index
+    /// backwards. The only exception is `snapshot.index == committed` while a snapshot
+    /// requested by `request_snapshot` is pending, as the requested snapshot replaces the
+    /// local state even if it's at the committed index.
+    ///
+    /// The boundary is not configurable, since it's what keeps the commit index monotonic.
+    ///
+    /// Returns `Error::Store(StorageError::SnapshotOutOfDate)` if the snapshot is out of date.
+    pub fn check_snapshot_freshness(&self, meta: &SnapshotMetadata) -> Result<()> {
+        let committed = self.raft_log.committed;
+        if meta.index < committed
+            || (meta.index == committed && self.pending_request_snapshot == INVALID_INDEX)
+        {
+            return Err(Error::Store(StorageError::SnapshotOutOfDate));
+        }
+        Ok(())
     }
 
     /// Recovers the state machine from a snapshot. It restores the log and the
     /// configuration of state machine.
     pub fn restore(&mut self, snap: Snapshot) -> bool {
-        if snap.get_metadata().index < self.raft_log.committed {
+        if self.check_snapshot_freshness(snap.get_metadata()).is_err() {
             return false;
         }
         if self.state != StateRole::Follower {
@@ -2656,8 +3734,11 @@ impl<T: Storage> Raft<T> {
                 .map_or(false, |acks| prs.has_quorum(acks))
             {
                 for rs in self.r.read_only.advance(&ctx, &self.r.logger) {
-                    if let Some(m) = self.handle_ready_read_index(rs.req, rs.index) {
-                        self.r.send(m, &mut self.msgs);
+                    let index = rs.index;
+                    for req in rs.into_requests() {
+                        if let Some(m) = self.handle_ready_read_index(req, index) {
+                            self.r.send(m, &mut self.msgs);
+                        }
                     }
                 }
             }
@@ -2672,6 +3753,39 @@ impl<T: Storage> Raft<T> {
         cs
     }
 
+    /// Whether applying `cc` to the current configuration would leave no voters, which
+    /// makes the cluster unrecoverable. Such conf changes are rejected.
+    pub fn would_leave_no_voters(&self, cc: &ConfChangeV2) -> bool {
+        let mut changer = Changer::new(&self.prs);
+        let res = if cc.leave_joint() {
+            changer.leave_joint()
+        } else if let Some(auto_leave) = cc.enter_joint() {
+            changer.enter_joint(auto_leave, &cc.changes)
+        } else {
+            changer.simple(&cc.changes)
+        };
+        // Other invalid changes are reported when the entry is applied.
+        matches!(res, Err(Error::ConfChangeError(ref e)) if e == confchange::REMOVED_ALL_VOTERS)
+    }
+
+    // check_conf_change_interval returns an error if the last conf change was applied
+    // less than min_conf_change_interval_ticks ago.
+    fn check_conf_change_interval(&self) -> Result<()> {
+        let applied_at = match self.last_conf_change_tick {
+            Some(tick) if self.min_conf_change_interval_ticks > 0 => tick,
+            _ => return Ok(()),
+        };
+        let elapsed = self.ticks - applied_at;
+        if elapsed < self.min_conf_change_interval_ticks as u64 {
+            return Err(Error::ConfChangeError(format!(
+                "last conf change was applied {} ticks ago, the next one can only be proposed \
+                 after {} ticks",
+                elapsed, self.min_conf_change_interval_ticks
+            )));
+        }
+        Ok(())
+    }
+
     /// Check if there is any pending confchange.
     ///
     /// This method can be false positive.
@@ -2680,6 +3794,95 @@ impl<T: Storage> Raft<T> {
         self.pending_conf_index > self.raft_log.applied
     }
 
+    // check_propose_admission returns an error if the leader can't accept proposals
+    // right now, whatever their entries are.
+    fn check_propose_admission(&self) -> Result<()> {
+        if !self.prs().progress().contains_key(&self.id) {
+            // If we are not currently a member of the range (i.e. this node
+            // was removed from the configuration while serving as leader),
+            // drop any new proposals.
+            return Err(Error::ProposalDropped);
+        }
+        if let Some(lead_transferee) = self
+            .lead_transferee
+            .filter(|_| !self.buffer_proposals_during_transfer)
+        {
+            debug!(
+                self.logger,
+                "[term {term}] transfer leadership to {lead_transferee} is in progress; dropping \
+                 proposal",
+                term = self.term,
+                lead_transferee = lead_transferee;
+            );
+            return Err(Error::ProposalDropped);
+        }
+        Ok(())
+    }
+
+    fn check_entry_sizes(&self, ents: &[Entry]) -> Result<()> {
+        if self.oversized_entry_policy == OversizedEntryPolicy::Force
+            || self.max_msg_size == NO_LIMIT
+        {
+            return Ok(());
+        }
+        for e in ents {
+            let size = u64::from(e.compute_size());
+            if size > self.max_msg_size {
+                debug!(
+                    self.logger,
+                    "[term {term}] proposed entry of {size} bytes exceeds max_size_per_msg; \
+                     dropping proposal",
+                    term = self.term,
+                    size = size;
+                );
+                return Err(Error::ProposalTooLarge {
+                    size,
+                    max: self.max_msg_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether proposing `ents` on this node would be accepted, without appending
+    /// anything to the log.
+    ///
+    /// Unlike stepping a proposal, this fails on followers as only the leader can tell if
+    /// a proposal is accepted. Returns `Error::ProposalDropped` if the proposal would be
+    /// dropped, or `Error::ProposalTooLarge` if an entry is rejected for its size.
+    pub fn validate_propose(&self, ents: &[Entry]) -> Result<()> {
+        if self.state != StateRole::Leader {
+            return Err(Error::ProposalDropped);
+        }
+        self.check_propose_admission()?;
+        self.check_entry_sizes(ents)?;
+        if !self.uncommitted_state.can_increase_uncommitted_size(ents) {
+            return Err(Error::ProposalDropped);
+        }
+        Ok(())
+    }
+
+    // Checks if `ents` are all empty entries and the last entry of the log is an uncommitted
+    // empty entry of the current term.
+    fn is_duplicate_empty_proposal(&self, ents: &[Entry]) -> bool {
+        let is_empty = |e: &Entry| {
+            e.get_entry_type() == EntryType::EntryNormal
+                && e.data.is_empty()
+                && e.context.is_empty()
+        };
+        if !ents.iter().all(is_empty) {
+            return false;
+        }
+        let last_index = self.raft_log.last_index();
+        if last_index <= self.raft_log.committed {
+            return false;
+        }
+        match self.raft_log.entries(last_index, None) {
+            Ok(last) => last[0].term == self.term && is_empty(&last[0]),
+            Err(_) => false,
+        }
+    }
+
     /// Specifies if the commit should be broadcast.
     pub fn should_bcast_commit(&self) -> bool {
         !self.skip_bcast_commit || self.has_pending_conf()
@@ -2703,6 +3906,7 @@ impl<T: Storage> Raft<T> {
         };
         self.prs
             .apply_conf(cfg, changes, self.raft_log.last_index());
+        self.last_conf_change_tick = Some(self.ticks);
         Ok(self.post_conf_change())
     }
 
@@ -2755,6 +3959,30 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout = timeout;
     }
 
+    // leader_lease_valid returns true if a quorum has acknowledged a heartbeat sent
+    // within the last leader_lease_ticks ticks. If leader_lease_ticks is 0, the lease
+    // is kept by check_quorum instead and is always considered valid.
+    fn leader_lease_valid(&self) -> bool {
+        if self.leader_lease_ticks == 0 {
+            return true;
+        }
+        let self_id = self.id;
+        let ticks = self.ticks;
+        let lease_ticks = self.leader_lease_ticks as u64;
+        let active: HashSet<u64> = self
+            .prs()
+            .iter()
+            .filter(|&(&id, pr)| {
+                id == self_id
+                    || pr
+                        .heartbeat_acked_tick
+                        .map_or(false, |t| ticks - t < lease_ticks)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        self.prs().has_quorum(&active)
+    }
+
     // check_quorum_active returns true if the quorum is active from
     // the view of the local raft state machine. Otherwise, it returns
     // false.
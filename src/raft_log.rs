@@ -56,6 +56,13 @@ pub struct RaftLog<T: Storage> {
     ///
     /// Invariant: applied <= min(committed, persisted)
     pub applied: u64,
+
+    /// The term of the entry at `applied`.
+    pub applied_term: u64,
+
+    /// The index and term of the last committed entry handed out to be applied, which
+    /// saves looking up the term once it's applied.
+    pub(crate) last_handed_out: (u64, u64),
 }
 
 impl<T> ToString for RaftLog<T>
@@ -79,6 +86,9 @@ impl<T: Storage> RaftLog<T> {
     pub fn new(store: T, logger: Logger) -> RaftLog<T> {
         let first_index = store.first_index().unwrap();
         let last_index = store.last_index().unwrap();
+        // The term may be unknown if the storage doesn't keep the one of the last compacted
+        // entry, it's updated on the next apply anyway.
+        let applied_term = store.term(first_index - 1).unwrap_or(0);
 
         // Initialize committed and applied pointers to the time of the last compaction.
         RaftLog {
@@ -86,6 +96,8 @@ impl<T: Storage> RaftLog<T> {
             committed: first_index - 1,
             persisted: last_index,
             applied: first_index - 1,
+            applied_term,
+            last_handed_out: (0, 0),
             unstable: Unstable::new(last_index + 1, logger),
         }
     }
@@ -320,6 +332,14 @@ impl<T: Storage> RaftLog<T> {
                 self.persisted,
             )
         }
+        self.applied_term = if idx == self.applied {
+            self.applied_term
+        } else if idx == self.last_handed_out.0 {
+            self.last_handed_out.1
+        } else {
+            // The term may be unknown if the entry is compacted, like in `new`.
+            self.term(idx).unwrap_or(0)
+        };
         self.applied = idx;
     }
 
@@ -328,6 +348,11 @@ impl<T: Storage> RaftLog<T> {
         self.applied
     }
 
+    /// Returns the term of the last applied entry.
+    pub fn applied_term(&self) -> u64 {
+        self.applied_term
+    }
+
     /// Clears the unstable entries and moves the stable offset up to the
     /// last index, if there is any.
     pub fn stable_entries(&mut self, index: u64, term: u64) {
@@ -433,6 +458,59 @@ impl<T: Storage> RaftLog<T> {
         self.next_entries_since(self.applied, max_size)
     }
 
+    /// Checks that applied <= committed <= last index, and that the log is contiguous
+    /// from the storage to the unstable entries.
+    ///
+    /// Returns `Error::InvariantViolated` describing the first violation found.
+    pub fn check_invariants(&self) -> Result<()> {
+        let violated = |msg: String| Err(Error::InvariantViolated(msg));
+        let last_index = self.last_index();
+        if self.applied > self.committed {
+            return violated(format!(
+                "applied index {} is greater than committed index {}",
+                self.applied, self.committed
+            ));
+        }
+        if self.committed > last_index {
+            return violated(format!(
+                "committed index {} is greater than last index {}",
+                self.committed, last_index
+            ));
+        }
+        let offset = self.unstable.offset;
+        for (i, e) in self.unstable.entries.iter().enumerate() {
+            if e.index != offset + i as u64 {
+                return violated(format!(
+                    "unstable entry {} is at index {}, expected {}",
+                    i,
+                    e.index,
+                    offset + i as u64
+                ));
+            }
+        }
+        let stable_last = self.store.last_index()?;
+        if self.unstable.snapshot.is_none() && offset > stable_last + 1 {
+            return violated(format!(
+                "unstable entries start at {} but the stable log ends at {}",
+                offset, stable_last
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the entries after the committed index, which is empty if every entry is
+    /// committed.
+    pub fn uncommitted_entries(&self) -> Vec<Entry> {
+        let (lo, hi) = (self.committed + 1, self.last_index() + 1);
+        if lo >= hi {
+            return vec![];
+        }
+        match self.slice(lo, hi, None) {
+            Ok(ents) => ents,
+            Err(e) => fatal!(self.unstable.logger, "{}", e),
+        }
+    }
+
     /// Returns whether there are committed and persisted entries since
     /// max(`since_idx` + 1, first_index).
     pub fn has_next_entries_since(&self, since_idx: u64) -> bool {
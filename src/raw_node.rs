@@ -20,17 +20,25 @@
 //! nodes but not the raft consensus itself. Generally, you'll interact with the
 //! RawNode first and use it to access the inner workings of the consensus protocol.
 
-use std::{collections::VecDeque, mem};
+use std::hash::{Hash, Hasher};
+use std::{cmp, collections::VecDeque, iter, mem};
 
+use fxhash::FxHasher64;
 use protobuf::Message as PbMessage;
-use raft_proto::ConfChangeI;
+use raft_proto::{new_conf_change_single, ConfChangeI};
 use slog::Logger;
 
-use crate::eraftpb::{ConfState, Entry, EntryType, HardState, Message, MessageType, Snapshot};
-use crate::errors::{Error, Result};
+use crate::eraftpb::{
+    ConfChange, ConfChangeType, ConfChangeV2, ConfState, Entry, EntryType, HardState, Message,
+    MessageType, Snapshot, SnapshotMetadata,
+};
+use crate::errors::{Error, Result, StorageError};
 use crate::read_only::ReadState;
-use crate::{config::Config, StateRole};
-use crate::{Raft, SoftState, Status, Storage};
+use crate::storage::MemStorage;
+use crate::{confchange, config::Config, StateRole};
+use crate::{
+    ElectionFailure, HashMap, HashSet, ProgressTransition, Raft, SoftState, Status, Storage,
+};
 
 use slog::info;
 
@@ -53,6 +61,15 @@ pub enum SnapshotStatus {
     Failure,
 }
 
+/// The role of a peer in the current configuration, see `RawNode::role_of`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PeerRole {
+    /// The peer votes in the incoming or the outgoing configuration.
+    Voter,
+    /// The peer replicates the log without voting.
+    Learner,
+}
+
 /// Checks if certain message type should be used internally.
 pub fn is_local_msg(t: MessageType) -> bool {
     matches!(
@@ -236,6 +253,69 @@ struct ReadyRecord {
     snapshot: Option<(u64, u64)>,
 }
 
+/// MessageStats tallies the messages of each type a `RawNode` has sent and received.
+///
+/// Messages are counted as sent once they are handed out to the application, e.g. by
+/// [`RawNode::ready`], and as received when they are stepped by [`RawNode::step`].
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MessageStats {
+    sent: HashMap<MessageType, u64>,
+    received: HashMap<MessageType, u64>,
+}
+
+impl MessageStats {
+    /// The number of sent messages of type `t`.
+    #[inline]
+    pub fn sent(&self, t: MessageType) -> u64 {
+        self.sent.get(&t).cloned().unwrap_or(0)
+    }
+
+    /// The number of received messages of type `t`.
+    #[inline]
+    pub fn received(&self, t: MessageType) -> u64 {
+        self.received.get(&t).cloned().unwrap_or(0)
+    }
+
+    fn record_sent(&mut self, msgs: &[Message]) {
+        for m in msgs {
+            *self.sent.entry(m.get_msg_type()).or_insert(0) += 1;
+        }
+    }
+
+    fn record_received(&mut self, t: MessageType) {
+        *self.received.entry(t).or_insert(0) += 1;
+    }
+}
+
+/// ReadyReason reports what makes a `RawNode` ready, as checked by [`RawNode::has_ready`].
+///
+/// More than one reason can apply at a time.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadyReason {
+    /// There are messages to send.
+    pub messages: bool,
+    /// The soft state changed.
+    pub soft_state: bool,
+    /// The hard state changed.
+    pub hard_state: bool,
+    /// There are read states to handle.
+    pub read_states: bool,
+    /// There are unstable entries to persist.
+    pub entries: bool,
+    /// There is a snapshot to apply.
+    pub snapshot: bool,
+    /// There are committed entries to apply.
+    pub committed_entries: bool,
+}
+
+impl ReadyReason {
+    /// Whether nothing makes the node ready.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == ReadyReason::default()
+    }
+}
+
 /// LightReady encapsulates the commit index, committed entries and
 /// messages that are ready to be applied or be sent to other peers.
 #[derive(Default, Debug, PartialEq)]
@@ -294,6 +374,9 @@ pub struct RawNode<T: Storage> {
     records: VecDeque<ReadyRecord>,
     // Index which the given committed entries should start from.
     commit_since_index: u64,
+    // Index of the last received snapshot that is persisted, 0 if there is none.
+    persisted_snapshot_index: u64,
+    message_stats: MessageStats,
 }
 
 impl<T: Storage> RawNode<T> {
@@ -309,6 +392,8 @@ impl<T: Storage> RawNode<T> {
             max_number: 0,
             records: VecDeque::new(),
             commit_since_index: config.applied,
+            persisted_snapshot_index: 0,
+            message_stats: MessageStats::default(),
         };
         rn.prev_hs = rn.raft.hard_state();
         rn.prev_ss = rn.raft.soft_state();
@@ -362,6 +447,46 @@ impl<T: Storage> RawNode<T> {
         self.raft.step(m)
     }
 
+    /// Proposes data be appended to the raft log only if the index and term of the last
+    /// entry of the log are `expected_last`, which allows compare-and-append workflows.
+    ///
+    /// Returns `Error::LastEntryMismatch` if the last entry differs, and
+    /// `Error::ProposalDropped` if this node is not the leader, as only the leader appends
+    /// proposals right away.
+    pub fn propose_if(
+        &mut self,
+        expected_last: (u64, u64),
+        context: Vec<u8>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        if self.raft.state != StateRole::Leader {
+            return Err(Error::ProposalDropped);
+        }
+        let actual = (
+            self.raft.raft_log.last_index(),
+            self.raft.raft_log.last_term(),
+        );
+        if actual != expected_last {
+            return Err(Error::LastEntryMismatch {
+                expected: expected_last,
+                actual,
+            });
+        }
+        self.propose(context, data)
+    }
+
+    /// Runs the checks `propose` would run on a proposal of `data` with `context` without
+    /// appending it, e.g. to pre-flight a large write.
+    ///
+    /// Returns `Error::ProposalDropped` if this node is not the leader or the proposal
+    /// would be dropped.
+    pub fn validate_propose(&self, context: &[u8], data: &[u8]) -> Result<()> {
+        let mut e = Entry::default();
+        e.data = data.to_vec().into();
+        e.context = context.to_vec().into();
+        self.raft.validate_propose(&[e])
+    }
+
     /// Broadcast heartbeats to all the followers.
     ///
     /// If it's not leader, nothing will happen.
@@ -398,6 +523,171 @@ impl<T: Storage> RawNode<T> {
         self.raft.apply_conf_change(&cc.as_v2())
     }
 
+    /// Whether applying `cc` to the current configuration would leave no voters. Such conf
+    /// changes are rejected when they are proposed, as well as when they are applied.
+    pub fn would_leave_no_voters(&self, cc: &impl ConfChangeI) -> bool {
+        self.raft.would_leave_no_voters(&cc.as_v2())
+    }
+
+    /// Returns whether the current configuration is joint, i.e. a configuration change is
+    /// in progress and it needs to leave the joint configuration before another one can be
+    /// proposed.
+    #[inline]
+    pub fn in_joint_config(&self) -> bool {
+        confchange::joint(self.raft.prs().conf())
+    }
+
+    /// Reports whether the incoming and the outgoing configuration respectively have a
+    /// quorum among the peers recently active, which tells which half stalls commits
+    /// during a joint configuration.
+    ///
+    /// The outgoing half always has a quorum when the configuration is not joint. Only the
+    /// leader tracks the activity of its peers, so the result is meaningless on other nodes.
+    pub fn joint_quorum_status(&self) -> (bool, bool) {
+        let prs = self.raft.prs();
+        let active: HashSet<u64> = prs
+            .iter()
+            .filter(|(id, pr)| **id == self.raft.id || pr.recent_active)
+            .map(|(id, _)| *id)
+            .collect();
+        prs.has_joint_quorum(&active)
+    }
+
+    /// Returns the role of peer `id` in the current configuration, or `None` if it's not a
+    /// member.
+    ///
+    /// In a joint configuration, a peer voting in either half is a voter, including a voter
+    /// that will become a learner once the joint configuration is left.
+    pub fn role_of(&self, id: u64) -> Option<PeerRole> {
+        let conf = self.raft.prs().conf();
+        if conf.voters().contains(id) {
+            Some(PeerRole::Voter)
+        } else if conf.learners().contains(&id) {
+            Some(PeerRole::Learner)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the config change carried by a committed entry, upgrading `EntryConfChange`
+    /// to the `ConfChangeV2` representation, so both kinds can be applied the same way.
+    ///
+    /// Returns `Error::ConfChangeError` if the entry is not a config change.
+    pub fn decode_conf_change(&self, entry: &Entry) -> Result<ConfChangeV2> {
+        match entry.get_entry_type() {
+            EntryType::EntryConfChange => {
+                let mut cc = ConfChange::default();
+                cc.merge_from_bytes(entry.get_data())?;
+                Ok(cc.into_v2())
+            }
+            EntryType::EntryConfChangeV2 => {
+                let mut cc = ConfChangeV2::default();
+                cc.merge_from_bytes(entry.get_data())?;
+                Ok(cc)
+            }
+            EntryType::EntryNormal => Err(Error::ConfChangeError(format!(
+                "entry {} is not a conf change",
+                entry.index
+            ))),
+        }
+    }
+
+    /// Computes the config change that turns the current configuration into the one with
+    /// `target_voters` and `target_learners`.
+    ///
+    /// Only the peers whose role differs are changed: peers missing from the target are
+    /// removed and demoted voters are added as learners. A change of more than one voter
+    /// enters the joint consensus, which is left automatically.
+    ///
+    /// The change is empty if the configuration is already the target, in which case it
+    /// must not be proposed as it would be treated as a request to leave the joint state.
+    pub fn plan_membership_change(
+        &self,
+        target_voters: &[u64],
+        target_learners: &[u64],
+    ) -> ConfChangeV2 {
+        let conf = self.raft.prs().conf();
+        let voters = &conf.voters.incoming;
+        let is_learner = |id| conf.learners.contains(&id) || conf.learners_next.contains(&id);
+
+        let mut changes = vec![];
+        for &id in target_voters {
+            if !voters.contains(&id) {
+                changes.push(new_conf_change_single(id, ConfChangeType::AddNode));
+            }
+        }
+        for &id in target_learners {
+            if !is_learner(id) {
+                changes.push(new_conf_change_single(id, ConfChangeType::AddLearnerNode));
+            }
+        }
+        let mut removed: Vec<u64> = voters
+            .ids()
+            .cloned()
+            .chain(conf.learners.iter().cloned())
+            .chain(conf.learners_next.iter().cloned())
+            .filter(|id| !target_voters.contains(id) && !target_learners.contains(id))
+            .collect();
+        removed.sort_unstable();
+        removed.dedup();
+        for id in removed {
+            changes.push(new_conf_change_single(id, ConfChangeType::RemoveNode));
+        }
+
+        let mut cc = ConfChangeV2::default();
+        cc.set_changes(changes.into());
+        cc
+    }
+
+    /// Checks whether `id` is an orphan learner: a learner that is tracked in progress
+    /// although the conf change adding it is not committed yet.
+    ///
+    /// A conf change must only be applied after it's committed, so an orphan is left behind
+    /// by applying the pending conf change early, which may never commit. Only the leader
+    /// tracks the pending conf change, so followers never report orphans.
+    pub fn is_orphan_learner(&self, id: u64) -> bool {
+        let conf = self.raft.prs().conf();
+        if !conf.learners.contains(&id) && !conf.learners_next.contains(&id) {
+            return false;
+        }
+        let index = self.raft.pending_conf_index;
+        if index <= self.raft.raft_log.committed {
+            return false;
+        }
+        let cc = match self.raft.raft_log.slice(index, index + 1, None) {
+            Ok(ents) if !ents.is_empty() => match self.decode_conf_change(&ents[0]) {
+                Ok(cc) => cc,
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+        cc.changes
+            .iter()
+            .any(|c| c.node_id == id && c.get_change_type() == ConfChangeType::AddLearnerNode)
+    }
+
+    /// Proposes removing an orphan learner, see `is_orphan_learner`.
+    ///
+    /// The removal is proposed even though the conf change adding the learner is pending,
+    /// as it's already applied. It's ordered after that conf change, so the learner is
+    /// removed whether or not that one commits. Returns `Error::NotExists` if `id` is not an
+    /// orphan learner.
+    pub fn remove_orphan_learner(&mut self, id: u64) -> Result<()> {
+        if !self.is_orphan_learner(id) {
+            return Err(Error::NotExists {
+                id,
+                set: "orphan learners",
+            });
+        }
+        let mut cc = ConfChangeV2::default();
+        cc.set_changes(vec![new_conf_change_single(id, ConfChangeType::RemoveNode)].into());
+        let pending = self.raft.pending_conf_index;
+        self.raft.pending_conf_index = self.raft.raft_log.applied;
+        let res = self.propose_conf_change(vec![], cc);
+        self.raft.pending_conf_index = cmp::max(pending, self.raft.pending_conf_index);
+        res
+    }
+
     /// Step advances the state machine using the given message.
     pub fn step(&mut self, m: Message) -> Result<()> {
         // Ignore unexpected local messages receiving over network
@@ -405,6 +695,7 @@ impl<T: Storage> RawNode<T> {
             return Err(Error::StepLocalMsg);
         }
         if self.raft.prs().get(m.from).is_some() || !is_response_msg(m.get_msg_type()) {
+            self.message_stats.record_received(m.get_msg_type());
             return self.raft.step(m);
         }
         Err(Error::StepPeerNotFound)
@@ -424,10 +715,12 @@ impl<T: Storage> RawNode<T> {
         if let Some(e) = rd.committed_entries.last() {
             assert!(self.commit_since_index < e.get_index());
             self.commit_since_index = e.get_index();
+            raft.raft_log.last_handed_out = (e.get_index(), e.get_term());
         }
 
         if !raft.msgs.is_empty() {
             rd.messages = mem::take(&mut raft.msgs);
+            self.message_stats.record_sent(&rd.messages);
         }
 
         rd
@@ -484,6 +777,10 @@ impl<T: Storage> RawNode<T> {
             rd.snapshot = snapshot.clone();
             assert!(self.commit_since_index <= rd.snapshot.get_metadata().index);
             self.commit_since_index = rd.snapshot.get_metadata().index;
+            raft.raft_log.last_handed_out = (
+                rd.snapshot.get_metadata().index,
+                rd.snapshot.get_metadata().term,
+            );
             // If there is a snapshot, the latter entries can not be persisted
             // so there is no committed entries.
             assert!(
@@ -517,38 +814,24 @@ impl<T: Storage> RawNode<T> {
 
     /// HasReady called when RawNode user need to check if any Ready pending.
     pub fn has_ready(&self) -> bool {
-        let raft = &self.raft;
-        if !raft.msgs.is_empty() {
-            return true;
-        }
-
-        if raft.soft_state() != self.prev_ss {
-            return true;
-        }
-        if raft.hard_state() != self.prev_hs {
-            return true;
-        }
-
-        if !raft.read_states.is_empty() {
-            return true;
-        }
-
-        if !raft.raft_log.unstable_entries().is_empty() {
-            return true;
-        }
-
-        if self.snap().map_or(false, |s| !s.is_empty()) {
-            return true;
-        }
+        !self.ready_reason().is_empty()
+    }
 
-        if raft
-            .raft_log
-            .has_next_entries_since(self.commit_since_index)
-        {
-            return true;
+    /// Reports what makes the node ready, i.e. why `has_ready` returns true. It's empty if
+    /// `has_ready` returns false.
+    pub fn ready_reason(&self) -> ReadyReason {
+        let raft = &self.raft;
+        ReadyReason {
+            messages: !raft.msgs.is_empty(),
+            soft_state: raft.soft_state() != self.prev_ss,
+            hard_state: raft.hard_state() != self.prev_hs,
+            read_states: !raft.read_states.is_empty(),
+            entries: !raft.raft_log.unstable_entries().is_empty(),
+            snapshot: self.snap().map_or(false, |s| !s.is_empty()),
+            committed_entries: raft
+                .raft_log
+                .has_next_entries_since(self.commit_since_index),
         }
-
-        false
     }
 
     fn commit_ready(&mut self, rd: Ready) {
@@ -602,12 +885,28 @@ impl<T: Storage> RawNode<T> {
         }
         if snap_index != 0 {
             self.raft.on_persist_snap(snap_index);
+            self.persisted_snapshot_index = snap_index;
         }
         if index != 0 {
             self.raft.on_persist_entries(index, term);
         }
     }
 
+    /// Notifies that the entries up to `index` have been persisted.
+    ///
+    /// Unlike [`Self::on_persist_ready`], `index` can point into the middle of a ready's
+    /// entries, which allows pipelined or group-committed writes to report progress as soon
+    /// as part of the entries are durable. The ready must have been handed back by
+    /// [`Self::advance_append_async`] before, indexes beyond that are ignored.
+    /// [`Self::on_persist_ready`] should still be called once the whole ready is persisted.
+    pub fn on_persist_entries_to(&mut self, index: u64) {
+        let term = match self.raft.raft_log.term(index) {
+            Ok(t) if t != 0 => t,
+            _ => return,
+        };
+        self.raft.on_persist_entries(index, term);
+    }
+
     /// Advances the ready after fully processing it.
     ///
     /// Fully processing a ready requires to persist snapshot, entries and hard states, apply all
@@ -664,6 +963,37 @@ impl<T: Storage> RawNode<T> {
         self.commit_ready(rd);
     }
 
+    /// Notifies that the snapshot of `index` handed out in a ready has been applied.
+    ///
+    /// Applying a large snapshot to the state machine can take long, so it can be deferred:
+    /// save the snapshot to the `Storage`, hand the ready back by
+    /// [`Self::advance_append_async`] and apply the snapshot in the background. Until this
+    /// function is called, the snapshot is treated as not persisted, so no committed entries
+    /// after it are handed out and the applied index must not be advanced; compaction
+    /// reports `StorageError::SnapshotTemporarilyUnavailable`.
+    ///
+    /// Returns `StorageError::SnapshotOutOfDate` if no such snapshot is pending.
+    pub fn snapshot_applied(&mut self, index: u64) -> Result<()> {
+        let number = self
+            .records
+            .iter()
+            .find(|r| r.snapshot.map_or(false, |(i, _)| i == index))
+            .map(|r| r.number);
+        match number {
+            Some(number) => {
+                self.on_persist_ready(number);
+                Ok(())
+            }
+            None => Err(Error::Store(StorageError::SnapshotOutOfDate)),
+        }
+    }
+
+    // Whether a snapshot is received but not persisted or applied yet.
+    fn has_pending_snapshot(&self) -> bool {
+        self.raft.raft_log.unstable_snapshot().is_some()
+            || self.records.iter().any(|r| r.snapshot.is_some())
+    }
+
     /// Advance apply to the index of the last committed entries given before.
     #[inline]
     pub fn advance_apply(&mut self) {
@@ -714,6 +1044,18 @@ impl<T: Storage> RawNode<T> {
         self.raft.request_snapshot(request_index)
     }
 
+    /// Requests a read lease from the leader. Once it's granted, read index requests are
+    /// served locally until the lease expires. See `Config::follower_lease_ticks`.
+    pub fn request_read_lease(&mut self) -> Result<()> {
+        self.raft.request_read_lease()
+    }
+
+    /// Whether this node holds a valid read lease delegated by its leader.
+    #[inline]
+    pub fn has_read_lease(&self) -> bool {
+        self.raft.has_read_lease()
+    }
+
     /// TransferLeader tries to transfer leadership to the given transferee.
     pub fn transfer_leader(&mut self, transferee: u64) {
         let mut m = Message::default();
@@ -722,10 +1064,36 @@ impl<T: Storage> RawNode<T> {
         let _ = self.raft.step(m);
     }
 
+    /// Transfers leadership to the voter with the highest match index and returns it.
+    ///
+    /// Ties are broken by the smaller id, as the leader doesn't know the priorities of its
+    /// followers. Returns `None` if the node is not leader or there is no other voter.
+    pub fn transfer_leader_to_best(&mut self) -> Option<u64> {
+        if self.raft.state != StateRole::Leader {
+            return None;
+        }
+        let self_id = self.raft.id;
+        let voters = self.raft.prs().conf().voters();
+        let (_, transferee) = self
+            .raft
+            .prs()
+            .iter()
+            .filter(|&(id, _)| *id != self_id && voters.contains(*id))
+            .map(|(id, pr)| (pr.matched, *id))
+            .max_by(|(m1, id1), (m2, id2)| m1.cmp(m2).then(id2.cmp(id1)))?;
+        self.transfer_leader(transferee);
+        Some(transferee)
+    }
+
     /// ReadIndex requests a read state. The read state will be set in ready.
     /// Read State has a read index. Once the application advances further than the read
     /// index, any linearizable read requests issued before the read request can be
     /// processed safely. The read state will have the same rctx attached.
+    ///
+    /// If the local node is the only voter, no heartbeat round is needed and the read
+    /// state is returned in the next `Ready` at the current commit index. Like other
+    /// read requests, it's dropped if the leader hasn't committed an entry in its term,
+    /// or if `Config::max_pending_read_count` requests are already pending.
     pub fn read_index(&mut self, rctx: Vec<u8>) {
         let mut m = Message::default();
         m.set_msg_type(MessageType::MsgReadIndex);
@@ -735,6 +1103,393 @@ impl<T: Storage> RawNode<T> {
         let _ = self.raft.step(m);
     }
 
+    /// Checks whether the entry at `index` can be committed directly by counting replicas.
+    ///
+    /// Raft only commits entries from the current term this way; entries from previous
+    /// terms are committed indirectly once a later entry of the current term commits.
+    /// Returns false if the index is unknown to the log (compacted or not yet appended).
+    pub fn can_commit_directly(&self, index: u64) -> bool {
+        self.raft
+            .raft_log
+            .term(index)
+            .map_or(false, |t| t == self.raft.term)
+    }
+
+    /// Returns the term of the entry at `index`.
+    ///
+    /// The term of the entry right before the first index is retained after compaction.
+    /// Returns `StorageError::Compacted` if the entry is compacted, or
+    /// `StorageError::Unavailable` if it's beyond the last index.
+    pub fn term_of(&self, index: u64) -> Result<u64> {
+        let raft_log = &self.raft.raft_log;
+        if index < raft_log.first_index() - 1 {
+            return Err(Error::Store(StorageError::Compacted));
+        }
+        if index > raft_log.last_index() {
+            return Err(Error::Store(StorageError::Unavailable));
+        }
+        raft_log.term(index)
+    }
+
+    /// Returns the entry at the current commit index.
+    ///
+    /// Returns `StorageError::Compacted` if the entry is compacted, e.g. it's included in the
+    /// last snapshot.
+    pub fn committed_entry(&self) -> Result<Entry> {
+        let raft_log = &self.raft.raft_log;
+        let committed = raft_log.committed;
+        let mut ents = raft_log.slice(committed, committed + 1, None)?;
+        Ok(ents.remove(0))
+    }
+
+    /// Checks the internal invariants of the log, i.e. applied <= committed <= last index
+    /// and that the log is contiguous, to catch bugs early in tests and development.
+    ///
+    /// Returns `Error::InvariantViolated` describing the violation.
+    #[inline]
+    pub fn check_invariants(&self) -> Result<()> {
+        self.raft.raft_log.check_invariants()
+    }
+
+    /// Returns the entries after the commit index, e.g. to diagnose why commits are stuck.
+    /// It's empty if every entry is committed.
+    #[inline]
+    pub fn uncommitted_entries(&self) -> Vec<Entry> {
+        self.raft.raft_log.uncommitted_entries()
+    }
+
+    /// Returns the index of the first entry available in the log, i.e. the index after the
+    /// last compacted one. If a received snapshot is not persisted yet, it's the index after
+    /// the snapshot.
+    #[inline]
+    pub fn first_index(&self) -> u64 {
+        self.raft.raft_log.first_index()
+    }
+
+    /// Returns the index of the last entry of the log, including the unstable entries. If
+    /// the log has no entries after a snapshot, it's the index of the snapshot.
+    #[inline]
+    pub fn last_index(&self) -> u64 {
+        self.raft.raft_log.last_index()
+    }
+
+    /// Returns every entry of the log from the first index to the last one, including the
+    /// unstable entries, e.g. for backup tooling. Entries compacted into a snapshot are not
+    /// included.
+    ///
+    /// Returns `StorageError::Compacted` if the storage no longer holds entries from the
+    /// first index it reports, e.g. as it's compacted concurrently.
+    pub fn dump_log(&self) -> Result<Vec<Entry>> {
+        let raft_log = &self.raft.raft_log;
+        raft_log.entries(raft_log.first_index(), None)
+    }
+
+    /// Returns the current hard state, which can be compared against what is persisted to
+    /// verify the persistence layer.
+    #[inline]
+    pub fn hard_state(&self) -> HardState {
+        self.raft.hard_state()
+    }
+
+    /// Forces this node to become a follower of `leader` at `term`, e.g. to set up tests or
+    /// in recovery flows. `leader` can be `INVALID_ID` if the leader is unknown.
+    ///
+    /// The vote is cleared if the term changes. Returns `Error::StaleTerm` if `term` is less
+    /// than the current term.
+    pub fn become_follower_at(&mut self, term: u64, leader: u64) -> Result<()> {
+        if term < self.raft.term {
+            return Err(Error::StaleTerm {
+                term,
+                current: self.raft.term,
+            });
+        }
+        self.raft.become_follower(term, leader);
+        Ok(())
+    }
+
+    /// Returns the sorted ids of the peers whose matched index is below `index`, including
+    /// learners and this node itself.
+    ///
+    /// Only a leader tracks the matched indexes of the other peers, so this is only
+    /// meaningful on the leader.
+    pub fn peers_behind(&self, index: u64) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .raft
+            .prs()
+            .iter()
+            .filter(|(_, pr)| pr.matched < index)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Returns the index of the next entry the leader will send to peer `id`, or `None` if
+    /// this node isn't the leader or doesn't track `id`.
+    ///
+    /// Together with the matched index, it tells whether replication to a peer is stuck,
+    /// e.g. in a probe loop where it keeps bouncing back after rejections.
+    pub fn next_index(&self, id: u64) -> Option<u64> {
+        if self.raft.state != StateRole::Leader {
+            return None;
+        }
+        self.raft.prs().get(id).map(|pr| pr.next_idx)
+    }
+
+    /// Returns the highest index the log can be compacted to, i.e. passed to
+    /// `RawNode::compact`, without forcing any voter that is still catching up into
+    /// snapshot state. Learners lagging behind are ignored.
+    ///
+    /// The index never exceeds the applied index plus 1. Only the leader tracks the other
+    /// peers, so on other nodes only the applied index is considered.
+    pub fn safe_compact_index(&self) -> u64 {
+        self.safe_compact_index_of(false)
+    }
+
+    /// Like [`Self::safe_compact_index`], but learners lagging behind are taken into account
+    /// too, e.g. when they serve reads and shouldn't be stalled by a snapshot.
+    pub fn safe_compact_index_including_learners(&self) -> u64 {
+        self.safe_compact_index_of(true)
+    }
+
+    fn safe_compact_index_of(&self, include_learners: bool) -> u64 {
+        let raft_log = &self.raft.raft_log;
+        let mut index = raft_log.applied + 1;
+        if self.raft.state == StateRole::Leader {
+            let prs = self.raft.prs();
+            let voters = prs.conf().voters();
+            for (id, pr) in prs.iter() {
+                if *id != self.raft.id && (include_learners || voters.contains(*id)) {
+                    index = cmp::min(index, pr.matched + 1);
+                }
+            }
+        }
+        cmp::max(index, raft_log.first_index())
+    }
+
+    /// Returns the metadata of the snapshot being transferred to follower `id`, i.e. its
+    /// index, term and conf state, if the follower is in snapshot state.
+    ///
+    /// Only a leader sends snapshots, so this always returns `None` on other nodes.
+    pub fn pending_snapshot_for(&self, id: u64) -> Option<SnapshotMetadata> {
+        self.raft.pending_snapshot_for(id).cloned()
+    }
+
+    /// Returns the IDs of the followers the leader considers inactive, sorted.
+    ///
+    /// A follower is inactive if it hasn't been heard from since the last `check_quorum`
+    /// check, nor within `Config::inactive_grace_ticks` ticks. The grace period smooths
+    /// this health signal only, the quorum check itself doesn't use it.
+    pub fn inactive_peers(&self) -> Vec<u64> {
+        self.raft.inactive_peers()
+    }
+
+    /// Returns the IDs of the followers that have been in snapshot state for at least
+    /// `threshold_ticks` ticks, sorted.
+    ///
+    /// A snapshot that never completes, e.g. because it's dropped by the transport and never
+    /// reported with `report_snapshot`, leaves the follower paused forever. The application
+    /// can report such snapshots as failed to make the leader send a new one.
+    pub fn stuck_snapshot_peers(&self, threshold_ticks: usize) -> Vec<u64> {
+        self.raft.stuck_snapshot_peers(threshold_ticks)
+    }
+
+    /// Replaces the logger of the node, e.g. to change the verbosity or destination of the
+    /// logs of a long running node without restarting it. Records logged afterwards only go
+    /// to the new logger.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.raft.set_logger(logger);
+    }
+
+    /// Reserves capacity for at least `additional` more entries in the unstable log, e.g.
+    /// before proposing a large batch, so that it isn't reallocated over and over.
+    pub fn reserve_entries(&mut self, additional: usize) {
+        self.raft.raft_log.unstable.reserve(additional);
+    }
+
+    /// Returns a digest of the current configuration, which is the same on every node
+    /// that agrees on it, whatever order the voters and learners were added in.
+    ///
+    /// Comparing the digests across nodes is a cheap way to verify that they have
+    /// converged to the same configuration.
+    pub fn conf_state_digest(&self) -> u64 {
+        let cs = self.raft.prs().conf().to_conf_state();
+        let mut hasher = FxHasher64::default();
+        for ids in &[cs.voters, cs.voters_outgoing, cs.learners, cs.learners_next] {
+            let mut ids = ids.clone();
+            ids.sort_unstable();
+            ids.hash(&mut hasher);
+        }
+        cs.auto_leave.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns the total serialized size in bytes of the unstable entries and snapshot, which
+    /// are held in memory until they are persisted.
+    ///
+    /// It takes time linear to the number of unstable entries.
+    pub fn unstable_size_bytes(&self) -> u64 {
+        let unstable = &self.raft.raft_log.unstable;
+        let entries_size: u64 = unstable
+            .entries
+            .iter()
+            .map(|e| u64::from(e.compute_size()))
+            .sum();
+        let snapshot_size = unstable
+            .snapshot
+            .as_ref()
+            .map_or(0, |s| u64::from(s.compute_size()));
+        entries_size + snapshot_size
+    }
+
+    /// Returns the number of committed entries that are not applied yet, which can be used
+    /// to throttle proposals when applying falls behind.
+    ///
+    /// Entries compacted into a snapshot that is not applied yet are not counted, as they
+    /// are applied at once with the snapshot.
+    pub fn apply_backlog(&self) -> u64 {
+        let raft_log = &self.raft.raft_log;
+        let applied = cmp::max(raft_log.applied, raft_log.first_index() - 1);
+        raft_log.committed.saturating_sub(applied)
+    }
+
+    /// Returns a lazy iterator over the entries starting at `lo`, whose total size is limited
+    /// by `max_size` in bytes.
+    ///
+    /// At least one entry is yielded if there is any after `lo`, even if it's larger than
+    /// `max_size`. Entries are fetched from the log in small batches as the iteration goes.
+    /// The iteration ends early if `lo` or the following entries are compacted, in which
+    /// case a snapshot is needed instead.
+    pub fn entries_from(&self, lo: u64, max_size: u64) -> impl Iterator<Item = Entry> + '_ {
+        const BATCH_SIZE: u64 = 64;
+
+        let raft_log = &self.raft.raft_log;
+        let mut next = lo;
+        let mut size = 0u64;
+        let mut batch = VecDeque::new();
+        iter::from_fn(move || {
+            if batch.is_empty() {
+                let last = raft_log.last_index();
+                if next > last {
+                    return None;
+                }
+                let high = cmp::min(last + 1, next + BATCH_SIZE);
+                batch = raft_log.slice(next, high, None).ok()?.into();
+            }
+            let e = batch.pop_front()?;
+            let entry_size = u64::from(e.compute_size());
+            if size > 0 && size.saturating_add(entry_size) > max_size {
+                return None;
+            }
+            size += entry_size;
+            next += 1;
+            Some(e)
+        })
+        .fuse()
+    }
+
+    /// Returns the index the storage can be compacted to once a received snapshot is
+    /// applied, i.e. the index after the snapshot, as the entries it covers are obsolete.
+    ///
+    /// The index comes from the metadata of the last received snapshot, so it can be passed
+    /// to [`Self::check_compact`] and then to the storage. Returns
+    /// `StorageError::SnapshotTemporarilyUnavailable` if a received snapshot isn't applied
+    /// yet, or `StorageError::Unavailable` if no snapshot has been received.
+    pub fn post_snapshot_compact_index(&self) -> Result<u64> {
+        if self.has_pending_snapshot() {
+            return Err(Error::Store(StorageError::SnapshotTemporarilyUnavailable));
+        }
+        if self.persisted_snapshot_index == 0 {
+            return Err(Error::Store(StorageError::Unavailable));
+        }
+        Ok(self.persisted_snapshot_index + 1)
+    }
+
+    /// Checks whether the log entries before `compact_index` can be discarded from the
+    /// storage.
+    ///
+    /// Committed entries handed out by a `Ready` must stay in the log until they are applied,
+    /// so `compact_index` can't be beyond the applied index reported by
+    /// [`Self::advance_apply_to`] plus 1. Returns `Error::CompactUnapplied` otherwise, or
+    /// `StorageError::SnapshotTemporarilyUnavailable` if a received snapshot isn't applied yet.
+    ///
+    /// Compacting the storage directly is not checked by raft, so applications must call
+    /// this before compacting their storage.
+    pub fn check_compact(&self, compact_index: u64) -> Result<()> {
+        if self.has_pending_snapshot() {
+            return Err(Error::Store(StorageError::SnapshotTemporarilyUnavailable));
+        }
+        let applied = self.raft.raft_log.applied;
+        if compact_index > applied + 1 {
+            return Err(Error::CompactUnapplied {
+                index: compact_index,
+                applied,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks whether the node is leader and a quorum of voters has recently been active,
+    /// so a proposal is expected to be committed promptly.
+    ///
+    /// Followers are marked active when the leader hears from them, and the marks are
+    /// cleared every election timeout if `check_quorum` is enabled. Without it, a follower
+    /// is considered active forever once it has responded.
+    pub fn can_commit_now(&self) -> bool {
+        if self.raft.state != StateRole::Leader {
+            return false;
+        }
+        let prs = self.raft.prs();
+        let active: HashSet<u64> = prs
+            .iter()
+            .filter(|&(id, pr)| *id == self.raft.id || pr.recent_active)
+            .map(|(id, _)| *id)
+            .collect();
+        prs.has_quorum(&active)
+    }
+
+    /// Removes and returns the queued messages destined for `peer`, the rest are left for
+    /// the next `Ready`.
+    ///
+    /// The taken messages bypass `Ready`. Messages of a leader can be sent right away, but
+    /// those of other roles must only be sent after the next `Ready` is persisted.
+    pub fn take_messages_for(&mut self, peer: u64) -> Vec<Message> {
+        let (msgs, rest) = mem::take(&mut self.raft.msgs)
+            .into_iter()
+            .partition(|m| m.to == peer);
+        self.raft.msgs = rest;
+        self.message_stats.record_sent(&msgs);
+        msgs
+    }
+
+    /// Returns the number of queued messages, which the next `Ready` or
+    /// [`Self::take_messages_for`] hands over. It can be used to size the transport buffers
+    /// before calling `ready`.
+    #[inline]
+    pub fn pending_messages_len(&self) -> usize {
+        self.raft.msgs.len()
+    }
+
+    /// Removes and returns the completed read states, which are then not delivered by the
+    /// next `Ready`. It lets event loops serve reads without building a full `Ready`.
+    ///
+    /// As with the read states in `Ready`, a read must only be served once the applied
+    /// index reaches the index of its read state.
+    pub fn take_read_states(&mut self) -> Vec<ReadState> {
+        mem::take(&mut self.raft.read_states)
+    }
+
+    /// Returns the tallies of the messages sent and received by this node since it's
+    /// created or [`Self::reset_message_stats`] is called.
+    pub fn message_stats(&self) -> MessageStats {
+        self.message_stats.clone()
+    }
+
+    /// Resets all the message tallies to zero.
+    pub fn reset_message_stats(&mut self) {
+        self.message_stats = MessageStats::default();
+    }
+
     /// Returns the store as an immutable reference.
     #[inline]
     pub fn store(&self) -> &T {
@@ -758,6 +1513,107 @@ impl<T: Storage> RawNode<T> {
     pub fn set_batch_append(&mut self, batch_append: bool) {
         self.raft.set_batch_append(batch_append)
     }
+
+    /// Stops the election timer from starting campaigns while heartbeats and replication
+    /// continue, which is gentler than isolating the node during maintenance.
+    #[inline]
+    pub fn pause_election(&mut self) {
+        self.raft.pause_election()
+    }
+
+    /// Resumes the election timer paused by [`Self::pause_election`].
+    #[inline]
+    pub fn resume_election(&mut self) {
+        self.raft.resume_election()
+    }
+
+    /// Whether this node is a leader that likely lost contact with a quorum, before check
+    /// quorum makes it step down. See [`Raft::is_likely_partitioned`].
+    #[inline]
+    pub fn is_likely_partitioned(&self) -> bool {
+        self.raft.is_likely_partitioned()
+    }
+
+    /// Overrides the max number of inflight append messages for the given peer. See
+    /// [`Raft::set_inflight_limit`].
+    #[inline]
+    pub fn set_inflight_limit(&mut self, id: u64, limit: usize) {
+        self.raft.set_inflight_limit(id, limit)
+    }
+
+    /// Recomputes the commit index from the matched indexes of the current progress and
+    /// returns it. This is mainly a hook for tests and recovery tools that change the
+    /// progress manually. See [`Raft::recompute_commit`].
+    #[inline]
+    pub fn recompute_commit(&mut self) -> u64 {
+        self.raft.recompute_commit()
+    }
+
+    /// Returns why the most recent failed campaign of this node failed, along with its vote
+    /// tally, if any campaign has failed.
+    #[inline]
+    pub fn last_election_failure(&self) -> Option<ElectionFailure> {
+        self.raft.last_election_failure()
+    }
+
+    /// Returns the number of consecutive ticks this node has gone without knowing the
+    /// leader, or 0 if the leader is known. Clients can use it to route requests away
+    /// from nodes that have been leaderless for too long.
+    #[inline]
+    pub fn leaderless_ticks(&self) -> usize {
+        self.raft.leaderless_ticks()
+    }
+
+    /// Sets the last known matched indexes of followers, which seed their progress the next
+    /// time this node becomes leader. See [`Raft::set_progress_hints`].
+    #[inline]
+    pub fn set_progress_hints<I>(&mut self, hints: I)
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+    {
+        self.raft.set_progress_hints(hints)
+    }
+
+    /// Sets the callback invoked whenever the progress state of a follower transitions.
+    #[inline]
+    pub fn set_progress_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&ProgressTransition) + Send + 'static,
+    {
+        self.raft.set_progress_observer(observer)
+    }
+
+    /// Sets the callback invoked when a learner first catches up with the commit index.
+    /// See [`Raft::set_learner_caught_up_observer`].
+    #[inline]
+    pub fn set_learner_caught_up_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        self.raft.set_learner_caught_up_observer(observer)
+    }
+}
+
+impl RawNode<MemStorage> {
+    /// Truncates the log entries that are made obsolete by a received snapshot from the
+    /// storage and returns the new first index.
+    ///
+    /// Entries are discarded up to the snapshot index, see
+    /// [`RawNode::post_snapshot_compact_index`] for the errors.
+    pub fn post_snapshot_truncate(&mut self) -> Result<u64> {
+        let compact_index = self.post_snapshot_compact_index()?;
+        self.compact(compact_index)?;
+        Ok(self.raft.raft_log.first_index())
+    }
+
+    /// Discards the log entries before `compact_index` from the storage, after checking it
+    /// with [`RawNode::check_compact`].
+    ///
+    /// Compacting through `store().wl().compact` bypasses the check.
+    pub fn compact(&mut self, compact_index: u64) -> Result<()> {
+        self.check_compact(compact_index)?;
+        self.raft.raft_log.store.wl().compact(compact_index)
+    }
 }
 
 #[cfg(test)]
@@ -788,6 +1644,8 @@ mod test {
             (MessageType::MsgReadIndexResp, false),
             (MessageType::MsgRequestPreVote, false),
             (MessageType::MsgRequestPreVoteResponse, false),
+            (MessageType::MsgReadLeaseRequest, false),
+            (MessageType::MsgReadLeaseResponse, false),
         ];
         for (msg_type, result) in tests {
             assert_eq!(is_local_msg(msg_type), result);
@@ -59,6 +59,25 @@ pub struct ReadIndexStatus {
     pub req: Message,
     pub index: u64,
     pub acks: HashSet<u64>,
+    /// The nodes that requested the read with the same context, in the order they did.
+    pub requesters: Vec<u64>,
+}
+
+impl ReadIndexStatus {
+    /// Returns the original request of every requester.
+    pub fn into_requests(self) -> Vec<Message> {
+        let ReadIndexStatus {
+            req, requesters, ..
+        } = self;
+        requesters
+            .into_iter()
+            .map(|from| {
+                let mut req = req.clone();
+                req.from = from;
+                req
+            })
+            .collect()
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -66,14 +85,25 @@ pub struct ReadOnly {
     pub option: ReadOnlyOption,
     pub pending_read_index: HashMap<Vec<u8>, ReadIndexStatus>,
     pub read_index_queue: VecDeque<Vec<u8>>,
+    pub max_pending_read_count: usize,
+    pub max_pending_read_count_per_peer: usize,
+    /// The number of pending requests of each requester.
+    pub pending_read_count_by_peer: HashMap<u64, usize>,
 }
 
 impl ReadOnly {
-    pub fn new(option: ReadOnlyOption) -> ReadOnly {
+    pub fn new(
+        option: ReadOnlyOption,
+        max_pending_read_count: usize,
+        max_pending_read_count_per_peer: usize,
+    ) -> ReadOnly {
         ReadOnly {
             option,
             pending_read_index: HashMap::default(),
             read_index_queue: VecDeque::new(),
+            max_pending_read_count,
+            max_pending_read_count_per_peer,
+            pending_read_count_by_peer: HashMap::default(),
         }
     }
 
@@ -83,19 +113,56 @@ impl ReadOnly {
     /// the read only request.
     ///
     /// `m` is the original read only request message from the local or remote node.
-    pub fn add_request(&mut self, index: u64, req: Message, self_id: u64) {
+    /// If a request with the same context is pending already, the node is recorded
+    /// so that it's also notified once the read index is ready.
+    ///
+    /// Returns false if the request is refused because there are already
+    /// `max_pending_read_count` pending requests, or `max_pending_read_count_per_peer`
+    /// pending requests of the same requester.
+    pub fn add_request(&mut self, index: u64, req: Message, self_id: u64) -> bool {
         let ctx = {
             let key: &[u8] = req.entries[0].data.as_ref();
-            if self.pending_read_index.contains_key(key) {
-                return;
+            if let Some(status) = self.pending_read_index.get(key) {
+                if status.requesters.contains(&req.from) {
+                    return true;
+                }
+                if !self.count_request_of(req.from) {
+                    return false;
+                }
+                let status = self.pending_read_index.get_mut(key).unwrap();
+                status.requesters.push(req.from);
+                return true;
+            }
+            if self.read_index_queue.len() >= self.max_pending_read_count {
+                return false;
+            }
+            if !self.count_request_of(req.from) {
+                return false;
             }
             key.to_vec()
         };
         let mut acks = HashSet::<u64>::default();
         acks.insert(self_id);
-        let status = ReadIndexStatus { req, index, acks };
+        let status = ReadIndexStatus {
+            requesters: vec![req.from],
+            req,
+            index,
+            acks,
+        };
         self.pending_read_index.insert(ctx.clone(), status);
         self.read_index_queue.push_back(ctx);
+        true
+    }
+
+    // count_request_of counts a new pending request of `from`, unless it already has
+    // `max_pending_read_count_per_peer` pending requests.
+    fn count_request_of(&mut self, from: u64) -> bool {
+        let count = self.pending_read_count_by_peer.entry(from).or_insert(0);
+        if *count >= self.max_pending_read_count_per_peer {
+            return false;
+        }
+        *count += 1;
+        true
     }
 
     /// Notifies the ReadOnly struct that the raft state machine received
@@ -122,6 +189,14 @@ impl ReadOnly {
             for _ in 0..=i {
                 let rs = self.read_index_queue.pop_front().unwrap();
                 let status = self.pending_read_index.remove(&rs).unwrap();
+                for from in &status.requesters {
+                    if let Some(count) = self.pending_read_count_by_peer.get_mut(from) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.pending_read_count_by_peer.remove(from);
+                        }
+                    }
+                }
                 rss.push(status);
             }
         }
@@ -137,4 +212,13 @@ impl ReadOnly {
     pub fn pending_read_count(&self) -> usize {
         self.read_index_queue.len()
     }
+
+    /// Returns the number of pending requests of the given requester.
+    #[inline]
+    pub fn pending_read_count_of(&self, from: u64) -> usize {
+        self.pending_read_count_by_peer
+            .get(&from)
+            .cloned()
+            .unwrap_or_default()
+    }
 }
@@ -31,8 +31,21 @@ pub struct Status<'a> {
     pub ss: SoftState,
     /// The index of the last entry to have been applied.
     pub applied: u64,
+    /// The term of the last entry to have been applied.
+    pub applied_term: u64,
     /// The progress towards catching up and applying logs.
     pub progress: Option<&'a ProgressTracker>,
+    /// The number of times the node stepped down from leader on an append of a higher term.
+    pub higher_term_append_step_downs: u64,
+    /// The number of received snapshots ignored because the log already contained them.
+    pub duplicate_snapshots_ignored: u64,
+    /// The number of appends received with a commit index lower than the local one.
+    pub stale_commit_appends: u64,
+    /// The randomized election timeout currently in use, in ticks.
+    pub randomized_election_timeout: usize,
+    /// Whether taking a snapshot is suggested, as the log holds more entries than
+    /// `Config::snapshot_entries_threshold` since the last snapshot.
+    pub should_snapshot: bool,
 }
 
 impl<'a> Status<'a> {
@@ -45,6 +58,12 @@ impl<'a> Status<'a> {
         s.hs = raft.hard_state();
         s.ss = raft.soft_state();
         s.applied = raft.raft_log.applied;
+        s.applied_term = raft.raft_log.applied_term;
+        s.higher_term_append_step_downs = raft.higher_term_append_step_downs();
+        s.duplicate_snapshots_ignored = raft.duplicate_snapshots_ignored();
+        s.stale_commit_appends = raft.stale_commit_appends();
+        s.randomized_election_timeout = raft.randomized_election_timeout();
+        s.should_snapshot = raft.should_snapshot();
         if s.ss.raft_state == StateRole::Leader {
             s.progress = Some(raft.prs());
         }
@@ -19,7 +19,7 @@ mod progress;
 mod state;
 
 pub use self::inflights::Inflights;
-pub use self::progress::Progress;
+pub use self::progress::{Progress, ProgressTransition};
 pub use self::state::ProgressState;
 
 use slog::Logger;
@@ -371,6 +371,20 @@ impl ProgressTracker {
             == VoteResult::Won
     }
 
+    /// Determines whether the given set of nodes forms a quorum in the incoming and the
+    /// outgoing configuration respectively.
+    ///
+    /// The outgoing configuration is empty when the configuration is not joint, in which
+    /// case it is always considered to have a quorum.
+    pub fn has_joint_quorum(&self, potential_quorum: &HashSet<u64>) -> (bool, bool) {
+        let check = |id| potential_quorum.get(&id).map(|_| true);
+        let voters = &self.conf.voters;
+        (
+            voters.incoming.vote_result(check) == VoteResult::Won,
+            voters.outgoing.vote_result(check) == VoteResult::Won,
+        )
+    }
+
     #[inline]
     pub(crate) fn progress(&self) -> &ProgressMap {
         &self.progress
@@ -24,6 +24,9 @@ pub struct Inflights {
 
     // ring buffer
     buffer: Vec<u64>,
+
+    // the capacity to shrink to once enough inflights are freed
+    incoming_cap: Option<usize>,
 }
 
 // The `buffer` must have it's capacity set correctly on clone, normally it does not.
@@ -35,6 +38,7 @@ impl Clone for Inflights {
             start: self.start,
             count: self.count,
             buffer,
+            incoming_cap: self.incoming_cap,
         }
     }
 }
@@ -46,13 +50,20 @@ impl Inflights {
             buffer: Vec::with_capacity(cap),
             start: 0,
             count: 0,
+            incoming_cap: None,
         }
     }
 
     /// Returns true if the inflights is full.
     #[inline]
     pub fn full(&self) -> bool {
-        self.count == self.cap()
+        self.count == self.cap() || self.incoming_cap.is_some()
+    }
+
+    /// The number of inflights in the buffer.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
     }
 
     /// The buffer capacity.
@@ -61,6 +72,27 @@ impl Inflights {
         self.buffer.capacity()
     }
 
+    /// Adjusts the buffer capacity, keeping the inflights.
+    ///
+    /// If there are more inflights than `cap`, the buffer stays full until enough of them
+    /// are freed to shrink it.
+    pub fn set_cap(&mut self, cap: usize) {
+        if self.count > cap {
+            self.incoming_cap = Some(cap);
+            return;
+        }
+        self.incoming_cap = None;
+        if cap == self.cap() {
+            return;
+        }
+        let mut buffer = Vec::with_capacity(cap);
+        for i in 0..self.count {
+            buffer.push(self.buffer[(self.start + i) % self.cap()]);
+        }
+        self.buffer = buffer;
+        self.start = 0;
+    }
+
     /// Adds an inflight into inflights
     pub fn add(&mut self, inflight: u64) {
         if self.full() {
@@ -107,6 +139,10 @@ impl Inflights {
         // free i inflights and set new start index
         self.count -= i;
         self.start = idx;
+
+        if let Some(cap) = self.incoming_cap {
+            self.set_cap(cap);
+        }
     }
 
     /// Frees the first buffer entry.
@@ -121,6 +157,9 @@ impl Inflights {
     pub fn reset(&mut self) {
         self.count = 0;
         self.start = 0;
+        if let Some(cap) = self.incoming_cap {
+            self.set_cap(cap);
+        }
     }
 }
 
@@ -139,6 +178,7 @@ mod tests {
             start: 0,
             count: 5,
             buffer: vec![0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
@@ -151,6 +191,7 @@ mod tests {
             start: 0,
             count: 10,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin2);
@@ -167,6 +208,7 @@ mod tests {
             start: 5,
             count: 5,
             buffer: vec![0, 0, 0, 0, 0, 0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight2, wantin21);
@@ -179,6 +221,7 @@ mod tests {
             start: 5,
             count: 10,
             buffer: vec![5, 6, 7, 8, 9, 0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight2, wantin22);
@@ -197,6 +240,7 @@ mod tests {
             start: 5,
             count: 5,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
@@ -207,6 +251,7 @@ mod tests {
             start: 9,
             count: 1,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin2);
@@ -221,6 +266,7 @@ mod tests {
             start: 3,
             count: 2,
             buffer: vec![10, 11, 12, 13, 14, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin3);
@@ -231,6 +277,7 @@ mod tests {
             start: 5,
             count: 0,
             buffer: vec![10, 11, 12, 13, 14, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin4);
@@ -249,8 +296,47 @@ mod tests {
             start: 1,
             count: 9,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
     }
+
+    #[test]
+    fn test_inflight_set_cap() {
+        let mut inflight = Inflights::new(5);
+        inflight.start = 3;
+        inflight.buffer.extend_from_slice(&[0, 0, 0]);
+        for i in 0..4 {
+            inflight.add(i);
+        }
+
+        // Growing keeps the inflights in order.
+        inflight.set_cap(8);
+        let wantin = Inflights {
+            start: 0,
+            count: 4,
+            buffer: vec![0, 1, 2, 3],
+            incoming_cap: None,
+        };
+        assert_eq!(inflight, wantin);
+        assert_eq!(inflight.cap(), 8);
+
+        // Shrinking below the inflights waits until enough of them are freed.
+        inflight.set_cap(2);
+        assert!(inflight.full());
+        assert_eq!(inflight.cap(), 8);
+        inflight.free_to(0);
+        assert!(inflight.full());
+        inflight.free_to(1);
+        let wantin2 = Inflights {
+            start: 0,
+            count: 2,
+            buffer: vec![2, 3],
+            incoming_cap: None,
+        };
+        assert_eq!(inflight, wantin2);
+        assert_eq!(inflight.cap(), 2);
+        assert!(inflight.full());
+    }
 }
@@ -3,6 +3,21 @@
 use crate::{Inflights, ProgressState, INVALID_INDEX};
 use std::cmp;
 
+/// A transition of the progress state of a follower, observed by the leader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressTransition {
+    /// The ID of the follower.
+    pub id: u64,
+    /// The state before the transition.
+    pub from: ProgressState,
+    /// The state after the transition.
+    pub to: ProgressState,
+    /// The index that triggered the transition: the index of the snapshot when entering
+    /// or leaving snapshot state, the index of the append response when probing or
+    /// replicating, or the matched index if the follower is unreachable.
+    pub index: u64,
+}
+
 /// The progress of catching up from a restart.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Progress {
@@ -53,6 +68,28 @@ pub struct Progress {
 
     /// Committed index in raft_log
     pub committed_index: u64,
+
+    /// The leader's tick at which the oldest unanswered heartbeat was sent to this peer.
+    pub heartbeat_sent_tick: Option<u64>,
+
+    /// The leader's tick at which the last answered heartbeat was sent to this peer.
+    pub heartbeat_acked_tick: Option<u64>,
+
+    /// The round trip time of the last answered heartbeat, in ticks.
+    pub heartbeat_rtt: u64,
+
+    /// The leader's tick at which a message was last received from this peer.
+    pub last_active_tick: Option<u64>,
+
+    /// The number of rejected appends since the progress became a probe.
+    pub probe_rounds: u64,
+
+    /// Whether the peer, as a learner, has matched the commit index of the leader since the
+    /// leader took over. See `Raft::set_learner_caught_up_observer`.
+    pub caught_up: bool,
+
+    /// The leader's tick at which the peer entered snapshot state, if it's in snapshot state.
+    pub snapshot_sent_tick: Option<u64>,
 }
 
 impl Progress {
@@ -69,6 +106,13 @@ impl Progress {
             ins: Inflights::new(ins_size),
             commit_group_id: 0,
             committed_index: 0,
+            heartbeat_sent_tick: None,
+            heartbeat_acked_tick: None,
+            heartbeat_rtt: 0,
+            last_active_tick: None,
+            probe_rounds: 0,
+            caught_up: false,
+            snapshot_sent_tick: None,
         }
     }
 
@@ -77,6 +121,8 @@ impl Progress {
         self.pending_snapshot = 0;
         self.state = state;
         self.ins.reset();
+        self.probe_rounds = 0;
+        self.snapshot_sent_tick = None;
     }
 
     pub(crate) fn reset(&mut self, next_idx: u64) {
@@ -89,6 +135,34 @@ impl Progress {
         self.recent_active = false;
         debug_assert!(self.ins.cap() != 0);
         self.ins.reset();
+        self.heartbeat_sent_tick = None;
+        self.heartbeat_acked_tick = None;
+        self.heartbeat_rtt = 0;
+        self.last_active_tick = None;
+        self.probe_rounds = 0;
+        self.caught_up = false;
+        self.snapshot_sent_tick = None;
+    }
+
+    /// Records that a heartbeat was sent at `tick`.
+    ///
+    /// Heartbeat responses can't be matched with their requests, so only the oldest
+    /// unanswered heartbeat is timed.
+    #[inline]
+    pub fn heartbeat_sent(&mut self, tick: u64) {
+        if self.heartbeat_sent_tick.is_none() {
+            self.heartbeat_sent_tick = Some(tick);
+        }
+    }
+
+    /// Records that a heartbeat response was received at `tick` and updates the round
+    /// trip time if a heartbeat is being timed.
+    #[inline]
+    pub fn heartbeat_responded(&mut self, tick: u64) {
+        if let Some(sent) = self.heartbeat_sent_tick.take() {
+            self.heartbeat_rtt = tick.saturating_sub(sent);
+            self.heartbeat_acked_tick = Some(sent);
+        }
     }
 
     /// Changes the progress to a probe.
@@ -197,6 +271,7 @@ impl Progress {
             if self.next_idx < 1 {
                 self.next_idx = 1;
             }
+            self.probe_rounds += 1;
         } else if self.pending_request_snapshot == INVALID_INDEX {
             // Allow requesting snapshot even if it's not Replicate.
             self.pending_request_snapshot = request_snapshot;
@@ -215,6 +290,16 @@ impl Progress {
         }
     }
 
+    /// The number of appends sent to the peer in replicate state that are not acknowledged
+    /// yet. It's 0 in other states, as at most one append is sent at a time.
+    #[inline]
+    pub fn pipeline_depth(&self) -> usize {
+        match self.state {
+            ProgressState::Replicate => self.ins.count(),
+            _ => 0,
+        }
+    }
+
     /// Resume progress
     #[inline]
     pub fn resume(&mut self) {
@@ -74,6 +74,24 @@ pub fn limit_size<T: PbMessage + Clone>(entries: &mut Vec<T>, max: Option<u64>)
     entries.truncate(limit);
 }
 
+/// Returns the serialized size of a message in bytes, as computed by protobuf.
+///
+/// # Examples
+///
+/// ```
+/// use protobuf::Message as PbMessage;
+/// use raft::{util::message_size, prelude::*};
+///
+/// let mut msg = Message::default();
+/// msg.set_msg_type(MessageType::MsgAppend);
+/// msg.set_entries(vec![Entry::default()].into());
+/// assert_eq!(message_size(&msg), msg.write_to_bytes().unwrap().len());
+/// ```
+#[inline]
+pub fn message_size(m: &Message) -> usize {
+    m.compute_size() as usize
+}
+
 /// Check whether the entry is continuous to the message.
 /// i.e msg's next entry index should be equal to the index of the first entry in `ents`
 pub fn is_continuous_ents(msg: &Message, ents: &[Entry]) -> bool {